@@ -1,11 +1,35 @@
 package com.rin
 
+/**
+ * Status codes from the Int-returning RinLib calls, and the negative handles
+ * from createEngine*; see `JniStatus` in android.rs
+ */
+object RinStatus {
+    const val OK = 0
+    const val FAILED = -1
+    const val UNKNOWN_HANDLE = -2
+    const val INVALID_ARGUMENT = -3
+    const val PTY_FAILED = -4
+}
+
 object RinLib {
     init {
         System.loadLibrary("rin")
     }
 
+    // A negative handle is a RinStatus code: PTY_FAILED if the shell couldn't start
     external fun createEngine(width: Int, height: Int, fontSize: Float, homeDir: String, username: String): Long
+    // A negative handle is a RinStatus code: PTY_FAILED if the shell couldn't start,
+    // INVALID_ARGUMENT if envKeys and envValues differ in length or hold a null
+    external fun createEngineWithEnv(
+        width: Int, height: Int, fontSize: Float, shell: String, homeDir: String, username: String,
+        envKeys: Array<String>, envValues: Array<String>
+    ): Long
+    // null or "" for no banner. A negative handle is a RinStatus code: PTY_FAILED if
+    // the shell couldn't start
+    external fun createEngineWithBanner(
+        width: Int, height: Int, fontSize: Float, homeDir: String, username: String, banner: String?
+    ): Long
     external fun destroyEngine(handle: Long)
     external fun write(handle: Long, data: ByteArray): Int
     external fun render(handle: Long): Int
@@ -14,6 +38,16 @@ object RinLib {
     external fun getCursorX(handle: Long): Int
     external fun getCursorY(handle: Long): Int
     external fun getCellData(handle: Long, y: Int): String
+    // The measured charWidth / lineHeight, so getCellAt matches the drawn text
+    external fun setCellSize(handle: Long, width: Float, height: Float): Int
+    // Packed as (row shl 16) or col, the row counted from the oldest scrollback line;
+    // RinStatus.UNKNOWN_HANDLE for an unknown handle
+    external fun getCellAt(handle: Long, px: Float, py: Float): Int
     external fun hasDirtyRows(handle: Long): Boolean
+    external fun needsRender(handle: Long): Boolean
+    external fun setFocused(handle: Long, focused: Boolean)
+    external fun flushResponses(handle: Long): Int
     external fun clearDirty(handle: Long)
+    // null only if the JVM couldn't allocate the message
+    external fun getLastError(handle: Long): String?
 }
@@ -162,6 +162,7 @@ private class TerminalCanvasView(context: Context) : View(context) {
             cols = (width / charWidth).toInt().coerceAtLeast(1)
             rows = (height / lineHeight).toInt().coerceAtLeast(1)
             if (engineHandle != 0L) {
+                RinLib.setCellSize(engineHandle, charWidth, lineHeight)
                 RinLib.resize(engineHandle, cols, rows)
             }
         }
@@ -402,9 +403,10 @@ private class TerminalCanvasView(context: Context) : View(context) {
             cursorPaint.color = scheme?.cursor ?: Color.WHITE
             cursorPaint.alpha = 255 // Solid opacity for thin bar
             
+            // Negative values are RinStatus codes, not positions
             val cx = RinLib.getCursorX(engineHandle)
             val cy = RinLib.getCursorY(engineHandle)
-            if (cx < cols && cy < rows) {
+            if (cx in 0 until cols && cy in 0 until rows) {
                 // Draw blinking bar cursor (thin vertical line)
                 val cursorWidth = charWidth / 5 // Make it thin relative to char width
                 canvas.drawRect(
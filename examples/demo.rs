@@ -7,14 +7,14 @@ fn main() {
     let mut parser = AnsiParser::new();
 
     println!("Demo 1: Basic Text");
-    write_and_execute(&mut buffer, &mut parser, b"Hello, Rin Terminal!\n");
+    write_and_execute(&mut buffer, &mut parser, b"Hello, Rin Terminal!\r\n");
     print_buffer_state(&buffer);
 
     println!("\nDemo 2: Basic ANSI Colors");
     write_and_execute(
         &mut buffer,
         &mut parser,
-        b"\x1b[31mRed\x1b[0m \x1b[32mGreen\x1b[0m \x1b[34mBlue\x1b[0m\n",
+        b"\x1b[31mRed\x1b[0m \x1b[32mGreen\x1b[0m \x1b[34mBlue\x1b[0m\r\n",
     );
     print_buffer_state(&buffer);
 
@@ -22,7 +22,7 @@ fn main() {
     write_and_execute(
         &mut buffer,
         &mut parser,
-        b"\x1b[38;5;196mRed256\x1b[0m \x1b[38;5;46mGreen256\x1b[0m\n",
+        b"\x1b[38;5;196mRed256\x1b[0m \x1b[38;5;46mGreen256\x1b[0m\r\n",
     );
     print_buffer_state(&buffer);
 
@@ -30,14 +30,14 @@ fn main() {
     write_and_execute(
         &mut buffer,
         &mut parser,
-        b"\x1b[38;2;255;128;0mOrange RGB\x1b[0m \x1b[38;2;128;0;255mPurple RGB\x1b[0m\n",
+        b"\x1b[38;2;255;128;0mOrange RGB\x1b[0m \x1b[38;2;128;0;255mPurple RGB\x1b[0m\r\n",
     );
     print_buffer_state(&buffer);
 
     println!("\nDemo 5: Scrollback Buffer");
     buffer.clear();
     for i in 0..15 {
-        let line = format!("Line {} of 15\n", i + 1);
+        let line = format!("Line {} of 15\r\n", i + 1);
         write_and_execute(&mut buffer, &mut parser, line.as_bytes());
     }
     println!("  Buffer height: 10 rows");
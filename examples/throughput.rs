@@ -0,0 +1,36 @@
+//! Rough parse + apply throughput for a plain-text log:
+//! `cargo run --release --example throughput`
+
+use rin::{AnsiParser, TerminalBuffer};
+use std::time::Instant;
+
+fn main() -> anyhow::Result<()> {
+    let mut log = Vec::new();
+    for i in 0..200_000 {
+        log.extend_from_slice(
+            format!(
+                "[{:>6}] INFO compiling crate number {} with some output\r\n",
+                i, i
+            )
+            .as_bytes(),
+        );
+    }
+
+    let mut parser = AnsiParser::new();
+    let mut buffer = TerminalBuffer::new(120, 40);
+
+    let start = Instant::now();
+    for chunk in log.chunks(4096) {
+        buffer.execute_commands(parser.parse(chunk)?)?;
+    }
+    let elapsed = start.elapsed();
+
+    let mib = log.len() as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:.1} MiB in {:.2?} ({:.1} MiB/s)",
+        mib,
+        elapsed,
+        mib / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
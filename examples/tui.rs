@@ -8,10 +8,8 @@ use crossterm::{
     style::{Color as CtColor, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
     terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use rin::{AnsiParser, Color, Pty, TerminalBuffer};
+use rin::{AnsiParser, Color, KeyEvent, Pty, PtyCoalescer, TerminalBuffer};
 use std::io::{self, Write};
-use std::sync::mpsc;
-use std::thread;
 use std::time::Duration;
 
 fn main() -> anyhow::Result<()> {
@@ -37,27 +35,13 @@ fn run_terminal(stdout: &mut io::Stdout) -> anyhow::Result<()> {
     let (mut term_width, mut term_height) = terminal::size()?;
     let (mut width, mut height) = (term_width as usize, term_height.saturating_sub(1) as usize);
 
-    let mut pty = Pty::spawn(&shell, width as u16, height as u16)?;
+    let mut pty = Pty::spawn(&shell, width as u16, height as u16, None, None)?;
     let mut buffer = TerminalBuffer::new(width, height);
     let mut parser = AnsiParser::new();
 
-    let (tx, rx) = mpsc::channel::<Vec<u8>>();
-
-    let mut pty_reader = pty.take_reader()?;
-    thread::spawn(move || {
-        let mut read_buf = [0u8; 4096];
-        loop {
-            match pty_reader.read(&mut read_buf) {
-                Ok(0) => break,
-                Ok(n) => {
-                    if tx.send(read_buf[..n].to_vec()).is_err() {
-                        break;
-                    }
-                }
-                Err(_) => break,
-            }
-        }
-    });
+    // Collect bursts of output for a frame's worth of time so chatty
+    // programs don't trigger a render per read
+    let output = PtyCoalescer::spawn(pty.take_reader()?, Duration::from_millis(8), 1 << 16);
 
     render_buffer(stdout, &buffer, width, height)?;
 
@@ -71,30 +55,33 @@ fn run_terminal(stdout: &mut io::Stdout) -> anyhow::Result<()> {
                         break;
                     }
 
-                    let bytes = key_to_bytes(key.code, key.modifiers);
-                    if !bytes.is_empty() {
-                        pty.write(&bytes)?;
+                    if let Ok(key) = KeyEvent::try_from(key) {
+                        let bytes = key.to_ansi();
+                        if !bytes.is_empty() {
+                            pty.write(&bytes)?;
+                        }
                     }
                 }
-                Event::Resize(new_width, new_height) => {
-                    if new_width != term_width || new_height != term_height {
-                        term_width = new_width;
-                        term_height = new_height;
-                        width = new_width as usize;
-                        height = new_height.saturating_sub(1) as usize;
-
-                        pty.resize(width as u16, height as u16)?;
-                        buffer.resize(width, height)?;
-
-                        render_buffer(stdout, &buffer, width, height)?;
-                    }
+                Event::Resize(new_width, new_height)
+                    if new_width != term_width || new_height != term_height =>
+                {
+                    term_width = new_width;
+                    term_height = new_height;
+                    width = new_width as usize;
+                    height = new_height.saturating_sub(1) as usize;
+
+                    pty.resize(width as u16, height as u16)?;
+                    buffer.resize(width, height)?;
+
+                    render_buffer(stdout, &buffer, width, height)?;
                 }
                 _ => {}
             }
         }
 
-        let mut needs_render = false;
-        while let Ok(data) = rx.try_recv() {
+        // One coalesced burst per frame, so a flood of output still leaves
+        // time to poll for keys
+        if let Some(data) = output.try_recv() {
             if let Ok(cmds) = parser.parse(&data) {
                 for cmd in cmds {
                     let _ = buffer.execute_command(cmd);
@@ -105,10 +92,6 @@ fn run_terminal(stdout: &mut io::Stdout) -> anyhow::Result<()> {
                 let _ = pty.write(&response);
             }
 
-            needs_render = true;
-        }
-
-        if needs_render {
             render_buffer(stdout, &buffer, width, height)?;
         }
     }
@@ -186,33 +169,3 @@ fn to_crossterm_color(color: Color) -> CtColor {
         b: color.b,
     }
 }
-
-fn key_to_bytes(code: KeyCode, modifiers: KeyModifiers) -> Vec<u8> {
-    let ctrl = modifiers.contains(KeyModifiers::CONTROL);
-
-    match code {
-        KeyCode::Char(c) => {
-            if ctrl && c >= 'a' && c <= 'z' {
-                vec![(c as u8) - b'a' + 1]
-            } else {
-                c.to_string().into_bytes()
-            }
-        }
-        KeyCode::Enter => vec![b'\r'],
-        KeyCode::Backspace => vec![0x7f],
-        KeyCode::Tab => vec![b'\t'],
-        KeyCode::Esc => vec![0x1b],
-        KeyCode::Up => vec![0x1b, b'[', b'A'],
-        KeyCode::Down => vec![0x1b, b'[', b'B'],
-        KeyCode::Right => vec![0x1b, b'[', b'C'],
-        KeyCode::Left => vec![0x1b, b'[', b'D'],
-        KeyCode::Home => vec![0x1b, b'[', b'H'],
-        KeyCode::End => vec![0x1b, b'[', b'F'],
-        KeyCode::PageUp => vec![0x1b, b'[', b'5', b'~'],
-        KeyCode::PageDown => vec![0x1b, b'[', b'6', b'~'],
-        KeyCode::Delete => vec![0x1b, b'[', b'3', b'~'],
-        KeyCode::Insert => vec![0x1b, b'[', b'2', b'~'],
-        KeyCode::F(n) if n >= 1 && n <= 4 => vec![0x1b, b'O', b'P' + (n - 1)],
-        _ => vec![],
-    }
-}
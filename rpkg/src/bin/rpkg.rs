@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::Parser;
 use rpkg::manager::PackageManager;
 use rpkg::DEFAULT_PREFIX;
 use std::path::PathBuf;
@@ -33,6 +33,9 @@ struct Cli {
     #[arg(short = 'f', long)]
     force: bool,
 
+    #[arg(short = 'i', long)]
+    info: bool,
+
     targets: Vec<String>,
 }
 
@@ -46,103 +49,103 @@ fn main() -> anyhow::Result<()> {
     let mut args = std::env::args();
     if let Some(arg0) = args.next() {
         let exe_path = PathBuf::from(&arg0);
-        if let Some(exe_name) = exe_path.file_name().and_then(|s| s.to_str()) {
-            if exe_name != "rpkg" && exe_name != "rpkg_cli" && exe_name != "librpkg_cli.so" {
-                let original_path = if exe_path.parent().map_or(true, |p| p.as_os_str().is_empty()) || exe_path.parent().unwrap().as_os_str() == "." {
-                    PathBuf::from(DEFAULT_PREFIX).join("usr").join("bin").join(exe_name)
-                } else {
-                    exe_path.clone()
-                };
+        if let Some(exe_name) = exe_path.file_name().and_then(|s| s.to_str())
+            && exe_name != "rpkg"
+            && exe_name != "rpkg_cli"
+            && exe_name != "librpkg_cli.so"
+        {
+            let original_path = if exe_path.parent().is_none_or(|p| p.as_os_str().is_empty()) || exe_path.parent().unwrap().as_os_str() == "." {
+                PathBuf::from(DEFAULT_PREFIX).join("usr").join("bin").join(exe_name)
+            } else {
+                exe_path.clone()
+            };
                 
-                let mut current = original_path.clone();
-                while let Ok(target) = std::fs::read_link(&current) {
-                    let next = if target.is_absolute() { target } else { current.parent().unwrap().join(target) };
-                    if next.file_name().and_then(|n| n.to_str()) == Some("rpkg") {
-                        break;
-                    }
-                    current = next;
+            let mut current = original_path.clone();
+            while let Ok(target) = std::fs::read_link(&current) {
+                let next = if target.is_absolute() { target } else { current.parent().unwrap().join(target) };
+                if next.file_name().and_then(|n| n.to_str()) == Some("rpkg") {
+                    break;
                 }
+                current = next;
+            }
                 
-                let target_elf = PathBuf::from(format!("{}.elf", current.display()));
-                let resolved_name = current.file_name().and_then(|n| n.to_str()).unwrap_or("");
-                let mut multicall_args = Vec::new();
-                if resolved_name != exe_name {
-                    if resolved_name == "coreutils" {
-                        multicall_args.push(format!("--coreutils-prog={}", exe_name));
-                    } else if resolved_name == "busybox" || resolved_name == "toybox" {
-                        multicall_args.push(exe_name.to_string());
-                    }
+            let target_elf = PathBuf::from(format!("{}.elf", current.display()));
+            let resolved_name = current.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            let mut multicall_args = Vec::new();
+            if resolved_name != exe_name {
+                if resolved_name == "coreutils" {
+                    multicall_args.push(format!("--coreutils-prog={}", exe_name));
+                } else if resolved_name == "busybox" || resolved_name == "toybox" {
+                    multicall_args.push(exe_name.to_string());
                 }
+            }
 
-                let mut is_elf = false;
-                if let Ok(mut f) = std::fs::File::open(&target_elf) {
-                    let mut magic = [0u8; 4];
-                    if f.read_exact(&mut magic).is_ok() && &magic == b"\x7FELF" {
-                        is_elf = true;
-                    }
+            let mut is_elf = false;
+            if let Ok(mut f) = std::fs::File::open(&target_elf) {
+                let mut magic = [0u8; 4];
+                if f.read_exact(&mut magic).is_ok() && &magic == b"\x7FELF" {
+                    is_elf = true;
                 }
+            }
 
-                let mut interpreter = String::from("/system/bin/sh");
-                let mut interpreter_args: Vec<String> = Vec::new();
-
-                if !is_elf {
-                    if let Ok(f) = std::fs::File::open(&target_elf) {
-                        use std::io::{BufRead, BufReader};
-                        let mut reader = BufReader::new(f);
-                        let mut first_line = String::new();
-                        if reader.read_line(&mut first_line).is_ok() {
-                            let first_line = first_line.trim();
-                            if first_line.starts_with("#!") {
-                                let shebang = first_line[2..].trim();
-                                let mut parts = shebang.split_whitespace();
-                                if let Some(cmd) = parts.next() {
-                                    if cmd.ends_with("/env") {
-                                        if let Some(env_cmd) = parts.next() {
-                                            interpreter = PathBuf::from(DEFAULT_PREFIX).join("usr/bin").join(env_cmd).to_string_lossy().into_owned();
-                                            for p in parts {
-                                                interpreter_args.push(p.to_string());
-                                            }
-                                        }
-                                    } else if cmd == "/bin/sh" || cmd == "/system/bin/sh" {
-                                        interpreter = String::from("/system/bin/sh");
-                                        for p in parts {
-                                            interpreter_args.push(p.to_string());
-                                        }
-                                    } else {
-                                        let cmd_path = std::path::Path::new(cmd);
-                                        if let Some(name) = cmd_path.file_name() {
-                                            interpreter = PathBuf::from(DEFAULT_PREFIX).join("usr/bin").join(name).to_string_lossy().into_owned();
-                                        }
-                                        for p in parts {
-                                            interpreter_args.push(p.to_string());
-                                        }
+            let mut interpreter = String::from("/system/bin/sh");
+            let mut interpreter_args: Vec<String> = Vec::new();
+
+            if !is_elf && let Ok(f) = std::fs::File::open(&target_elf) {
+                use std::io::{BufRead, BufReader};
+                let mut reader = BufReader::new(f);
+                let mut first_line = String::new();
+                if reader.read_line(&mut first_line).is_ok() {
+                    let first_line = first_line.trim();
+                    if let Some(shebang) = first_line.strip_prefix("#!") {
+                        let shebang = shebang.trim();
+                        let mut parts = shebang.split_whitespace();
+                        if let Some(cmd) = parts.next() {
+                            if cmd.ends_with("/env") {
+                                if let Some(env_cmd) = parts.next() {
+                                    interpreter = PathBuf::from(DEFAULT_PREFIX).join("usr/bin").join(env_cmd).to_string_lossy().into_owned();
+                                    for p in parts {
+                                        interpreter_args.push(p.to_string());
                                     }
                                 }
+                            } else if cmd == "/bin/sh" || cmd == "/system/bin/sh" {
+                                interpreter = String::from("/system/bin/sh");
+                                for p in parts {
+                                    interpreter_args.push(p.to_string());
+                                }
+                            } else {
+                                let cmd_path = std::path::Path::new(cmd);
+                                if let Some(name) = cmd_path.file_name() {
+                                    interpreter = PathBuf::from(DEFAULT_PREFIX).join("usr/bin").join(name).to_string_lossy().into_owned();
+                                }
+                                for p in parts {
+                                    interpreter_args.push(p.to_string());
+                                }
                             }
                         }
                     }
                 }
+            }
 
-                let lib_path = PathBuf::from(DEFAULT_PREFIX).join("usr").join("lib");
-                let err = if is_elf {
-                    Command::new("/system/bin/linker64")
-                        .arg(&target_elf)
-                        .args(multicall_args)
-                        .args(args)
-                        .env("LD_LIBRARY_PATH", &lib_path)
-                        .exec()
-                } else {
-                    let mut cmd = Command::new(&interpreter);
-                    cmd.args(interpreter_args);
-                    cmd.arg(&target_elf);
-                    cmd.args(args);
-                    cmd.env("LD_LIBRARY_PATH", &lib_path);
-                    cmd.exec()
-                };
+            let lib_path = PathBuf::from(DEFAULT_PREFIX).join("usr").join("lib");
+            let err = if is_elf {
+                Command::new("/system/bin/linker64")
+                    .arg(&target_elf)
+                    .args(multicall_args)
+                    .args(args)
+                    .env("LD_LIBRARY_PATH", &lib_path)
+                    .exec()
+            } else {
+                let mut cmd = Command::new(&interpreter);
+                cmd.args(interpreter_args);
+                cmd.arg(&target_elf);
+                cmd.args(args);
+                cmd.env("LD_LIBRARY_PATH", &lib_path);
+                cmd.exec()
+            };
                     
-                eprintln!("rpkg proxy: failed to exec {}: {}", target_elf.display(), err);
-                std::process::exit(1);
-            }
+            eprintln!("rpkg proxy: failed to exec {}: {}", target_elf.display(), err);
+            std::process::exit(1);
         }
     }
 
@@ -155,16 +158,18 @@ fn main() -> anyhow::Result<()> {
         }
 
         if cli.search {
-            for query in &cli.targets {
-                let results = pm.search(query)?;
-                for pkg in results {
-                    println!("{} {} ({})", pkg.name, pkg.version, pkg.size);
-                    println!("    {}", pkg.description);
-                }
+            // All targets form one query, so every term has to match
+            for pkg in pm.search(&cli.targets.join(" "))? {
+                println!("{} {} ({})", pkg.name, pkg.version, pkg.size);
+                println!("    {}", pkg.description);
             }
             return Ok(());
         }
 
+        if cli.info {
+            return print_info(&cli.targets, |name| pm.info(name));
+        }
+
         if cli.sysupgrade {
             pm.upgrade()?;
         }
@@ -177,6 +182,18 @@ fn main() -> anyhow::Result<()> {
             pm.remove(pkg)?;
         }
     } else if cli.query {
+        if cli.search {
+            for pkg in pm.search_installed(&cli.targets.join(" ")) {
+                println!("{} {}", pkg.info.name, pkg.info.version);
+                println!("    {}", pkg.info.description);
+            }
+            return Ok(());
+        }
+
+        if cli.info {
+            return print_info(&cli.targets, |name| pm.info_installed(name));
+        }
+
         let installed = pm.list_installed();
         for pkg in installed {
             println!("{} {}", pkg.info.name, pkg.info.version);
@@ -187,3 +204,25 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Prints what `lookup` finds for each target. Like pacman, every target is
+/// reported before failing if any was missing.
+fn print_info<T: std::fmt::Display>(
+    targets: &[String],
+    lookup: impl Fn(&str) -> anyhow::Result<T>,
+) -> anyhow::Result<()> {
+    let mut missing = 0;
+    for name in targets {
+        match lookup(name) {
+            Ok(pkg) => println!("{}\n", pkg),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                missing += 1;
+            }
+        }
+    }
+    if missing > 0 {
+        anyhow::bail!("{} of {} packages not found", missing, targets.len());
+    }
+    Ok(())
+}
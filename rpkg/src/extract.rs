@@ -46,36 +46,34 @@ fn patch_content(content: &[u8]) -> Vec<u8> {
         out = temp;
     }
 
-    if out.starts_with(b"\x7FELF") {
-        if let Ok(elf) = goblin::elf::Elf::parse(&out) {
-            if let Some(interp) = elf.interpreter {
-                if interp.contains("com.termux") || interp.contains("com.rin") {
-                    let is_64bit = elf.is_64;
-                    let interp_owned = interp.as_bytes().to_vec();
-                    let interp_len = interp_owned.len();
-                    drop(elf);
-
-                    let system_linker: &[u8] = if is_64bit {
-                        b"/system/bin/linker64\0"
-                    } else {
-                        b"/system/bin/linker\0"
-                    };
-
-                    if let Some(pos) = out.windows(interp_len).position(|w| w == interp_owned) {
-                        if system_linker.len() <= interp_len + 1 {
-                            for (i, &b) in system_linker.iter().enumerate() {
-                                out[pos + i] = b;
-                            }
-                            for i in system_linker.len()..interp_len + 1 {
-                                if pos + i < out.len() {
-                                    out[pos + i] = 0;
-                                }
-                            }
-                            log::debug!("Patched ELF interpreter to {:?}", std::str::from_utf8(system_linker).unwrap_or(""));
-                        }
-                    }
+    if out.starts_with(b"\x7FELF")
+        && let Ok(elf) = goblin::elf::Elf::parse(&out)
+        && let Some(interp) = elf.interpreter
+        && (interp.contains("com.termux") || interp.contains("com.rin"))
+    {
+        let is_64bit = elf.is_64;
+        let interp_owned = interp.as_bytes().to_vec();
+        let interp_len = interp_owned.len();
+        drop(elf);
+
+        let system_linker: &[u8] = if is_64bit {
+            b"/system/bin/linker64\0"
+        } else {
+            b"/system/bin/linker\0"
+        };
+
+        if let Some(pos) = out.windows(interp_len).position(|w| w == interp_owned)
+            && system_linker.len() <= interp_len + 1
+        {
+            for (i, &b) in system_linker.iter().enumerate() {
+                out[pos + i] = b;
+            }
+            for i in system_linker.len()..interp_len + 1 {
+                if pos + i < out.len() {
+                    out[pos + i] = 0;
                 }
             }
+            log::debug!("Patched ELF interpreter to {:?}", std::str::from_utf8(system_linker).unwrap_or(""));
         }
     }
 
@@ -154,11 +152,7 @@ pub fn extract_deb<R: Read>(reader: R, target_dir: &Path) -> anyhow::Result<Vec<
                                 fs::create_dir_all(parent)?;
                             }
                             let cleaned_target = clean_link_target(&link_target);
-                            let abs_target = if link_target.is_absolute() {
-                                target_dir.join(&cleaned_target)
-                            } else {
-                                target_dir.join(&cleaned_target)
-                            };
+                            let abs_target = target_dir.join(&cleaned_target);
                             let _ = fs::remove_file(&dest_path);
                             if abs_target.exists() {
                                 if fs::hard_link(&abs_target, &dest_path).is_err() {
@@ -181,7 +175,6 @@ pub fn extract_deb<R: Read>(reader: R, target_dir: &Path) -> anyhow::Result<Vec<
                         let mut content = Vec::new();
                         file.read_to_end(&mut content)?;
                         
-                        let is_elf = content.starts_with(b"\x7FELF");
                         let patched = patch_content(&content);
 
                         let dest_str = dest_path.to_string_lossy();
@@ -36,11 +36,11 @@ impl PackageIndex {
                 current.clear();
                 current_key = None;
             } else if line.starts_with(' ') || line.starts_with('\t') {
-                if let Some(key) = &current_key {
-                    if let Some(value) = current.get_mut(key) {
-                        value.push('\n');
-                        value.push_str(line.trim());
-                    }
+                if let Some(key) = &current_key
+                    && let Some(value) = current.get_mut(key)
+                {
+                    value.push('\n');
+                    value.push_str(line.trim());
                 }
             } else if let Some((key, value)) = line.split_once(": ") {
                 current_key = Some(key.to_string());
@@ -48,10 +48,10 @@ impl PackageIndex {
             }
         }
 
-        if !current.is_empty() {
-            if let Some(pkg) = Self::build_package(&current) {
-                packages.insert(pkg.name.clone(), pkg);
-            }
+        if !current.is_empty()
+            && let Some(pkg) = Self::build_package(&current)
+        {
+            packages.insert(pkg.name.clone(), pkg);
         }
 
         log::debug!("Parsed {} packages from index", packages.len());
@@ -137,15 +137,17 @@ impl PackageIndex {
         self.packages.get(name)
     }
 
+    /// Returns packages matching every whitespace-separated term in `query`,
+    /// best name matches first and ties broken by name.
     pub fn search(&self, query: &str) -> Vec<&PackageInfo> {
-        let query = query.to_lowercase();
-        self.packages
+        let terms = search_terms(query);
+        let mut results: Vec<(u8, &PackageInfo)> = self
+            .packages
             .values()
-            .filter(|p| {
-                p.name.to_lowercase().contains(&query)
-                    || p.description.to_lowercase().contains(&query)
-            })
-            .collect()
+            .filter_map(|p| match_rank(p, &terms).map(|rank| (rank, p)))
+            .collect();
+        results.sort_by(|(ra, a), (rb, b)| ra.cmp(rb).then_with(|| a.name.cmp(&b.name)));
+        results.into_iter().map(|(_, p)| p).collect()
     }
 
     pub fn len(&self) -> usize {
@@ -160,6 +162,37 @@ impl PackageIndex {
     }
 }
 
+pub(crate) fn search_terms(query: &str) -> Vec<String> {
+    query.split_whitespace().map(|t| t.to_lowercase()).collect()
+}
+
+/// Ranks a package against lowercase search terms, lower is better:
+/// 0 = a term is the exact name, 1 = the name starts with a term,
+/// 2 = a term appears in the name, 3 = description-only match.
+/// Returns `None` unless every term appears in the name or description.
+pub(crate) fn match_rank(pkg: &PackageInfo, terms: &[String]) -> Option<u8> {
+    let name = pkg.name.to_lowercase();
+    let description = pkg.description.to_lowercase();
+
+    if !terms
+        .iter()
+        .all(|t| name.contains(t.as_str()) || description.contains(t.as_str()))
+    {
+        return None;
+    }
+
+    let rank = if terms.contains(&name) {
+        0
+    } else if terms.iter().any(|t| name.starts_with(t.as_str())) {
+        1
+    } else if terms.iter().any(|t| name.contains(t.as_str())) {
+        2
+    } else {
+        3
+    };
+    Some(rank)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -181,6 +214,55 @@ mod tests {
         assert_eq!(deps[2].version.as_ref().unwrap().op, VersionOp::Eq);
     }
     
+    fn sample_index() -> PackageIndex {
+        let data = "\
+Package: git
+Version: 2.43.0
+Filename: pool/main/g/git/git_2.43.0_aarch64.deb
+Size: 100
+Description: fast, scalable, distributed revision control system
+
+Package: git-lfs
+Version: 3.4.0
+Filename: pool/main/g/git-lfs/git-lfs_3.4.0_aarch64.deb
+Size: 100
+Description: git extension for versioning large files
+
+Package: tig
+Version: 2.5.8
+Filename: pool/main/t/tig/tig_2.5.8_aarch64.deb
+Size: 100
+Description: ncurses-based text-mode interface for git
+
+Package: lazygit
+Version: 0.40.2
+Filename: pool/main/l/lazygit/lazygit_0.40.2_aarch64.deb
+Size: 100
+Description: simple terminal UI for git commands
+";
+        PackageIndex::parse(BufReader::new(data.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_search_ranks_name_matches_first() {
+        let index = sample_index();
+        let names: Vec<&str> = index.search("git").iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["git", "git-lfs", "lazygit", "tig"]);
+    }
+
+    #[test]
+    fn test_search_requires_all_terms() {
+        let index = sample_index();
+        let names: Vec<&str> = index
+            .search("git Terminal")
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["lazygit"]);
+
+        assert!(index.search("git nonexistent").is_empty());
+    }
+
     #[test]
     fn test_parse_simple_list() {
         let provides = "editor, vi".to_string();
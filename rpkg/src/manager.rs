@@ -1,17 +1,81 @@
 use crate::extract::extract_deb;
-use crate::index::PackageIndex;
+use crate::index::{PackageIndex, match_rank, search_terms};
 use crate::resolver::Resolver;
-use crate::types::{InstalledPackage, Repository};
+use crate::types::{InstalledPackage, PackageInfo, Repository};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Bytes needed on disk to unpack `packages`. Debian's `Installed-Size` is in KiB.
+pub fn required_space(packages: &[PackageInfo]) -> u64 {
+    packages
+        .iter()
+        .map(|p| p.installed_size.saturating_mul(1024))
+        .fold(0, u64::saturating_add)
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+fn available_space(path: &Path) -> anyhow::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+/// Fails unless `available` covers `required`. With `force`, a shortfall or
+/// a filesystem that can't report its free space is only a warning.
+fn check_space(required: u64, available: anyhow::Result<u64>, force: bool) -> anyhow::Result<()> {
+    let available = match available {
+        Ok(available) => available,
+        Err(e) if force => {
+            log::warn!("Could not check free disk space, installing anyway: {}", e);
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(e.context("Could not check free disk space (use --force to skip the check)"));
+        }
+    };
+    if required > available {
+        if !force {
+            anyhow::bail!(
+                "Insufficient disk space: need {} KiB, only {} KiB available (use --force to override)",
+                required / 1024,
+                available / 1024
+            );
+        }
+        log::warn!("Installing despite insufficient disk space ({} KiB needed, {} KiB available)", required / 1024, available / 1024);
+    }
+    Ok(())
+}
+
+/// Takes an exclusive advisory lock on `path`, held until the returned file is dropped.
+fn lock_database(path: &Path) -> anyhow::Result<fs::File> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(fs::TryLockError::WouldBlock) => anyhow::bail!(
+            "database locked: another rpkg process is using {}",
+            path.display()
+        ),
+        Err(fs::TryLockError::Error(e)) => Err(e.into()),
+    }
+}
 
 pub struct PackageManager {
     prefix: PathBuf,
     db_path: PathBuf,
     installed: HashMap<String, InstalledPackage>,
     repo: Repository,
+    _lock: fs::File,
 }
 
 impl PackageManager {
@@ -20,14 +84,17 @@ impl PackageManager {
         let db_path = prefix.join("var/lib/rpkg/db.json");
         let cache_dir = prefix.join("var/cache/rpkg");
 
-        fs::create_dir_all(&prefix.join("var/lib/rpkg"))?;
+        fs::create_dir_all(prefix.join("var/lib/rpkg"))?;
         fs::create_dir_all(&cache_dir)?;
 
+        let lock = lock_database(&prefix.join("var/lib/rpkg/lock"))?;
+
         let mut pm = Self {
             prefix,
             db_path,
             installed: HashMap::new(),
             repo: Repository::default(),
+            _lock: lock,
         };
 
         pm.load_database()?;
@@ -38,7 +105,19 @@ impl PackageManager {
         if self.db_path.exists() {
             let data = fs::read_to_string(&self.db_path)?;
             if !data.is_empty() {
-                self.installed = serde_json::from_str(&data)?;
+                match serde_json::from_str(&data) {
+                    Ok(installed) => self.installed = installed,
+                    Err(e) => {
+                        let mut backup_path = self.db_path.clone();
+                        backup_path.set_extension("json.bak");
+                        fs::rename(&self.db_path, &backup_path)?;
+                        log::warn!(
+                            "Package database is corrupt ({}); moved it to {} and starting with an empty database",
+                            e,
+                            backup_path.display()
+                        );
+                    }
+                }
             }
         }
         Ok(())
@@ -96,6 +175,12 @@ impl PackageManager {
 
         log::info!("Packages to install: {:?}", to_install.iter().map(|p| &p.name).collect::<Vec<_>>());
 
+        for pkg in to_install.iter().filter(|p| p.installed_size == 0) {
+            log::warn!("Package {} does not declare an Installed-Size", pkg.name);
+        }
+
+        check_space(required_space(&to_install), available_space(&self.prefix), force)?;
+
         for pkg in to_install {
             log::info!("Downloading {}...", pkg.name);
             let url = format!("{}/{}", self.repo.url, pkg.filename);
@@ -126,10 +211,10 @@ impl PackageManager {
         if let Some(pkg) = self.installed.remove(package_name) {
             for file_path in &pkg.files {
                 let absolute_path = self.prefix.join(file_path);
-                if absolute_path.exists() {
-                    if absolute_path.is_file() || absolute_path.is_symlink() {
-                        let _ = fs::remove_file(&absolute_path);
-                    }
+                if absolute_path.exists()
+                    && (absolute_path.is_file() || absolute_path.is_symlink())
+                {
+                    let _ = fs::remove_file(&absolute_path);
                 }
             }
             self.save_database()?;
@@ -150,6 +235,35 @@ impl PackageManager {
         Ok(index.search(query).into_iter().cloned().collect())
     }
 
+    /// Looks a package up in the cached remote index (`-Si`).
+    pub fn info(&self, package_name: &str) -> anyhow::Result<crate::types::PackageInfo> {
+        let index = PackageIndex::from_cache(&self.index_path())
+            .map_err(|e| anyhow::anyhow!("Failed to read index: {}", e))?;
+        index
+            .get(package_name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("package '{}' was not found", package_name))
+    }
+
+    /// Looks a package up in the local database (`-Qi`).
+    pub fn info_installed(&self, package_name: &str) -> anyhow::Result<&InstalledPackage> {
+        self.installed
+            .get(package_name)
+            .ok_or_else(|| anyhow::anyhow!("package '{}' was not found", package_name))
+    }
+
+    /// Searches the local database, ranked like [`PackageIndex::search`].
+    pub fn search_installed(&self, query: &str) -> Vec<&InstalledPackage> {
+        let terms = search_terms(query);
+        let mut results: Vec<(u8, &InstalledPackage)> = self
+            .installed
+            .values()
+            .filter_map(|p| match_rank(&p.info, &terms).map(|rank| (rank, p)))
+            .collect();
+        results.sort_by(|(ra, a), (rb, b)| ra.cmp(rb).then_with(|| a.info.name.cmp(&b.info.name)));
+        results.into_iter().map(|(_, p)| p).collect()
+    }
+
     pub fn upgrade(&mut self) -> anyhow::Result<()> {
         log::info!("Upgrading all packages...");
         let index = PackageIndex::from_cache(&self.index_path())
@@ -157,10 +271,10 @@ impl PackageManager {
         
         let mut to_upgrade = Vec::new();
         for (name, installed) in &self.installed {
-            if let Some(latest) = index.get(name) {
-                if latest.version != installed.info.version {
-                    to_upgrade.push(name.clone());
-                }
+            if let Some(latest) = index.get(name)
+                && latest.version != installed.info.version
+            {
+                to_upgrade.push(name.clone());
             }
         }
 
@@ -175,3 +289,157 @@ impl PackageManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::PackageInfo;
+
+    fn temp_prefix(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rpkg-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn fake_installed(name: &str, description: &str) -> InstalledPackage {
+        InstalledPackage {
+            info: PackageInfo {
+                name: name.into(),
+                version: "1.0".into(),
+                architecture: "aarch64".into(),
+                filename: format!("pool/main/{}.deb", name),
+                size: 0,
+                installed_size: 0,
+                sha256: String::new(),
+                depends: vec![],
+                provides: vec![],
+                conflicts: vec![],
+                description: description.into(),
+                homepage: None,
+                maintainer: None,
+            },
+            files: vec![],
+            install_time: 0,
+            explicit: true,
+            required_by: vec![],
+        }
+    }
+
+    #[test]
+    fn test_required_space_sums_installed_size() {
+        let mut a = fake_installed("a", "").info;
+        a.installed_size = 100;
+        let mut b = fake_installed("b", "").info;
+        b.installed_size = 2048;
+        let c = fake_installed("c", "").info;
+
+        assert_eq!(required_space(&[]), 0);
+        assert_eq!(required_space(&[a.clone(), b, c]), (100 + 2048) * 1024);
+
+        a.installed_size = u64::MAX;
+        assert_eq!(required_space(&[a.clone(), a]), u64::MAX);
+    }
+
+    #[test]
+    fn test_check_space_force_tolerates_unknown_free_space() {
+        assert!(check_space(2048, Ok(4096), false).is_ok());
+        assert!(check_space(8192, Ok(4096), false).is_err());
+        assert!(check_space(8192, Ok(4096), true).is_ok());
+
+        let unknown = || Err(anyhow::anyhow!("statvfs failed"));
+        assert!(check_space(2048, unknown(), false).is_err());
+        assert!(check_space(2048, unknown(), true).is_ok());
+        assert!(available_space(Path::new("/nonexistent/rpkg-prefix")).is_err());
+    }
+
+    #[test]
+    fn test_second_instance_is_locked_out() {
+        let prefix = temp_prefix("lock");
+        let pm = PackageManager::new(&prefix).unwrap();
+
+        let err = PackageManager::new(&prefix).err().expect("second lock should fail");
+        assert!(err.to_string().contains("database locked"));
+
+        drop(pm);
+        assert!(PackageManager::new(&prefix).is_ok());
+
+        let _ = fs::remove_dir_all(&prefix);
+    }
+
+    #[test]
+    fn test_corrupt_database_is_backed_up() {
+        let prefix = temp_prefix("corrupt-db");
+        let db_dir = prefix.join("var/lib/rpkg");
+        fs::create_dir_all(&db_dir).unwrap();
+        fs::write(db_dir.join("db.json"), "{\"vim\": {\"info\": ").unwrap();
+
+        let pm = PackageManager::new(&prefix).unwrap();
+        assert!(pm.list_installed().is_empty());
+        assert_eq!(
+            fs::read_to_string(db_dir.join("db.json.bak")).unwrap(),
+            "{\"vim\": {\"info\": "
+        );
+        assert!(!db_dir.join("db.json").exists());
+
+        let _ = fs::remove_dir_all(&prefix);
+    }
+
+    #[test]
+    fn test_search_installed() {
+        let prefix = temp_prefix("search-installed");
+        let mut pm = PackageManager::new(&prefix).unwrap();
+        for pkg in [
+            fake_installed("vim", "Vi IMproved, a text editor"),
+            fake_installed("nano", "small, friendly text editor"),
+        ] {
+            pm.installed.insert(pkg.info.name.clone(), pkg);
+        }
+        pm.save_database().unwrap();
+        drop(pm);
+
+        let pm = PackageManager::new(&prefix).unwrap();
+        let names: Vec<&str> = pm
+            .search_installed("editor")
+            .iter()
+            .map(|p| p.info.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["nano", "vim"]);
+
+        let names: Vec<&str> = pm
+            .search_installed("vim")
+            .iter()
+            .map(|p| p.info.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["vim"]);
+        assert!(pm.search_installed("emacs").is_empty());
+
+        let _ = fs::remove_dir_all(&prefix);
+    }
+
+    #[test]
+    fn test_info_installed_reports_install_details() {
+        let prefix = temp_prefix("info-installed");
+        let mut pm = PackageManager::new(&prefix).unwrap();
+        let mut explicit = fake_installed("vim", "Vi IMproved, a text editor");
+        explicit.install_time = 1_700_000_000;
+        let mut dep = fake_installed("libsodium", "crypto library");
+        dep.explicit = false;
+        dep.required_by = vec!["vim".into()];
+        for pkg in [explicit, dep] {
+            pm.installed.insert(pkg.info.name.clone(), pkg);
+        }
+
+        let vim = pm.info_installed("vim").unwrap().to_string();
+        assert!(vim.contains("Name            : vim"));
+        assert!(vim.contains("Install Date    : 1700000000"));
+        assert!(vim.contains("Install Reason  : Explicitly installed"));
+
+        let lib = pm.info_installed("libsodium").unwrap().to_string();
+        assert!(lib.contains("Install Reason  : Installed as a dependency"));
+        assert!(lib.contains("Required By     : vim"));
+
+        let err = pm.info_installed("emacs").unwrap_err();
+        assert_eq!(err.to_string(), "package 'emacs' was not found");
+        let _ = fs::remove_dir_all(&prefix);
+    }
+}
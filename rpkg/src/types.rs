@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct PackageInfo {
@@ -48,6 +49,68 @@ pub struct InstalledPackage {
     pub required_by: Vec<String>,
 }
 
+fn join_or_none<T: fmt::Display>(items: &[T]) -> String {
+    if items.is_empty() {
+        "None".to_string()
+    } else {
+        items.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("  ")
+    }
+}
+
+impl fmt::Display for VersionOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            VersionOp::Eq => "=",
+            VersionOp::Ge => ">=",
+            VersionOp::Le => "<=",
+            VersionOp::Gt => ">>",
+            VersionOp::Lt => "<<",
+        };
+        f.write_str(op)
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(v) => write!(f, "{} ({} {})", self.name, v.op, v.version),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// pacman-style `-Si` listing
+impl fmt::Display for PackageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Name            : {}", self.name)?;
+        writeln!(f, "Version         : {}", self.version)?;
+        writeln!(f, "Description     : {}", self.description.replace('\n', "\n                  "))?;
+        writeln!(f, "Architecture    : {}", self.architecture)?;
+        writeln!(f, "URL             : {}", self.homepage.as_deref().unwrap_or("None"))?;
+        writeln!(f, "Provides        : {}", join_or_none(&self.provides))?;
+        writeln!(f, "Depends On      : {}", join_or_none(&self.depends))?;
+        writeln!(f, "Conflicts With  : {}", join_or_none(&self.conflicts))?;
+        writeln!(f, "Maintainer      : {}", self.maintainer.as_deref().unwrap_or("None"))?;
+        writeln!(f, "Download Size   : {} B", self.size)?;
+        write!(f, "Installed Size  : {} KiB", self.installed_size)
+    }
+}
+
+/// pacman-style `-Qi` listing
+impl fmt::Display for InstalledPackage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.info)?;
+        writeln!(f, "Required By     : {}", join_or_none(&self.required_by))?;
+        writeln!(f, "Install Date    : {}", self.install_time)?;
+        let reason = if self.explicit {
+            "Explicitly installed"
+        } else {
+            "Installed as a dependency for another package"
+        };
+        write!(f, "Install Reason  : {}", reason)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Repository {
     pub name: String,
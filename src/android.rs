@@ -1,30 +1,223 @@
 #[cfg(feature = "android")]
-use crate::{Pty, TerminalEngine, renderer::AndroidRenderer};
+use crate::{
+    Error, Pty, PtyOptions, Result, TerminalEngine,
+    renderer::{AndroidRenderer, screen::cell_at_pixel},
+};
 use jni::JNIEnv;
-use jni::objects::{JByteArray, JClass, JString};
+use jni::objects::{JByteArray, JClass, JObject, JObjectArray, JString};
 use jni::sys::{jint, jlong};
 use std::collections::HashMap;
 use std::fmt::Write;
 use std::sync::atomic::{AtomicI64, Ordering};
-use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError, RwLock, RwLockWriteGuard};
 use std::thread;
 
 type EngineHandle = jlong;
 
-struct AndroidSession {
+/// Status codes returned by the `jint` JNI calls, mirrored in `RinStatus`
+/// on the Kotlin side. Getters return their value on success and a negative
+/// status otherwise; so do the `createEngine*` calls with their handle.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JniStatus {
+    Ok = 0,
+    /// The engine failed; `getLastError` has the message
+    Failed = -1,
+    /// No session has this handle
+    UnknownHandle = -2,
+    /// A Java argument could not be converted
+    InvalidArgument = -3,
+    /// Talking to the shell's PTY failed; `getLastError` has the message
+    PtyFailed = -4,
+}
+
+impl JniStatus {
+    pub fn code(self) -> jint {
+        self as jint
+    }
+
+    pub fn of<T>(result: &Result<T>) -> Self {
+        match result {
+            Ok(_) => JniStatus::Ok,
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl From<&Error> for JniStatus {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::Pty { .. } => JniStatus::PtyFailed,
+            _ => JniStatus::Failed,
+        }
+    }
+}
+
+pub(crate) struct AndroidSession {
     engine: Arc<Mutex<TerminalEngine>>,
     pty: Arc<Mutex<Pty>>,
+    /// Message for the last failed call, for `getLastError`
+    last_error: Mutex<Option<String>>,
     // We keep these to ensure they live as long as the session
     // reader_thread: Option<thread::JoinHandle<()>>,
 }
 
-static SESSIONS: OnceLock<Arc<RwLock<HashMap<EngineHandle, AndroidSession>>>> = OnceLock::new();
+impl AndroidSession {
+    /// Maps `result` to a status, remembering the error message if any
+    fn status<T>(&self, result: Result<T>) -> jint {
+        let status = JniStatus::of(&result);
+        if let Err(e) = result {
+            log::error!("{}", e);
+            *lock(&self.last_error) = Some(error_message(&e));
+        }
+        status.code()
+    }
+}
+
+/// `error` with its source chain, e.g. "PTY write failed: broken pipe"
+pub(crate) fn error_message(error: &Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(e) = source {
+        let _ = write!(message, ": {}", e);
+        source = e.source();
+    }
+    message
+}
+
+type Sessions = HashMap<EngineHandle, AndroidSession>;
+
+static SESSIONS: OnceLock<RwLock<Sessions>> = OnceLock::new();
 static NEXT_HANDLE: AtomicI64 = AtomicI64::new(1);
 
-fn get_sessions() -> Arc<RwLock<HashMap<EngineHandle, AndroidSession>>> {
-    SESSIONS
-        .get_or_init(|| Arc::new(RwLock::new(HashMap::new())))
-        .clone()
+fn sessions() -> &'static RwLock<Sessions> {
+    SESSIONS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+fn sessions_mut() -> RwLockWriteGuard<'static, Sessions> {
+    sessions().write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs `f` on the session for `handle`, or returns `None` if there is none.
+/// A panic elsewhere (say in the reader thread) poisons the locks; every
+/// JNI call goes through here and [`lock`] so the terminal keeps working.
+pub(crate) fn with_session<T>(
+    handle: EngineHandle,
+    f: impl FnOnce(&AndroidSession) -> T,
+) -> Option<T> {
+    let sessions = sessions().read().unwrap_or_else(PoisonError::into_inner);
+    sessions.get(&handle).map(f)
+}
+
+/// Locks `mutex`, recovering the data if a holder panicked
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Writes PTY output to the engine, then hands any replies it produced
+/// (DSR, DA, DECRQSS, ...) to `reply` so queries are answered on the spot.
+/// The engine is unlocked before `reply` runs.
+pub(crate) fn feed_engine(
+    engine: &Mutex<TerminalEngine>,
+    data: &[u8],
+    reply: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let responses = {
+        let mut engine = lock(engine);
+        if !engine.process(data)?.has_responses {
+            return Ok(());
+        }
+        engine.buffer_mut().drain_responses()
+    };
+    send_responses(responses, reply)
+}
+
+fn send_responses(
+    responses: Vec<Vec<u8>>,
+    mut reply: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    responses.iter().try_for_each(|response| reply(response))
+}
+
+/// Written to new sessions unless the caller asks for another banner
+pub(crate) const DEFAULT_BANNER: &str = concat!(
+    "\x1b[36m",
+    r"  ____  _       ",
+    "\r\n",
+    r" |  _ \(_)_ __  ",
+    "\r\n",
+    r" | |_) | | '_ \ ",
+    "\r\n",
+    r" |  _ <| | | | |",
+    "\r\n",
+    r" |_| \_\_|_| |_|",
+    "\r\n",
+    "\x1b[0m\r\n",
+    " \x1b[90mTerminal v",
+    env!("CARGO_PKG_VERSION"),
+    "\x1b[0m\r\n",
+    " \x1b[90mgithub.com/pavelc4/Rin\x1b[0m\r\n",
+    "\r\n",
+);
+
+fn init_logging() {
+    #[cfg(feature = "android")]
+    android_logger::init_once(
+        android_logger::Config::default()
+            .with_max_level(log::LevelFilter::Debug)
+            .with_tag("RinNative"),
+    );
+}
+
+fn java_string(env: &mut JNIEnv, s: &JString) -> Option<String> {
+    env.get_string(s).ok().map(|s| s.into())
+}
+
+/// Reads a Java `String[]` element by element, so positions line up with
+/// the array; a null array reads as empty and a null element as `None`
+fn java_string_array(env: &mut JNIEnv, array: &JObjectArray) -> Vec<Option<String>> {
+    let len = env.get_array_length(array).unwrap_or(0);
+    (0..len)
+        .map(|i| {
+            let element = env.get_object_array_element(array, i).ok()?;
+            if element.is_null() {
+                return None;
+            }
+            java_string(env, &JString::from(element))
+        })
+        .collect()
+}
+
+/// Pairs `keys[i]` with `values[i]`, or `None` if the lengths differ or any
+/// element is missing, rather than shifting a value onto the wrong key
+pub(crate) fn env_pairs(
+    keys: Vec<Option<String>>,
+    values: Vec<Option<String>>,
+) -> Option<Vec<(String, String)>> {
+    if keys.len() != values.len() {
+        return None;
+    }
+    keys.into_iter()
+        .zip(values)
+        .map(|(key, value)| Some((key?, value?)))
+        .collect()
+}
+
+/// PTY options for a session: HOME, USER and the Rin prefix paths, then
+/// `env` on top so callers can override any of them
+pub(crate) fn session_options(
+    shell: &str,
+    width: u16,
+    height: u16,
+    home_dir: &str,
+    username: &str,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> PtyOptions {
+    PtyOptions::new(shell, width, height)
+        .home(home_dir)
+        .user(username)
+        .prefix(rpkg::DEFAULT_PREFIX)
+        .vars(env)
 }
 
 #[unsafe(no_mangle)]
@@ -37,22 +230,10 @@ pub extern "system" fn Java_com_rin_RinLib_createEngine(
     home_dir: JString,
     username: JString,
 ) -> jlong {
-    #[cfg(feature = "android")]
-    android_logger::init_once(
-        android_logger::Config::default()
-            .with_max_level(log::LevelFilter::Debug)
-            .with_tag("RinNative"),
-    );
+    init_logging();
 
-    let home_dir_str: String = env
-        .get_string(&home_dir)
-        .map(|s| s.into())
-        .unwrap_or_default();
-
-    let username_str: String = env
-        .get_string(&username)
-        .map(|s| s.into())
-        .unwrap_or_else(|_| "user".to_string());
+    let home_dir_str = java_string(&mut env, &home_dir).unwrap_or_default();
+    let username_str = java_string(&mut env, &username).unwrap_or_else(|| "user".to_string());
 
     log::info!(
         "Creating Engine: {}x{}, HOME={}, USER={}",
@@ -62,51 +243,125 @@ pub extern "system" fn Java_com_rin_RinLib_createEngine(
         username_str
     );
 
-    // 1. Create Renderer & Engine
-    let renderer = Box::new(AndroidRenderer::new(font_size));
-    let engine = Arc::new(Mutex::new(TerminalEngine::new(
-        width as usize,
-        height as usize,
-        renderer,
-    )));
-
-    // 2. Write startup banner
-    {
-        let mut engine_guard = engine.lock().unwrap();
-        let banner = concat!(
-            "\x1b[36m",
-            r"  ____  _       ",
-            "\r\n",
-            r" |  _ \(_)_ __  ",
-            "\r\n",
-            r" | |_) | | '_ \ ",
-            "\r\n",
-            r" |  _ <| | | | |",
-            "\r\n",
-            r" |_| \_\_|_| |_|",
-            "\r\n",
-            "\x1b[0m\r\n",
-            " \x1b[90mTerminal v",
-            env!("CARGO_PKG_VERSION"),
-            "\x1b[0m\r\n",
-            " \x1b[90mgithub.com/pavelc4/Rin\x1b[0m\r\n",
-            "\r\n",
+    let options = PtyOptions::new("/system/bin/sh", width as u16, height as u16)
+        .home(&home_dir_str)
+        .user(&username_str);
+    create_session(width, height, font_size, options, Some(DEFAULT_BANNER))
+}
+
+/// Like `createEngine`, but runs `shell` (default `/system/bin/sh`) with the
+/// Rin prefix on PATH and `envKeys[i]=envValues[i]` set on top. Returns
+/// `InvalidArgument` if the arrays differ in length or hold a null.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub extern "system" fn Java_com_rin_RinLib_createEngineWithEnv(
+    mut env: JNIEnv,
+    _class: JClass,
+    width: jint,
+    height: jint,
+    font_size: f32,
+    shell: JString,
+    home_dir: JString,
+    username: JString,
+    env_keys: JObjectArray,
+    env_values: JObjectArray,
+) -> jlong {
+    init_logging();
+
+    let shell_str = java_string(&mut env, &shell)
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "/system/bin/sh".to_string());
+    let home_dir_str = java_string(&mut env, &home_dir).unwrap_or_default();
+    let username_str = java_string(&mut env, &username).unwrap_or_else(|| "user".to_string());
+    let keys = java_string_array(&mut env, &env_keys);
+    let values = java_string_array(&mut env, &env_values);
+    let (key_count, value_count) = (keys.len(), values.len());
+    let Some(vars) = env_pairs(keys, values) else {
+        log::error!(
+            "Rejecting environment: {} keys, {} values, or a null entry",
+            key_count,
+            value_count
         );
-        let _ = engine_guard.write(banner.as_bytes());
-    }
+        return JniStatus::InvalidArgument.code() as jlong;
+    };
+
+    log::info!(
+        "Creating Engine: {}x{}, SHELL={}, HOME={}, USER={}, {} extra vars",
+        width,
+        height,
+        shell_str,
+        home_dir_str,
+        username_str,
+        vars.len()
+    );
 
-    // 3. Spawn PTY with home directory and username
-    let pty = match Pty::spawn(
-        "/system/bin/sh",
+    let options = session_options(
+        &shell_str,
         width as u16,
         height as u16,
-        Some(&home_dir_str),
-        Some(&username_str),
-    ) {
+        &home_dir_str,
+        &username_str,
+        vars,
+    );
+    create_session(width, height, font_size, options, Some(DEFAULT_BANNER))
+}
+
+/// Like `createEngine`, but writes `banner` instead of the Rin logo; a null
+/// or empty banner starts with a blank screen
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_rin_RinLib_createEngineWithBanner(
+    mut env: JNIEnv,
+    _class: JClass,
+    width: jint,
+    height: jint,
+    font_size: f32,
+    home_dir: JString,
+    username: JString,
+    banner: JString,
+) -> jlong {
+    init_logging();
+
+    let home_dir_str = java_string(&mut env, &home_dir).unwrap_or_default();
+    let username_str = java_string(&mut env, &username).unwrap_or_else(|| "user".to_string());
+    let banner_str = java_string(&mut env, &banner).filter(|s| !s.is_empty());
+
+    let options = PtyOptions::new("/system/bin/sh", width as u16, height as u16)
+        .home(&home_dir_str)
+        .user(&username_str);
+    create_session(width, height, font_size, options, banner_str.as_deref())
+}
+
+/// An engine with `banner`, if any, already written to it
+pub(crate) fn new_engine(
+    width: jint,
+    height: jint,
+    font_size: f32,
+    banner: Option<&str>,
+) -> TerminalEngine {
+    let renderer = Box::new(AndroidRenderer::new(font_size));
+    let mut engine = TerminalEngine::new(width as usize, height as usize, renderer);
+    if let Some(banner) = banner {
+        let _ = engine.write(banner.as_bytes());
+    }
+    engine
+}
+
+fn create_session(
+    width: jint,
+    height: jint,
+    font_size: f32,
+    options: PtyOptions,
+    banner: Option<&str>,
+) -> jlong {
+    // 1. Create the engine and write the startup banner
+    let engine = Arc::new(Mutex::new(new_engine(width, height, font_size, banner)));
+
+    // 2. Spawn PTY
+    let pty = match Pty::spawn_with(&options) {
         Ok(pty) => Arc::new(Mutex::new(pty)),
         Err(e) => {
             log::error!("Failed to spawn PTY: {}", e);
-            return -1;
+            return JniStatus::PtyFailed.code() as jlong;
         }
     };
 
@@ -117,7 +372,7 @@ pub extern "system" fn Java_com_rin_RinLib_createEngine(
     thread::spawn(move || {
         let mut buffer = [0u8; 4096];
         let mut reader = {
-            let mut pty_guard = pty_clone.lock().unwrap();
+            let mut pty_guard = lock(&pty_clone);
             match pty_guard.take_reader() {
                 Ok(r) => r,
                 Err(e) => {
@@ -134,8 +389,10 @@ pub extern "system" fn Java_com_rin_RinLib_createEngine(
                     break;
                 }
                 Ok(n) => {
-                    let mut engine_guard = engine_clone.lock().unwrap();
-                    if let Err(e) = engine_guard.write(&buffer[..n]) {
+                    let result = feed_engine(&engine_clone, &buffer[..n], |response| {
+                        lock(&pty_clone).write(response)
+                    });
+                    if let Err(e) = result {
                         log::error!("Failed to write to engine: {}", e);
                     }
                 }
@@ -148,10 +405,13 @@ pub extern "system" fn Java_com_rin_RinLib_createEngine(
     });
 
     let handle = NEXT_HANDLE.fetch_add(1, Ordering::SeqCst);
-    let session = AndroidSession { engine, pty };
+    let session = AndroidSession {
+        engine,
+        pty,
+        last_error: Mutex::new(None),
+    };
 
-    let sessions_arc = get_sessions();
-    sessions_arc.write().unwrap().insert(handle, session);
+    sessions_mut().insert(handle, session);
 
     log::info!("Engine created with handle: {}", handle);
     handle
@@ -163,8 +423,7 @@ pub extern "system" fn Java_com_rin_RinLib_destroyEngine(
     _class: JClass,
     handle: jlong,
 ) {
-    let sessions_arc = get_sessions();
-    sessions_arc.write().unwrap().remove(&handle);
+    sessions_mut().remove(&handle);
     log::info!("Engine destroyed: {}", handle);
 }
 
@@ -175,26 +434,15 @@ pub extern "system" fn Java_com_rin_RinLib_write(
     handle: jlong,
     data: JByteArray,
 ) -> jint {
-    match env.convert_byte_array(&data) {
-        Ok(bytes) => {
-            let sessions_arc = get_sessions();
-            let sessions = sessions_arc.read().unwrap();
-            if let Some(session) = sessions.get(&handle) {
-                // Write to PTY, not Engine
-                let mut pty = session.pty.lock().unwrap();
-                match pty.write(&bytes) {
-                    Ok(_) => 0,
-                    Err(e) => {
-                        log::error!("Failed to write to PTY: {}", e);
-                        -1
-                    }
-                }
-            } else {
-                -2 // Handle not found
-            }
-        }
-        Err(_) => -1, // Convert failed
-    }
+    let Ok(bytes) = env.convert_byte_array(&data) else {
+        return JniStatus::InvalidArgument.code();
+    };
+    with_session(handle, |session| {
+        // Write to PTY, not Engine
+        let result = lock(&session.pty).write(&bytes);
+        session.status(result)
+    })
+    .unwrap_or(JniStatus::UnknownHandle.code())
 }
 
 #[unsafe(no_mangle)]
@@ -203,17 +451,11 @@ pub extern "system" fn Java_com_rin_RinLib_render(
     _class: JClass,
     handle: jlong,
 ) -> jint {
-    let sessions_arc = get_sessions();
-    let sessions = sessions_arc.read().unwrap();
-    if let Some(session) = sessions.get(&handle) {
-        let mut engine = session.engine.lock().unwrap();
-        match engine.render() {
-            Ok(_) => 0,
-            Err(_) => -1,
-        }
-    } else {
-        -1
-    }
+    with_session(handle, |session| {
+        let result = lock(&session.engine).render();
+        session.status(result)
+    })
+    .unwrap_or(JniStatus::UnknownHandle.code())
 }
 
 #[unsafe(no_mangle)]
@@ -224,19 +466,16 @@ pub extern "system" fn Java_com_rin_RinLib_resize(
     width: jint,
     height: jint,
 ) -> jint {
-    let sessions_arc = get_sessions();
-    let sessions = sessions_arc.read().unwrap();
-    if let Some(session) = sessions.get(&handle) {
+    with_session(handle, |session| {
         // Resize both Engine and PTY
-        let mut engine = session.engine.lock().unwrap();
-        let _ = engine.resize(width as usize, height as usize);
-
-        let mut pty = session.pty.lock().unwrap();
-        let _ = pty.resize(width as u16, height as u16);
-        0
-    } else {
-        -1
-    }
+        let result = lock(&session.engine).resize(width as usize, height as usize);
+        if JniStatus::of(&result) != JniStatus::Ok {
+            return session.status(result);
+        }
+        let result = lock(&session.pty).resize(width as u16, height as u16);
+        session.status(result)
+    })
+    .unwrap_or(JniStatus::UnknownHandle.code())
 }
 
 #[unsafe(no_mangle)]
@@ -246,52 +485,90 @@ pub extern "system" fn Java_com_rin_RinLib_getLine<'local>(
     handle: jlong,
     y: jint,
 ) -> JString<'local> {
-    let sessions_arc = get_sessions();
-    let sessions = sessions_arc.read().unwrap();
-    if let Some(session) = sessions.get(&handle) {
-        let engine = session.engine.lock().unwrap();
-        let buffer = engine.buffer();
-        let grid = buffer.grid();
-        if let Some(row) = grid.row(y as usize) {
-            let line: String = row.iter().map(|c| c.character).collect();
-            return env
-                .new_string(line)
-                .unwrap_or_else(|_| env.new_string("").unwrap());
-        }
-    }
-    env.new_string("").unwrap()
+    let line = with_session(handle, |session| {
+        let engine = lock(&session.engine);
+        let row = engine.buffer().grid().row(y as usize)?;
+        Some(row.iter().map(|c| c.character).collect::<String>())
+    })
+    .flatten()
+    .unwrap_or_default();
+    env.new_string(line)
+        .unwrap_or_else(|_| env.new_string("").unwrap())
 }
 
+/// Cursor column, or `UnknownHandle`; callers must check for a negative
+/// status before using it as a position
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_com_rin_RinLib_getCursorX(
     _env: JNIEnv,
     _class: JClass,
     handle: jlong,
 ) -> jint {
-    let sessions_arc = get_sessions();
-    let sessions = sessions_arc.read().unwrap();
-    if let Some(session) = sessions.get(&handle) {
-        let engine = session.engine.lock().unwrap();
-        engine.buffer().cursor_pos().0 as jint
-    } else {
-        0
-    }
+    with_session(handle, |session| {
+        lock(&session.engine).buffer().cursor_pos().0 as jint
+    })
+    .unwrap_or(JniStatus::UnknownHandle.code())
 }
 
+/// Cursor row, or `UnknownHandle`; see `getCursorX`
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_com_rin_RinLib_getCursorY(
     _env: JNIEnv,
     _class: JClass,
     handle: jlong,
 ) -> jint {
-    let sessions_arc = get_sessions();
-    let sessions = sessions_arc.read().unwrap();
-    if let Some(session) = sessions.get(&handle) {
-        let engine = session.engine.lock().unwrap();
-        engine.buffer().cursor_pos().1 as jint
-    } else {
-        0
+    with_session(handle, |session| {
+        lock(&session.engine).buffer().cursor_pos().1 as jint
+    })
+    .unwrap_or(JniStatus::UnknownHandle.code())
+}
+
+/// Passes the cell size the view measured (`measureText` / `fontSpacing`) to
+/// the renderer, so `getCellAt` maps pixels the way the text was drawn. Call
+/// it whenever the font size changes.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_rin_RinLib_setCellSize(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    width: f32,
+    height: f32,
+) -> jint {
+    if !(width > 0.0 && height > 0.0) {
+        return JniStatus::InvalidArgument.code();
     }
+    with_session(handle, |session| {
+        lock(&session.engine).set_cell_size(width, height);
+        JniStatus::Ok.code()
+    })
+    .unwrap_or(JniStatus::UnknownHandle.code())
+}
+
+/// Returns the cell under a pixel packed as `(row << 16) | col`, clamped to
+/// the viewport, or `UnknownHandle`. The row counts from the oldest
+/// scrollback line, so it names the same text while scrolled back.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_rin_RinLib_getCellAt(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    px: f32,
+    py: f32,
+) -> jint {
+    with_session(handle, |session| {
+        let engine = lock(&session.engine);
+        let buffer = engine.buffer();
+        let grid = buffer.grid();
+        let (col, row) = cell_at_pixel(engine.cell_size().unwrap_or_default(), px, py);
+        let col = col.min(grid.width().saturating_sub(1)).min(0xffff);
+        let row = row.min(grid.height().saturating_sub(1));
+        let first_visible = buffer
+            .scrollback_len()
+            .saturating_sub(buffer.scroll_offset());
+        let row = (first_visible + row).min(0x7fff);
+        ((row << 16) | col) as jint
+    })
+    .unwrap_or(JniStatus::UnknownHandle.code())
 }
 
 #[unsafe(no_mangle)]
@@ -301,81 +578,130 @@ pub extern "system" fn Java_com_rin_RinLib_getCellData<'local>(
     handle: jlong,
     y: jint,
 ) -> JString<'local> {
-    let sessions_arc = get_sessions();
-    let sessions = sessions_arc.read().unwrap();
-    if let Some(session) = sessions.get(&handle) {
-        let engine = session.engine.lock().unwrap();
-        let buffer = engine.buffer();
-        let grid = buffer.grid();
-        if let Some(row) = grid.row(y as usize) {
-            let mut result = String::with_capacity(row.len() * 32);
-            for cell in row.iter() {
-                // Skip wide spacer cells
-                if cell.wide_spacer {
-                    continue;
-                }
+    let data = with_session(handle, |session| {
+        let engine = lock(&session.engine);
+        let row = engine.buffer().grid().row(y as usize)?;
+        let mut result = String::with_capacity(row.len() * 32);
+        for cell in row.iter() {
+            // Skip wide spacer cells
+            if cell.wide_spacer {
+                continue;
+            }
 
-                let style = &cell.style;
-                let (fg, bg) = if style.reverse {
-                    (&style.bg, &style.fg)
-                } else {
-                    (&style.fg, &style.bg)
-                };
-
-                // Format: char\tfgR,fgG,fgB\tbgR,bgG,bgB\tflags (tab-separated)
-                // Use write! instead of format! to avoid heap allocations
-                let _ = write!(
-                    result,
-                    "{}\t{},{},{}\t{},{},{}",
-                    cell.character, fg.r, fg.g, fg.b, bg.r, bg.g, bg.b
-                );
-                result.push('\t');
-
-                // Flags
-                if style.bold {
-                    result.push('b');
-                }
-                if style.italic {
-                    result.push('i');
-                }
-                if style.dim {
-                    result.push('d');
-                }
-                if cell.wide {
-                    result.push('w');
-                }
+            let style = &cell.style;
+            let (fg, bg) = if style.reverse {
+                (&style.bg, &style.fg)
+            } else {
+                (&style.fg, &style.bg)
+            };
+
+            // Format: char\tfgR,fgG,fgB\tbgR,bgG,bgB\tflags (tab-separated)
+            // Use write! instead of format! to avoid heap allocations
+            let _ = write!(
+                result,
+                "{}\t{},{},{}\t{},{},{}",
+                cell.character, fg.r, fg.g, fg.b, bg.r, bg.g, bg.b
+            );
+            result.push('\t');
 
-                result.push('\n');
+            // Flags
+            if style.bold {
+                result.push('b');
+            }
+            if style.italic {
+                result.push('i');
             }
-            return env
-                .new_string(result)
-                .unwrap_or_else(|_| env.new_string("").unwrap());
+            if style.dim {
+                result.push('d');
+            }
+            if cell.wide {
+                result.push('w');
+            }
+
+            result.push('\n');
         }
-    }
-    env.new_string("").unwrap()
+        Some(result)
+    })
+    .flatten()
+    .unwrap_or_default();
+    env.new_string(data)
+        .unwrap_or_else(|_| env.new_string("").unwrap())
 }
+
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_com_rin_RinLib_hasDirtyRows(
     _env: JNIEnv,
     _class: JClass,
     handle: jlong,
 ) -> bool {
-    let sessions_arc = get_sessions();
-    let sessions = sessions_arc.read().unwrap();
-    if let Some(session) = sessions.get(&handle) {
-        let engine = session.engine.lock().unwrap();
-        engine.buffer().grid().has_dirty_rows()
-    } else {
-        false
-    }
+    with_session(handle, |session| {
+        lock(&session.engine).buffer().grid().has_dirty_rows()
+    })
+    .unwrap_or(false)
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_rin_RinLib_needsRender(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> bool {
+    with_session(handle, |session| lock(&session.engine).needs_render()).unwrap_or(false)
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_rin_RinLib_setFocused(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    focused: bool,
+) {
+    with_session(handle, |session| {
+        let responses = {
+            let mut engine = lock(&session.engine);
+            engine.focus_changed(focused);
+            engine.buffer_mut().drain_responses()
+        };
+        if let Err(e) = send_responses(responses, |r| lock(&session.pty).write(r)) {
+            log::error!("Failed to write focus report to PTY: {}", e);
+        }
+    });
+}
+
+/// Writes any replies still queued in the engine to the PTY. The reader
+/// thread already does this after every read, so this is only needed after
+/// driving the engine from Kotlin.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_rin_RinLib_flushResponses(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jint {
+    with_session(handle, |session| {
+        let responses = lock(&session.engine).buffer_mut().drain_responses();
+        let result = send_responses(responses, |r| lock(&session.pty).write(r));
+        session.status(result)
+    })
+    .unwrap_or(JniStatus::UnknownHandle.code())
 }
 
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_com_rin_RinLib_clearDirty(_env: JNIEnv, _class: JClass, handle: jlong) {
-    let sessions_arc = get_sessions();
-    let sessions = sessions_arc.read().unwrap();
-    if let Some(session) = sessions.get(&handle) {
-        let mut engine = session.engine.lock().unwrap();
-        engine.buffer_mut().grid_mut().clear_dirty();
-    }
+    with_session(handle, |session| lock(&session.engine).mark_rendered());
+}
+
+/// Message for the last call on `handle` that returned `Failed` or
+/// `PtyFailed`, or "" if none has; null if the string can't be allocated
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_rin_RinLib_getLastError<'local>(
+    env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> JString<'local> {
+    let message = with_session(handle, |session| {
+        lock(&session.last_error).clone().unwrap_or_default()
+    })
+    .unwrap_or_else(|| format!("unknown handle {}", handle));
+    env.new_string(message)
+        .unwrap_or_else(|_| JString::from(JObject::null()))
 }
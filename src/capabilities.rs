@@ -0,0 +1,61 @@
+/// What the emulator supports, in one place so the `TERM` a PTY gets, the
+/// device attribute replies and XTGETTCAP answers agree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The terminfo entry programs should use
+    pub term: &'static str,
+    /// Reported as the firmware version in the DA2 reply
+    pub version: u16,
+    /// Palette size: 8, 16 or 256
+    pub colors: u16,
+    /// 24-bit color via `38;2;r;g;b`
+    pub true_color: bool,
+    /// X10/normal/button/any-event mouse tracking with SGR reports
+    pub mouse: bool,
+    /// DECSCA and DECSED/DECSEL
+    pub selective_erase: bool,
+}
+
+/// Rin's own capabilities
+pub const CAPABILITIES: Capabilities = Capabilities {
+    term: "xterm-256color",
+    version: 100,
+    colors: 256,
+    true_color: true,
+    mouse: true,
+    selective_erase: true,
+};
+
+impl Capabilities {
+    /// Reply to DA1 (`CSI c`): a VT220-level terminal plus its extensions
+    pub fn primary_da(&self) -> String {
+        let mut reply = String::from("\x1b[?62");
+        if self.selective_erase {
+            reply.push_str(";6");
+        }
+        if self.colors > 0 {
+            // 22: ANSI color
+            reply.push_str(";22");
+        }
+        reply.push('c');
+        reply
+    }
+
+    /// Reply to DA2 (`CSI > c`): terminal type 1 (VT220), version, ROM 0
+    pub fn secondary_da(&self) -> String {
+        format!("\x1b[>1;{};0c", self.version)
+    }
+
+    /// The value of a termcap/terminfo capability for XTGETTCAP: `Some("")`
+    /// for boolean capabilities we have, `None` for unknown or unsupported
+    /// ones.
+    pub fn termcap(&self, name: &str) -> Option<String> {
+        match name {
+            "TN" | "name" => Some(self.term.to_string()),
+            "Co" | "colors" => Some(self.colors.to_string()),
+            "RGB" if self.true_color => Some("8".to_string()),
+            "Tc" if self.true_color => Some(String::new()),
+            _ => None,
+        }
+    }
+}
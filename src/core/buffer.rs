@@ -1,10 +1,206 @@
-use super::cell::{Cell, CellStyle, Hyperlink};
-use super::grid::Grid;
-use crate::parser::{Charset, Command, CursorStyle, MouseMode};
-use anyhow::Result;
-use std::collections::VecDeque;
+use super::cell::{Cell, CellStyle, Color, Hyperlink};
+use super::grid::{CellRuns, Direction, Grid, LineAttr};
+use crate::capabilities::CAPABILITIES;
+use crate::error::{Error, Result};
+use crate::parser::{Charset, Command, CursorStyle, MouseMode, Theme};
+use crate::renderer::text::push_row;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hasher;
+use std::io::Write;
+use std::ops::Range;
+use std::time::{Duration, Instant};
 
 const DEFAULT_SCROLLBACK_LIMIT: usize = 2_000;
+const DEFAULT_REFLOW_LINE_LIMIT: usize = 64 * 1024;
+/// How long a synchronized-output frame may hold rendering
+const DEFAULT_SYNCHRONIZED_OUTPUT_TIMEOUT: Duration = Duration::from_millis(150);
+/// Punctuation that double-click selection keeps inside a word, so paths
+/// and URLs select whole
+const DEFAULT_WORD_CHARS: &str = "_-./~@";
+
+/// Reads the state of one mode
+type ModeState = fn(&TerminalBuffer) -> bool;
+
+/// The private modes (`CSI ? n h`) the buffer tracks, in ascending order.
+/// Aliases are left out; see [`canonical_private_mode`].
+const PRIVATE_MODES: &[(u16, ModeState)] = &[
+    (1, |b| b.application_cursor_keys),
+    (6, |b| b.origin_mode),
+    (7, |b| b.auto_wrap_mode),
+    (25, |b| b.cursor_visible),
+    (66, |b| b.application_keypad),
+    (67, |b| b.backarrow_sends_backspace),
+    (1000, |b| b.mouse_mode == MouseMode::ReportClick),
+    (1002, |b| b.mouse_mode == MouseMode::ReportMotion),
+    (1003, |b| b.mouse_mode == MouseMode::ReportAll),
+    (1004, |b| b.focus_events),
+    (1049, |b| b.is_alternate_screen()),
+    (2004, |b| b.bracketed_paste),
+    (2026, |b| b.synchronized_output),
+];
+
+/// The ANSI modes (`CSI n h`, no `?`) the buffer tracks
+const ANSI_MODES: &[(u16, ModeState)] = &[(4, |b| b.insert_mode), (20, |b| b.line_feed_mode)];
+
+/// The [`PRIVATE_MODES`] entry an alias reads: X10 mouse (9) is 1000, and
+/// the older alternate screens (47, 1047) are 1049
+fn canonical_private_mode(mode: u16) -> u16 {
+    match mode {
+        9 => 1000,
+        47 | 1047 => 1049,
+        _ => mode,
+    }
+}
+
+fn mode_state(table: &[(u16, ModeState)], mode: u16) -> Option<ModeState> {
+    table
+        .iter()
+        .find(|&&(number, _)| number == mode)
+        .map(|&(_, state)| state)
+}
+
+/// DEC Special Graphics: maps the ASCII letters used for box drawing
+fn translate_line_drawing(c: char) -> char {
+    match c {
+        'j' => '┘',
+        'k' => '┐',
+        'l' => '┌',
+        'm' => '└',
+        'n' => '┼',
+        'q' => '─',
+        't' => '├',
+        'u' => '┤',
+        'v' => '┴',
+        'w' => '┬',
+        'x' => '│',
+        'a' => '▒',
+        _ => c,
+    }
+}
+
+fn encode_hex(s: &str) -> String {
+    s.bytes().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Appends the text of `cells`, skipping wide-character spacers
+fn push_cells(text: &mut String, cells: &[Cell]) {
+    for cell in cells.iter().filter(|c| !c.wide_spacer) {
+        text.push(cell.character);
+        text.extend(cell.zerowidth.iter());
+    }
+}
+
+/// 64-bit FNV-1a. Unlike std's `DefaultHasher` the algorithm is fixed, and
+/// [`hash_cell`] feeds it explicit little-endian bytes rather than derived
+/// `Hash` output, so a hash is the same on every run, platform and release.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Writes the parts of `cell` that [`TerminalBuffer::content_hash`] covers:
+/// the text, each style field in a fixed layout, the protected flag and the
+/// link's URI. Link ids are left out: unnamed links get a generated one.
+fn hash_cell(hasher: &mut Fnv1a, cell: &Cell) {
+    hasher.write(&u32::from(cell.character).to_le_bytes());
+    hasher.write(&(cell.zerowidth.len() as u32).to_le_bytes());
+    for &c in &cell.zerowidth {
+        hasher.write(&u32::from(c).to_le_bytes());
+    }
+    let style = &cell.style;
+    for color in [style.fg, style.bg] {
+        hasher.write(&[color.r, color.g, color.b]);
+    }
+    let flags = [
+        style.bold,
+        style.italic,
+        style.reverse,
+        style.strikethrough,
+        style.dim,
+        style.hidden,
+    ];
+    let bits = flags
+        .iter()
+        .enumerate()
+        .fold(0u8, |bits, (i, &on)| bits | u8::from(on) << i);
+    hasher.write(&[bits, style.underline as u8, style.font]);
+    match style.underline_color {
+        Some(c) => hasher.write(&[1, c.r, c.g, c.b]),
+        None => hasher.write(&[0]),
+    }
+    hasher.write(&[u8::from(cell.protected)]);
+    match &cell.hyperlink {
+        Some(link) => {
+            hasher.write(&[1]);
+            hasher.write(&(link.uri().len() as u32).to_le_bytes());
+            hasher.write(link.uri().as_bytes());
+        }
+        None => hasher.write(&[0]),
+    }
+}
+
+/// A row's cells and whether it wraps onto the next row
+type ReflowRow = (Vec<Cell>, bool);
+
+/// Splits a logical line into rows of `width` cells, moving a wide char that
+/// would straddle the edge onto the next row and padding with `blank`.
+/// Returns the rows with their wrapped flag, and the (row, col) that
+/// `offset` into the line lands on.
+fn rewrap(
+    line: &[Cell],
+    width: usize,
+    offset: Option<usize>,
+    blank: &Cell,
+) -> (Vec<ReflowRow>, Option<(usize, usize)>) {
+    let mut rows = Vec::new();
+    let mut row: Vec<Cell> = Vec::with_capacity(width);
+    let mut position = None;
+    for (i, cell) in line.iter().enumerate() {
+        let straddles = cell.wide && width > 1 && row.len() == width - 1;
+        if row.len() == width || straddles {
+            row.resize(width, blank.clone());
+            rows.push((std::mem::take(&mut row), true));
+        }
+        if offset == Some(i) {
+            position = Some((rows.len(), row.len()));
+        }
+        row.push(cell.clone());
+    }
+    if offset.is_some() && position.is_none() {
+        // The cursor sits just past the end of the line
+        if row.len() == width {
+            rows.push((std::mem::take(&mut row), true));
+        }
+        position = Some((rows.len(), row.len()));
+    }
+    rows.push((row, false));
+    (rows, position)
+}
+
+/// What the buffer does with control characters that reach `write_char`,
+/// including those `Command::Print` has no action for (anything but LF, VT,
+/// FF, CR and TAB). `Replace('\u{fffd}')` makes leaked controls visible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlCharPolicy {
+    #[default]
+    Skip,
+    Replace(char),
+}
 
 #[derive(Debug, Clone)]
 pub struct TerminalBuffer {
@@ -12,12 +208,27 @@ pub struct TerminalBuffer {
     cursor_x: usize,
     cursor_y: usize,
     current_style: CellStyle,
+    default_style: CellStyle,
+    /// Palette for the SGR strings the buffer reports and exports
+    theme: Theme,
+    /// OSC 4 overrides of the theme's 16 colors, the ones reports and
+    /// exports map back to an SGR index
+    palette_overrides: [Option<Color>; 16],
     saved_cursor: Option<(usize, usize, CellStyle)>,
     scrollback: VecDeque<Vec<Cell>>,
     scrollback_limit: usize,
+    reflow_line_limit: usize,
+    scrollback_on_clear: bool,
+    word_chars: String,
     scroll_offset: usize,
+    auto_scroll: bool,
+    new_output_while_scrolled: bool,
     alternate_state: Option<Box<AlternateState>>,
     cursor_style: CursorStyle,
+    default_cursor_style: CursorStyle,
+    blink_phase: bool,
+    cursor_visible: bool,
+    pixel_size: (u16, u16),
     bracketed_paste: bool,
     charset: Charset,
     tab_stops: Vec<bool>,
@@ -28,9 +239,209 @@ pub struct TerminalBuffer {
     focus_events: bool,
     origin_mode: bool,
     auto_wrap_mode: bool,
+    /// LNM: when set, LF also returns the cursor to column 0
+    line_feed_mode: bool,
+    /// IRM: printed characters push the rest of the row right
+    insert_mode: bool,
+    /// Mode 2026: the application is mid-frame, so the screen should not be presented
+    synchronized_output: bool,
+    synchronized_since: Option<Instant>,
+    synchronized_output_timeout: Duration,
+    /// DECCKM: cursor keys send SS3 instead of CSI sequences
+    application_cursor_keys: bool,
+    /// DECBKM: the backarrow key sends BS (0x08) instead of DEL
+    backarrow_sends_backspace: bool,
+    /// DECKPAM: keypad keys send `ESC O x` instead of their characters
+    application_keypad: bool,
+    /// XTSAVE slots; like xterm, one saved value per mode
+    saved_private_modes: HashMap<u16, bool>,
+    control_char_policy: ControlCharPolicy,
+    /// DECSCA: cells written while set are protected from selective erase
+    character_protection: bool,
+    /// S8C1T: replies use 8-bit C1 controls (0x9B CSI ...)
+    c1_8bit: bool,
+    /// Reply to ENQ (0x05)
+    answerback: String,
     pending_clipboard: Vec<String>,
 }
 
+/// Printed over a column to leave a spacer in the column after it, then
+/// overwritten
+const DIFF_FILLER: char = '\u{3000}';
+
+/// Whether `row[x + 1]` is the spacer printing the wide char at `x` leaves,
+/// apart from the marks and link it keeps from the cell it replaced
+fn spacer_follows(row: &[Cell], x: usize, columns: usize) -> bool {
+    let (Some(head), Some(spacer)) = (row.get(x), row.get(x + 1)) else {
+        return false;
+    };
+    head.wide
+        && x + 1 < columns
+        && spacer.wide_spacer
+        && !spacer.wide
+        && spacer.character == ' '
+        && spacer.style == head.style
+        && spacer.protected == head.protected
+}
+
+/// The commands [`TerminalBuffer::diff_commands`] builds, with the
+/// receiver's cursor and pen as they stand after them
+struct DiffPrinter {
+    commands: Vec<Command>,
+    text: String,
+    /// `None` once the position is unknown
+    cursor: Option<(usize, usize)>,
+    style: CellStyle,
+    protected: bool,
+    hyperlink: Option<Hyperlink>,
+    started: bool,
+}
+
+impl DiffPrinter {
+    fn flush(&mut self) {
+        if !self.text.is_empty() {
+            self.commands
+                .push(Command::PrintStr(std::mem::take(&mut self.text)));
+        }
+    }
+
+    fn push(&mut self, command: Command) {
+        self.flush();
+        self.commands.push(command);
+    }
+
+    /// Switches off the modes that change what printing does, before the
+    /// first change
+    fn begin(&mut self, buffer: &TerminalBuffer) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        self.push(Command::SetAutoWrapMode(false));
+        if buffer.insert_mode {
+            self.push(Command::SetInsertMode(false));
+        }
+        if buffer.origin_mode {
+            self.push(Command::SetOriginMode(false));
+            self.cursor = None;
+        }
+        if buffer.charset != Charset::Ascii {
+            self.push(Command::SetCharset(Charset::Ascii));
+        }
+    }
+
+    fn move_to(&mut self, x: usize, y: usize) {
+        if self.cursor != Some((x, y)) {
+            self.push(Command::MoveCursor(x, y));
+            self.cursor = Some((x, y));
+        }
+    }
+
+    /// Prints `c` at (`x`, `y`) in `cell`'s pen, followed by its marks
+    fn print(&mut self, x: usize, y: usize, columns: usize, c: char, cell: &Cell) {
+        self.move_to(x, y);
+        if cell.style != self.style {
+            self.push(Command::SetStyle(cell.style));
+            self.style = cell.style;
+        }
+        if cell.protected != self.protected {
+            self.push(Command::SetCharacterProtection(cell.protected));
+            self.protected = cell.protected;
+        }
+        if cell.hyperlink != self.hyperlink {
+            self.push(Command::SetHyperlink(cell.hyperlink.clone()));
+            self.hyperlink = cell.hyperlink.clone();
+        }
+        self.text.push(c);
+        self.text.extend(cell.zerowidth.iter());
+        let span = unicode_width::UnicodeWidthChar::width(c).unwrap_or(1);
+        // With auto-wrap off the last usable column holds the cursor
+        self.cursor = (x + span < columns).then_some((x + span, y));
+    }
+
+    /// Gives a spacer the marks and link of `spacer`, which printing a wide
+    /// char before it doesn't touch. `before` is what the column holds now,
+    /// if known.
+    fn prepare_spacer(
+        &mut self,
+        x: usize,
+        y: usize,
+        columns: usize,
+        spacer: &Cell,
+        before: Option<&Cell>,
+    ) {
+        if before
+            .is_some_and(|c| c.hyperlink == spacer.hyperlink && c.zerowidth == spacer.zerowidth)
+        {
+            return;
+        }
+        let cell = Cell {
+            style: self.style,
+            protected: self.protected,
+            hyperlink: spacer.hyperlink.clone(),
+            zerowidth: spacer.zerowidth.clone(),
+            ..Cell::default()
+        };
+        self.print(x, y, columns, ' ', &cell);
+    }
+
+    /// Prints the target cells starting at column `x`: a wide char with its
+    /// spacer, or a single cell. Returns the column after it if printing
+    /// overwrote that too.
+    fn print_cluster(
+        &mut self,
+        to: &[Cell],
+        x: usize,
+        y: usize,
+        columns: usize,
+        before: Option<&Cell>,
+    ) -> Option<usize> {
+        let cell = &to[x];
+        if spacer_follows(to, x, columns) {
+            self.prepare_spacer(x + 1, y, columns, &to[x + 1], before);
+            self.print(x, y, columns, cell.character, cell);
+            return None;
+        }
+        if cell.wide_spacer && x > 0 {
+            self.prepare_spacer(x, y, columns, cell, before);
+            let head = &to[x - 1];
+            if head.wide {
+                // A wide char writes its spacer in its own pen, so a spacer
+                // in another pen (a double-width row can hide the column
+                // while the wide char is printed) comes out in the wide
+                // char's
+                self.print(x - 1, y, columns, head.character, head);
+                return None;
+            }
+            // A spacer whose wide char was overwritten: print a wide filler
+            // in its pen one column left, then print that column again
+            let filler = Cell {
+                style: cell.style,
+                protected: cell.protected,
+                hyperlink: self.hyperlink.clone(),
+                ..Cell::default()
+            };
+            self.print(x - 1, y, columns, DIFF_FILLER, &filler);
+            let head = if x >= 2 && spacer_follows(to, x - 2, columns) {
+                x - 2
+            } else {
+                x - 1
+            };
+            self.print_cluster(to, head, y, columns, None);
+            return None;
+        }
+        // A spacer in the first column can't be printed; it's sent as a blank
+        let c = if cell.wide_spacer {
+            ' '
+        } else {
+            cell.character
+        };
+        self.print(x, y, columns, c, cell);
+        // A wide char without its spacer still writes one
+        (cell.wide && x + 1 < columns).then_some(x + 1)
+    }
+}
+
 #[derive(Debug, Clone)]
 struct AlternateState {
     grid: Grid,
@@ -41,7 +452,10 @@ struct AlternateState {
 }
 
 impl TerminalBuffer {
+    /// A zero width or height is taken as 1, so the cursor always has a
+    /// cell to sit on
     pub fn new(width: usize, height: usize) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
         let mut tab_stops = vec![false; width];
         for i in (8..width).step_by(8) {
             tab_stops[i] = true;
@@ -52,12 +466,24 @@ impl TerminalBuffer {
             cursor_x: 0,
             cursor_y: 0,
             current_style: CellStyle::default(),
+            default_style: CellStyle::default(),
+            theme: Theme::default(),
+            palette_overrides: [None; 16],
             saved_cursor: None,
             scrollback: VecDeque::new(),
             scrollback_limit: DEFAULT_SCROLLBACK_LIMIT,
+            reflow_line_limit: DEFAULT_REFLOW_LINE_LIMIT,
+            scrollback_on_clear: false,
+            word_chars: DEFAULT_WORD_CHARS.to_string(),
             scroll_offset: 0,
+            auto_scroll: true,
+            new_output_while_scrolled: false,
             alternate_state: None,
             cursor_style: CursorStyle::default(),
+            default_cursor_style: CursorStyle::default(),
+            blink_phase: true,
+            cursor_visible: true,
+            pixel_size: (0, 0),
             bracketed_paste: false,
             charset: Charset::default(),
             tab_stops,
@@ -68,6 +494,19 @@ impl TerminalBuffer {
             focus_events: false,
             origin_mode: false,
             auto_wrap_mode: true,
+            line_feed_mode: false,
+            insert_mode: false,
+            synchronized_output: false,
+            synchronized_since: None,
+            synchronized_output_timeout: DEFAULT_SYNCHRONIZED_OUTPUT_TIMEOUT,
+            application_cursor_keys: false,
+            backarrow_sends_backspace: false,
+            application_keypad: false,
+            saved_private_modes: HashMap::new(),
+            control_char_policy: ControlCharPolicy::default(),
+            character_protection: false,
+            c1_8bit: false,
+            answerback: String::new(),
             pending_clipboard: Vec::new(),
         }
     }
@@ -84,10 +523,199 @@ impl TerminalBuffer {
         (self.cursor_x, self.cursor_y)
     }
 
+    /// Cursor position within the viewport shown at the current
+    /// `scroll_offset`, or `None` if scrolling has pushed it below the view.
+    pub fn viewport_cursor(&self) -> Option<(usize, usize)> {
+        let y = self.cursor_y + self.scroll_offset;
+        (y < self.grid.height()).then_some((self.cursor_x, y))
+    }
+
+    /// Row `y` of the viewport shown at the current `scroll_offset`: history
+    /// rows first, then the top of the screen
+    pub fn viewport_row(&self, y: usize) -> Option<&[Cell]> {
+        let history = self.scrollback.len();
+        let index = history.saturating_sub(self.scroll_offset) + y;
+        if index < history {
+            self.scrollback_row(index)
+        } else {
+            self.grid.row(index - history)
+        }
+    }
+
+    /// Hash of the characters, styles, links and line attributes in the
+    /// viewport, for spotting unchanged frames or comparing screens in
+    /// tests. Stable across runs and platforms; the cursor isn't included,
+    /// so compare [`viewport_cursor`](Self::viewport_cursor) alongside it if
+    /// it matters.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::default();
+        // Scrollback rows keep no line attributes, so they count as single
+        let history = self.scrollback.len().min(self.scroll_offset);
+        for y in 0..self.grid.height() {
+            let attr = match y.checked_sub(history) {
+                Some(row) => self.grid.line_attr(row),
+                None => LineAttr::Single,
+            };
+            hasher.write(&[attr as u8]);
+            for cell in self.viewport_row(y).unwrap_or_default() {
+                hash_cell(&mut hasher, cell);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Text of the logical line under the cursor, soft-wrapped rows joined,
+    /// with trailing whitespace trimmed
+    pub fn current_line_text(&self) -> String {
+        self.line_at(self.cursor_y).1
+    }
+
+    /// The logical line through screen row `y` (clamped to the screen), for
+    /// triple-click selection: the rows it spans, soft-wrapped rows
+    /// joined, and its text with trailing whitespace trimmed
+    pub fn line_at(&self, y: usize) -> (Range<usize>, String) {
+        let y = y.min(self.grid.height() - 1);
+        let mut top = y;
+        while top > 0 && self.grid.is_row_wrapped(top - 1) {
+            top -= 1;
+        }
+        let mut bottom = y;
+        while bottom + 1 < self.grid.height() && self.grid.is_row_wrapped(bottom) {
+            bottom += 1;
+        }
+
+        let mut text = String::new();
+        for row in (top..=bottom).filter_map(|y| self.grid.row(y)) {
+            push_cells(&mut text, row);
+        }
+        text.truncate(text.trim_end().len());
+        (top..bottom + 1, text)
+    }
+
+    /// The word under screen cell (`x`, `y`), for double-click selection:
+    /// its columns on that row and its text. A word is a run of
+    /// alphanumerics and [`word_chars`](Self::word_chars); `None` if the
+    /// cell holds anything else or is off screen.
+    pub fn word_at(&self, x: usize, y: usize) -> Option<(Range<usize>, String)> {
+        let row = self.grid.row(y)?;
+        let is_word = |cell: &Cell| {
+            let c = cell.character;
+            c.is_alphanumeric() || self.word_chars.contains(c)
+        };
+        // A wide character's spacer belongs to the character
+        let base = |x: usize| {
+            if row[x].wide_spacer {
+                x.saturating_sub(1)
+            } else {
+                x
+            }
+        };
+        if x >= row.len() {
+            return None;
+        }
+        let x = base(x);
+        if !is_word(&row[x]) {
+            return None;
+        }
+
+        let mut start = x;
+        while start > 0 && is_word(&row[base(start - 1)]) {
+            start = base(start - 1);
+        }
+        let mut end = x + 1;
+        while end < row.len() && is_word(&row[base(end)]) {
+            end += 1;
+        }
+        let mut text = String::new();
+        push_cells(&mut text, &row[start..end]);
+        Some((start..end, text))
+    }
+
+    /// Characters besides alphanumerics that [`word_at`](Self::word_at)
+    /// counts as part of a word
+    pub fn word_chars(&self) -> &str {
+        &self.word_chars
+    }
+
+    pub fn set_word_chars(&mut self, chars: impl Into<String>) {
+        self.word_chars = chars.into();
+    }
+
     pub fn current_style(&self) -> CellStyle {
         self.current_style
     }
 
+    /// Style applied to subsequent writes, as SGR would set it
+    pub fn set_current_style(&mut self, style: CellStyle) {
+        self.current_style = style;
+    }
+
+    /// The style [`reset_style`](Self::reset_style) and a full reset return to
+    pub fn default_style(&self) -> CellStyle {
+        self.default_style
+    }
+
+    /// Sets the default style. Blank cells, and text in the old default
+    /// colors, switch to the new ones on both screens and in scrollback.
+    pub fn set_default_style(&mut self, style: CellStyle) {
+        let old = std::mem::replace(&mut self.default_style, style);
+        if old == style {
+            return;
+        }
+        let restyle = |current: &mut CellStyle| current.follow_default(&old, &style);
+        restyle(&mut self.current_style);
+        self.grid.set_blank_style(style);
+        let mut scrollback = vec![&mut self.scrollback];
+        if let Some(state) = self.alternate_state.as_mut() {
+            restyle(&mut state.current_style);
+            state.grid.set_blank_style(style);
+            scrollback.push(&mut state.scrollback);
+        }
+        for row in scrollback.into_iter().flatten() {
+            row.iter_mut().for_each(|cell| restyle(&mut cell.style));
+        }
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// The theme with the OSC 4 palette overrides applied, as the parser
+    /// resolves colors
+    fn palette_theme(&self) -> Theme {
+        let mut theme = self.theme;
+        for (color, &set) in theme.ansi.iter_mut().zip(&self.palette_overrides) {
+            if let Some(set) = set {
+                *color = set;
+            }
+        }
+        theme
+    }
+
+    /// Reports and exports SGR colors against `theme`, and makes its
+    /// foreground and background the [default style](Self::set_default_style)
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        self.set_default_style(CellStyle {
+            fg: theme.foreground,
+            bg: theme.background,
+            ..CellStyle::default()
+        });
+    }
+
+    /// Drops all text attributes, like SGR 0, leaving the screen alone
+    pub fn reset_style(&mut self) {
+        self.current_style = self.default_style;
+    }
+
+    pub fn set_foreground(&mut self, color: Color) {
+        self.current_style.fg = color;
+    }
+
+    pub fn set_background(&mut self, color: Color) {
+        self.current_style.bg = color;
+    }
+
     pub fn scrollback_len(&self) -> usize {
         self.scrollback.len()
     }
@@ -96,25 +724,120 @@ impl TerminalBuffer {
         self.scroll_offset
     }
 
+    /// Moves the view `delta` lines into history (negative moves back toward
+    /// the live screen), clamped to `0..=scrollback_len()`.
     pub fn scroll_by(&mut self, delta: i32) {
         let new_offset = (self.scroll_offset as i32 + delta)
             .max(0)
             .min(self.scrollback.len() as i32) as usize;
-        self.scroll_offset = new_offset;
+        self.set_scroll_offset(new_offset);
     }
 
+    /// Sets the view offset, clamped to `scrollback_len()`.
     pub fn scroll_to(&mut self, offset: usize) {
-        self.scroll_offset = offset.min(self.scrollback.len());
+        self.set_scroll_offset(offset.min(self.scrollback.len()));
     }
 
     pub fn scroll_to_bottom(&mut self) {
-        self.scroll_offset = 0;
+        self.set_scroll_offset(0);
+    }
+
+    /// Follow mode: while on, new output snaps the view back to the live
+    /// screen. Scrolling up turns it off; returning to the bottom turns it on.
+    pub fn auto_scroll(&self) -> bool {
+        self.auto_scroll
+    }
+
+    pub fn set_auto_scroll(&mut self, enabled: bool) {
+        self.auto_scroll = enabled;
+    }
+
+    /// True if output arrived while the view was scrolled into history, so
+    /// the UI can offer a "jump to bottom" affordance.
+    pub fn has_new_output_while_scrolled(&self) -> bool {
+        self.new_output_while_scrolled
+    }
+
+    fn set_scroll_offset(&mut self, offset: usize) {
+        if offset > self.scroll_offset {
+            self.auto_scroll = false;
+        } else if offset == 0 {
+            self.auto_scroll = true;
+            self.new_output_while_scrolled = false;
+        }
+        self.scroll_offset = offset;
+    }
+
+    fn note_output(&mut self) {
+        if self.scroll_offset == 0 {
+            return;
+        }
+        if self.auto_scroll {
+            self.scroll_offset = 0;
+        } else {
+            self.new_output_while_scrolled = true;
+        }
+    }
+
+    /// Scrolls one screen height into history (PageUp).
+    pub fn scroll_page_up(&mut self) {
+        self.scroll_by(self.grid.height() as i32);
+    }
+
+    /// Scrolls one screen height back toward the live screen (PageDown).
+    pub fn scroll_page_down(&mut self) {
+        self.scroll_by(-(self.grid.height() as i32));
     }
 
     pub fn scrollback_row(&self, index: usize) -> Option<&[Cell]> {
         self.scrollback.get(index).map(|v| v.as_slice())
     }
 
+    /// The scrollback, oldest row first, in run-length-encoded form for
+    /// snapshots. Pair with [`Grid`]'s own serialization for the screen.
+    pub fn scrollback_snapshot(&self) -> Vec<CellRuns> {
+        self.scrollback
+            .iter()
+            .map(|row| CellRuns::encode(row))
+            .collect()
+    }
+
+    /// Replaces the scrollback with rows from [`Self::scrollback_snapshot`],
+    /// keeping only the newest `scrollback_limit` of them. The view returns
+    /// to the live screen. Fails with [`Error::InvalidSnapshot`], leaving the
+    /// scrollback alone, if a row is wider than the screen.
+    pub fn restore_scrollback(&mut self, rows: Vec<CellRuns>) -> Result<()> {
+        let skip = rows.len().saturating_sub(self.scrollback_limit);
+        let width = self.grid.width();
+        self.scrollback = rows
+            .iter()
+            .skip(skip)
+            .map(|row| row.decode(width).ok_or(Error::InvalidSnapshot))
+            .collect::<Result<_>>()?;
+        self.set_scroll_offset(0);
+        Ok(())
+    }
+
+    /// Writes the scrollback followed by the visible screen, one line per
+    /// row, as plain text or with SGR styling (like `tmux capture-pane -S -`)
+    pub fn export(&self, writer: &mut impl Write, include_styles: bool) -> Result<()> {
+        let theme = self.palette_theme();
+        let screen = (0..self.grid.height()).filter_map(|y| self.grid.row(y));
+        let mut line = String::new();
+        for row in self
+            .scrollback
+            .iter()
+            .map(|row| row.as_slice())
+            .chain(screen)
+        {
+            line.clear();
+            push_row(&mut line, row, include_styles, &theme);
+            line.push('\n');
+            writer.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+
     pub fn set_scrollback_limit(&mut self, limit: usize) {
         self.scrollback_limit = limit;
         while self.scrollback.len() > limit {
@@ -122,14 +845,76 @@ impl TerminalBuffer {
         }
     }
 
+    /// Caps how many cells of soft-wrapped rows reflow joins into one
+    /// logical line. A wrap that would take a line past the cap is kept as a
+    /// hard break, so one enormous line can't make every resize rewrap it
+    /// whole.
+    pub fn set_reflow_line_limit(&mut self, cells: usize) {
+        self.reflow_line_limit = cells;
+    }
+
+    pub fn reflow_line_limit(&self) -> usize {
+        self.reflow_line_limit
+    }
+
+    /// When on, `ClearScreen` (`CSI 2 J`) first scrolls the screen into
+    /// scrollback, down to its last non-blank row, so a `clear` keeps the
+    /// old output in history. Off by default; the alternate screen never
+    /// keeps scrollback either way.
+    pub fn set_scrollback_on_clear(&mut self, enabled: bool) {
+        self.scrollback_on_clear = enabled;
+    }
+
+    pub fn scrollback_on_clear(&self) -> bool {
+        self.scrollback_on_clear
+    }
+
     pub fn is_alternate_screen(&self) -> bool {
         self.alternate_state.is_some()
     }
 
+    /// Size of the text area in pixels as (width, height), for the
+    /// `CSI 14 t` reply. Keep it in step with `Pty::resize_pixels`.
+    pub fn set_pixel_size(&mut self, width: u16, height: u16) {
+        self.pixel_size = (width, height);
+    }
+
+    pub fn pixel_size(&self) -> (u16, u16) {
+        self.pixel_size
+    }
+
+    /// The primary screen saved while the alternate screen is showing, or
+    /// `None` on the primary screen, where [`grid`](Self::grid) is it
+    pub fn primary_grid(&self) -> Option<&Grid> {
+        self.alternate_state.as_ref().map(|state| &state.grid)
+    }
+
     pub fn cursor_style(&self) -> CursorStyle {
         self.cursor_style
     }
 
+    /// The style `CSI 0 SP q` resets to.
+    pub fn default_cursor_style(&self) -> CursorStyle {
+        self.default_cursor_style
+    }
+
+    pub fn set_default_cursor_style(&mut self, style: CursorStyle) {
+        self.default_cursor_style = style;
+    }
+
+    /// Sets the shared blink phase. A frontend toggles this from its blink
+    /// timer; moving the cursor or printing sets it back to on, so the
+    /// cursor stays visible while the user types.
+    pub fn set_blink_phase(&mut self, on: bool) {
+        self.blink_phase = on;
+    }
+
+    /// Whether the cursor should be drawn right now: always for a steady
+    /// style, otherwise only in the "on" half of the blink phase
+    pub fn cursor_blink_on(&self) -> bool {
+        self.blink_phase || !self.cursor_style.is_blinking()
+    }
+
     pub fn is_bracketed_paste(&self) -> bool {
         self.bracketed_paste
     }
@@ -142,59 +927,317 @@ impl TerminalBuffer {
         self.mouse_mode
     }
 
+    /// True between `CSI ? 2026 h` and `CSI ? 2026 l`; renderers should keep
+    /// showing the previous frame until it clears.
+    pub fn is_synchronized_output(&self) -> bool {
+        self.synchronized_output
+    }
+
+    /// True while synchronized output should hold rendering: the mode is on
+    /// and has been for less than the timeout, so a program that dies
+    /// mid-frame can't freeze the screen
+    pub fn is_frame_held(&self) -> bool {
+        self.synchronized_output
+            && self
+                .synchronized_since
+                .is_some_and(|since| since.elapsed() < self.synchronized_output_timeout)
+    }
+
+    /// Longest a synchronized-output frame holds rendering; 150 ms by default
+    pub fn set_synchronized_output_timeout(&mut self, timeout: Duration) {
+        self.synchronized_output_timeout = timeout;
+    }
+
+    pub fn synchronized_output_timeout(&self) -> Duration {
+        self.synchronized_output_timeout
+    }
+
+    pub fn is_application_cursor_keys(&self) -> bool {
+        self.application_cursor_keys
+    }
+
+    pub fn is_application_keypad(&self) -> bool {
+        self.application_keypad
+    }
+
+    /// The byte the Backspace key should send under DECBKM, for
+    /// [`InputHandler::set_backspace_byte`](crate::InputHandler::set_backspace_byte)
+    pub fn backspace_byte(&self) -> u8 {
+        if self.backarrow_sends_backspace {
+            0x08
+        } else {
+            0x7f
+        }
+    }
+
+    /// Current value of a DEC private mode, `None` for modes the buffer
+    /// doesn't track
+    pub fn private_mode(&self, mode: u16) -> Option<bool> {
+        mode_state(PRIVATE_MODES, canonical_private_mode(mode)).map(|state| state(self))
+    }
+
+    /// Every private mode [`private_mode`](Self::private_mode) tracks, with
+    /// its state, in ascending order. Aliases (9 for 1000, 47 and 1047 for
+    /// 1049) are listed once under the main number. ANSI modes have their
+    /// own numbering; see [`active_ansi_modes`](Self::active_ansi_modes).
+    pub fn active_modes(&self) -> Vec<(u16, bool)> {
+        PRIVATE_MODES
+            .iter()
+            .map(|&(mode, state)| (mode, state(self)))
+            .collect()
+    }
+
+    /// State of ANSI mode `mode` (set with `CSI mode h`, no `?`): IRM (4)
+    /// or LNM (20). `None` for modes the buffer doesn't track.
+    pub fn ansi_mode(&self, mode: u16) -> Option<bool> {
+        mode_state(ANSI_MODES, mode).map(|state| state(self))
+    }
+
+    /// Every ANSI mode [`ansi_mode`](Self::ansi_mode) tracks, with its
+    /// state, in ascending order
+    pub fn active_ansi_modes(&self) -> Vec<(u16, bool)> {
+        ANSI_MODES
+            .iter()
+            .map(|&(mode, state)| (mode, state(self)))
+            .collect()
+    }
+
+    /// DECTCEM (mode 25)
+    pub fn is_cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
     pub fn focus_events_enabled(&self) -> bool {
         self.focus_events
     }
 
+    /// Reports a focus change to the application as `CSI I` / `CSI O` when
+    /// it enabled focus reporting (mode 1004); otherwise does nothing.
+    pub fn focus_changed(&mut self, focused: bool) {
+        if self.focus_events {
+            let report: &[u8] = if focused { b"\x1b[I" } else { b"\x1b[O" };
+            self.respond(report);
+        }
+    }
+
+    pub fn is_line_feed_mode(&self) -> bool {
+        self.line_feed_mode
+    }
+
+    /// IRM (ANSI mode 4)
+    pub fn is_insert_mode(&self) -> bool {
+        self.insert_mode
+    }
+
+    pub fn control_char_policy(&self) -> ControlCharPolicy {
+        self.control_char_policy
+    }
+
+    pub fn set_control_char_policy(&mut self, policy: ControlCharPolicy) {
+        self.control_char_policy = policy;
+    }
+
+    pub fn answerback(&self) -> &str {
+        &self.answerback
+    }
+
+    /// Sets the string sent back when the application writes ENQ; empty
+    /// (the default) sends nothing.
+    pub fn set_answerback(&mut self, answerback: &str) {
+        self.answerback = answerback.to_string();
+    }
+
     pub fn drain_content_clipboard(&mut self) -> Vec<String> {
         std::mem::take(&mut self.pending_clipboard)
     }
 
+    pub fn has_pending_responses(&self) -> bool {
+        !self.pending_responses.is_empty()
+    }
+
     pub fn drain_responses(&mut self) -> Vec<Vec<u8>> {
         std::mem::take(&mut self.pending_responses)
     }
 
+    /// Queues a reply. Replies are built with 7-bit `ESC Fe` introducers,
+    /// which are swapped for their 8-bit C1 forms after S8C1T.
+    fn respond(&mut self, reply: impl Into<Vec<u8>>) {
+        let reply = reply.into();
+        if !self.c1_8bit {
+            self.pending_responses.push(reply);
+            return;
+        }
+        let mut out = Vec::with_capacity(reply.len());
+        let mut bytes = reply.iter().copied().peekable();
+        while let Some(b) = bytes.next() {
+            match (b, bytes.peek()) {
+                (0x1b, Some(&fe @ (b'P' | b'[' | b'\\' | b']'))) => {
+                    out.push(fe + 0x40);
+                    bytes.next();
+                }
+                _ => out.push(b),
+            }
+        }
+        self.pending_responses.push(out);
+    }
+
+    /// Executes `cmd` and returns the reply bytes it produced, concatenated,
+    /// instead of queueing them. Replies already pending stay queued for
+    /// [`drain_responses`](Self::drain_responses).
+    pub fn query(&mut self, cmd: Command) -> Result<Vec<u8>> {
+        let queued = self.pending_responses.len();
+        let result = self.execute_command(cmd);
+        let reply = self.pending_responses.split_off(queued).concat();
+        result.map(|_| reply)
+    }
+
+    /// Commands that, applied to this buffer, make its visible screen
+    /// (line attributes included), cursor position and pen (style, protection, hyperlink) match
+    /// `target`'s. Only changed cells are printed; wide chars and their
+    /// spacers are rebuilt as a unit, orphaned halves included. Auto-wrap,
+    /// insert mode, origin mode and the line-drawing charset are switched
+    /// off while printing and restored afterwards; other modes and the
+    /// scrollback are left alone.
+    /// Screens of different sizes are compared over their common area.
+    pub fn diff_commands(&self, target: &TerminalBuffer) -> Vec<Command> {
+        let width = self.grid.width().min(target.grid.width());
+        let height = self.grid.height().min(target.grid.height());
+        let mut printer = DiffPrinter {
+            commands: Vec::new(),
+            text: String::new(),
+            cursor: Some(self.cursor_pos()),
+            style: self.current_style,
+            protected: self.character_protection,
+            hyperlink: self.current_hyperlink.clone(),
+            started: false,
+        };
+        for y in 0..height {
+            let (Some(from), Some(to)) = (self.grid.row(y), target.grid.row(y)) else {
+                continue;
+            };
+            let attr = target.grid.line_attr(y);
+            if self.grid.line_attr(y) != attr {
+                printer.begin(self);
+                printer.move_to(0, y);
+                printer.push(Command::SetLineAttr(attr));
+            }
+            // Cells past the usable half of a double-width row aren't shown
+            let columns = target.grid.row_columns(y).min(width);
+            let mut x = 0;
+            // A cell the last print overwrote although it already matched
+            let mut stale = None;
+            while x < columns {
+                let span = if spacer_follows(to, x, columns) { 2 } else { 1 };
+                if stale != Some(x) && from[x..x + span] == to[x..x + span] {
+                    x += 1;
+                    continue;
+                }
+                printer.begin(self);
+                let spacer = span - 1;
+                stale = printer.print_cluster(to, x, y, columns, Some(&from[x + spacer]));
+                x += span;
+            }
+        }
+        printer.flush();
+
+        let DiffPrinter {
+            mut commands,
+            mut cursor,
+            style,
+            protected,
+            hyperlink,
+            started,
+            ..
+        } = printer;
+        if started {
+            if self.charset != Charset::Ascii {
+                commands.push(Command::SetCharset(self.charset));
+            }
+            if self.insert_mode {
+                commands.push(Command::SetInsertMode(true));
+            }
+            if self.auto_wrap_mode {
+                commands.push(Command::SetAutoWrapMode(true));
+            }
+            if self.origin_mode {
+                // Homes the cursor
+                commands.push(Command::SetOriginMode(true));
+                cursor = None;
+            }
+        }
+        let (x, y) = target.cursor_pos();
+        if cursor != Some((x, y)) {
+            let y = if self.origin_mode {
+                y.saturating_sub(self.home_row())
+            } else {
+                y
+            };
+            commands.push(Command::MoveCursor(x, y));
+        }
+        if target.current_style != style {
+            commands.push(Command::SetStyle(target.current_style));
+        }
+        if target.character_protection != protected {
+            commands.push(Command::SetCharacterProtection(target.character_protection));
+        }
+        if target.current_hyperlink != hyperlink {
+            commands.push(Command::SetHyperlink(target.current_hyperlink.clone()));
+        }
+        commands
+    }
+
     fn translate_char(&self, c: char) -> char {
         if self.charset == Charset::LineDrawing {
-            match c {
-                'j' => '┘',
-                'k' => '┐',
-                'l' => '┌',
-                'm' => '└',
-                'n' => '┼',
-                'q' => '─',
-                't' => '├',
-                'u' => '┤',
-                'v' => '┴',
-                'w' => '┬',
-                'x' => '│',
-                'a' => '▒',
-                _ => c,
-            }
+            translate_line_drawing(c)
         } else {
             c
         }
     }
 
     pub fn write_char(&mut self, c: char) -> Result<()> {
+        self.note_output();
+        self.blink_phase = true;
+
+        if c.is_control() {
+            return match self.control_char_policy {
+                ControlCharPolicy::Replace(replacement) if !replacement.is_control() => {
+                    self.write_char(replacement)
+                }
+                _ => Ok(()),
+            };
+        }
+
         // Check character width
         let char_width = unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
 
         // Zero-width: attach to previous cell
         if char_width == 0 && c != ' ' {
-            let prev_x = if self.cursor_x > 0 {
+            let mut prev_x = if self.cursor_x > 0 {
                 self.cursor_x - 1
             } else {
                 0
             };
+            // After a wide char, the mark belongs to the char, not its spacer
+            if prev_x > 0
+                && self
+                    .grid
+                    .get(prev_x, self.cursor_y)
+                    .is_some_and(|c| c.wide_spacer)
+            {
+                prev_x -= 1;
+            }
             if let Some(cell) = self.grid.get_mut(prev_x, self.cursor_y) {
                 cell.push_zerowidth(c);
             }
             return Ok(());
         }
 
+        self.note_direction(c);
         let translated = self.translate_char(c);
         let is_wide = char_width == 2;
+        if self.insert_mode {
+            self.insert_blanks(char_width);
+        }
 
         // Write the main character
         if let Some(cell) = self.grid.get_mut(self.cursor_x, self.cursor_y) {
@@ -204,36 +1247,145 @@ impl TerminalBuffer {
             cell.zerowidth.clear();
             cell.wide = is_wide;
             cell.wide_spacer = false;
+            cell.protected = self.character_protection;
         }
 
         self.cursor_x += 1;
 
         // For wide chars, add a spacer cell
-        if is_wide && self.cursor_x < self.grid.width() {
+        if is_wide && self.cursor_x < self.grid.row_columns(self.cursor_y) {
             if let Some(cell) = self.grid.get_mut(self.cursor_x, self.cursor_y) {
                 cell.character = ' ';
                 cell.style = self.current_style;
                 cell.wide = false;
                 cell.wide_spacer = true;
+                cell.protected = self.character_protection;
             }
             self.cursor_x += 1;
         }
 
-        // Handle line wrap
-        // Handle line wrap
-        if self.cursor_x >= self.grid.width() {
+        self.wrap_if_needed();
+        Ok(())
+    }
+
+    /// Writes a run of text. Same result as `write_char` per char, but
+    /// printable ASCII is copied into the grid a row segment at a time.
+    pub fn write_str(&mut self, s: &str) -> Result<()> {
+        self.note_output();
+        self.blink_phase = true;
+
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            let run = rest
+                .bytes()
+                .take_while(|b| (0x20..0x7f).contains(b))
+                .count();
+            let width = self.grid.row_columns(self.cursor_y);
+            if run == 0
+                || self.insert_mode
+                || self.cursor_x >= width
+                || self.cursor_y >= self.grid.height()
+            {
+                self.write_char(c)?;
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+
+            let take = run.min(width - self.cursor_x);
+            let (x, y) = (self.cursor_x, self.cursor_y);
+            let style = self.current_style;
+            if self.grid.row_direction(y).is_none() {
+                let strong = rest[..take].chars().find(char::is_ascii_alphabetic);
+                self.note_direction(strong.unwrap_or(c));
+            }
+            let hyperlink = self.current_hyperlink.clone();
+            let line_drawing = self.charset == Charset::LineDrawing;
+            let protected = self.character_protection;
+            if let Some(row) = self.grid.row_mut(y) {
+                for (cell, b) in row[x..x + take].iter_mut().zip(rest.bytes()) {
+                    let c = b as char;
+                    cell.character = if line_drawing {
+                        translate_line_drawing(c)
+                    } else {
+                        c
+                    };
+                    cell.style = style;
+                    cell.hyperlink = hyperlink.clone();
+                    cell.zerowidth.clear();
+                    cell.wide = false;
+                    cell.wide_spacer = false;
+                    cell.protected = protected;
+                }
+            }
+            self.cursor_x += take;
+            self.wrap_if_needed();
+            rest = &rest[take..];
+        }
+        Ok(())
+    }
+
+    /// Sets the cursor row's direction hint from its first strongly
+    /// directional character (rule P2 of UAX #9, per logical line): a row
+    /// continuing a wrapped line takes the direction the line started with.
+    fn note_direction(&mut self, c: char) {
+        let y = self.cursor_y;
+        if self.grid.row_direction(y).is_some() {
+            return;
+        }
+        let inherited = y
+            .checked_sub(1)
+            .filter(|&above| self.grid.is_row_wrapped(above))
+            .and_then(|above| self.grid.row_direction(above));
+        if let Some(direction) = inherited.or_else(|| Direction::of(c)) {
+            self.grid.set_row_direction(y, Some(direction));
+        }
+    }
+
+    fn wrap_if_needed(&mut self) {
+        let columns = self.grid.row_columns(self.cursor_y);
+        if self.cursor_x >= columns {
             if self.auto_wrap_mode {
+                self.grid.set_row_wrapped(self.cursor_y, true);
                 self.cursor_x = 0;
+                self.move_down_or_scroll();
+            } else {
+                self.cursor_x = columns - 1;
+            }
+        }
+    }
+
+    /// LF, VT or FF: a line feed, plus a carriage return under LNM
+    fn line_feed_with_mode(&mut self) {
+        if self.line_feed_mode {
+            self.cursor_x = 0;
+        }
+        self.line_feed();
+    }
+
+    /// Moves the cursor down a row; see [`Self::move_down_or_scroll`]. The
+    /// row being left ends in a real line break, so it's no longer wrapped.
+    fn line_feed(&mut self) {
+        self.grid.set_row_wrapped(self.cursor_y, false);
+        self.move_down_or_scroll();
+    }
+
+    /// Moves the cursor down a row for a line feed or an autowrap. At the
+    /// bottom margin of a scroll region the region scrolls instead; rows
+    /// leaving a region that doesn't span the whole screen are discarded
+    /// rather than kept as scrollback.
+    fn move_down_or_scroll(&mut self) {
+        let last = self.grid.height().saturating_sub(1);
+        match self.scroll_region {
+            Some((top, bottom)) if self.cursor_y == bottom && (top, bottom) != (0, last) => {
+                self.grid.scroll_up_region(top, bottom, 1);
+            }
+            _ => {
                 self.cursor_y += 1;
-                if self.cursor_y >= self.grid.height() {
+                if self.cursor_y > last {
                     self.scroll_up(1);
                 }
-            } else {
-                self.cursor_x = self.grid.width().saturating_sub(1);
             }
         }
-
-        Ok(())
     }
 
     fn advance_to_next_tab_stop(&mut self) {
@@ -247,11 +1399,14 @@ impl TerminalBuffer {
         self.cursor_x = width.saturating_sub(1);
     }
 
+    /// Scrolls the whole screen up by `n`. Rows leaving the top go to
+    /// scrollback, except on the alternate screen, whose history is thrown
+    /// away on exit anyway.
     fn scroll_up(&mut self, n: usize) {
-        let width = self.grid.width();
         let height = self.grid.height();
 
-        for y in 0..n.min(height) {
+        let keep = if self.is_alternate_screen() { 0 } else { n };
+        for y in 0..keep.min(height) {
             if let Some(row) = self.grid.row(y) {
                 self.scrollback.push_back(row.to_vec());
             }
@@ -261,51 +1416,129 @@ impl TerminalBuffer {
             self.scrollback.pop_front();
         }
 
-        for y in n..height {
-            for x in 0..width {
-                if let Some(cell) = self.grid.get(x, y).cloned() {
-                    let _ = self.grid.set(x, y - n, cell);
-                }
-            }
-        }
-
-        for y in (height.saturating_sub(n))..height {
-            for x in 0..width {
-                let _ = self.grid.set(x, y, Cell::default());
-            }
-        }
+        self.grid.scroll_up_region(0, height.saturating_sub(1), n);
 
         self.cursor_y = self.cursor_y.saturating_sub(n);
     }
 
+    /// Shifts the scroll region (or the whole screen) down by `n` rows,
+    /// clamped to the region height. Rows pushed off the bottom are discarded
+    /// and blank rows appear at the top; nothing is restored from scrollback,
+    /// matching xterm's SD.
     fn scroll_down(&mut self, n: usize) {
+        let (top, bottom) = self
+            .scroll_region
+            .unwrap_or((0, self.grid.height().saturating_sub(1)));
+        self.grid.scroll_down_region(top, bottom, n);
+    }
+
+    /// The scroll region's bottom row, if the cursor is inside the region
+    fn bottom_margin_below_cursor(&self) -> Option<usize> {
+        let (top, bottom) = self
+            .scroll_region
+            .unwrap_or((0, self.grid.height().saturating_sub(1)));
+        (top..=bottom).contains(&self.cursor_y).then_some(bottom)
+    }
+
+    /// Shifts the cursor row right from the cursor by `n`, dropping cells
+    /// pushed past the edge, and blanks the `n` cells opened up (ICH, IRM)
+    fn insert_blanks(&mut self, n: usize) {
         let width = self.grid.width();
-        let height = self.grid.height();
+        let n = n.min(width);
+        let y = self.cursor_y;
+        for x in (self.cursor_x..width).rev() {
+            if x + n < width
+                && let Some(cell) = self.grid.get(x, y).cloned()
+            {
+                let _ = self.grid.set(x + n, y, cell);
+            }
+        }
+        for x in self.cursor_x..self.cursor_x.saturating_add(n).min(width) {
+            if let Some(cell) = self.grid.get_mut(x, y) {
+                *cell = Cell::default();
+                cell.style = self.current_style;
+            }
+        }
+    }
+
+    /// Blanks columns `x0..x1` of the cursor row
+    fn erase_row(&mut self, x0: usize, x1: usize) {
+        let y = self.cursor_y;
+        let blank = self.blank_cell();
+        self.grid.fill_region(x0, y, x1, y + 1, blank);
+    }
 
-        for y in (0..(height - n)).rev() {
-            for x in 0..width {
-                if let Some(cell) = self.grid.get(x, y).cloned() {
-                    let _ = self.grid.set(x, y + n, cell);
+    /// Blanks the unprotected cells of columns `x0..x1`, rows `y0..y1`
+    fn selective_erase(&mut self, x0: usize, y0: usize, x1: usize, y1: usize) {
+        let blank = self.blank_cell();
+        let x1 = x1.min(self.grid.width());
+        for y in y0..y1.min(self.grid.height()) {
+            if let Some(row) = self.grid.row_mut(y) {
+                for cell in row.get_mut(x0..x1).unwrap_or_default() {
+                    if !cell.protected {
+                        *cell = blank.clone();
+                    }
                 }
             }
         }
+    }
 
-        for y in 0..n.min(height) {
-            for x in 0..width {
-                let _ = self.grid.set(x, y, Cell::default());
+    /// An erased cell: blank, but keeping the current background (BCE)
+    fn blank_cell(&self) -> Cell {
+        let mut cell = self.grid.blank_cell();
+        cell.style.bg = self.current_style.bg;
+        cell
+    }
+
+    /// Applies `commands` in order, stopping at the first error. Runs of
+    /// printable text are joined and written in one pass rather than
+    /// dispatched one command at a time.
+    pub fn execute_commands(&mut self, commands: impl IntoIterator<Item = Command>) -> Result<()> {
+        self.execute_commands_with(commands, |_| {})
+    }
+
+    /// [`execute_commands`](Self::execute_commands), showing `observe` each
+    /// command other than printable text just before it is applied
+    pub(crate) fn execute_commands_with(
+        &mut self,
+        commands: impl IntoIterator<Item = Command>,
+        mut observe: impl FnMut(&Command),
+    ) -> Result<()> {
+        let mut text = String::new();
+        for cmd in commands {
+            match cmd {
+                Command::Print(c) if !c.is_control() => text.push(c),
+                Command::PrintStr(s) if text.is_empty() => text = s,
+                Command::PrintStr(s) => text.push_str(&s),
+                cmd => {
+                    if !text.is_empty() {
+                        self.write_str(&text)?;
+                        text.clear();
+                    }
+                    observe(&cmd);
+                    self.execute_command(cmd)?;
+                }
             }
         }
+        if !text.is_empty() {
+            self.write_str(&text)?;
+        }
+        Ok(())
     }
 
     pub fn execute_command(&mut self, cmd: Command) -> Result<()> {
+        let cursor = self.cursor_pos();
+        if matches!(
+            cmd,
+            Command::Print(_) | Command::PrintStr(_) | Command::Execute(_)
+        ) {
+            self.note_output();
+        }
+
         match cmd {
             Command::Print(c) => {
-                if c == '\n' {
-                    self.cursor_x = 0;
-                    self.cursor_y += 1;
-                    if self.cursor_y >= self.grid.height() {
-                        self.scroll_up(1);
-                    }
+                if matches!(c, '\n' | '\x0b' | '\x0c') {
+                    self.line_feed_with_mode();
                 } else if c == '\r' {
                     self.cursor_x = 0;
                 } else if c == '\t' {
@@ -314,108 +1547,141 @@ impl TerminalBuffer {
                     self.write_char(c)?;
                 }
             }
+            Command::PrintStr(s) => self.write_str(&s)?,
             Command::Execute(byte) => match byte {
-                b'\n' => {
+                // VT and FF are treated as LF, as in xterm
+                b'\n' | 0x0b | 0x0c => self.line_feed_with_mode(),
+                // NEL
+                0x85 => {
                     self.cursor_x = 0;
-                    self.cursor_y += 1;
-                    if self.cursor_y >= self.grid.height() {
-                        self.scroll_up(1);
-                    }
+                    self.line_feed();
                 }
                 b'\r' => self.cursor_x = 0,
-                b'\t' => {
-                    let tab_stop = 8;
-                    self.cursor_x = ((self.cursor_x / tab_stop) + 1) * tab_stop;
+                b'\t' => self.advance_to_next_tab_stop(),
+                0x08 if self.cursor_x > 0 => {
+                    self.cursor_x -= 1;
                 }
-                0x08 => {
-                    if self.cursor_x > 0 {
-                        self.cursor_x -= 1;
-                    }
+                0x05 if !self.answerback.is_empty() => {
+                    self.pending_responses
+                        .push(self.answerback.as_bytes().to_vec());
                 }
                 _ => {}
             },
             Command::MoveCursor(x, y) => {
                 let (top, bottom) = if self.origin_mode {
                     self.scroll_region
-                        .map(|(t, b)| (t, b))
                         .unwrap_or((0, self.grid.height().saturating_sub(1)))
                 } else {
                     (0, self.grid.height().saturating_sub(1))
                 };
 
-                let real_y = if self.origin_mode { top + y } else { y };
+                let real_y = if self.origin_mode {
+                    top.saturating_add(y)
+                } else {
+                    y
+                };
 
-                self.cursor_x = x.min(self.grid.width().saturating_sub(1));
                 self.cursor_y = real_y
                     .clamp(top, bottom)
                     .min(self.grid.height().saturating_sub(1));
+                self.cursor_x = x.min(self.grid.row_columns(self.cursor_y) - 1);
             }
             Command::MoveCursorRelative(dx, dy) => {
-                self.cursor_x = (self.cursor_x as i32 + dx)
-                    .max(0)
-                    .min(self.grid.width() as i32 - 1) as usize;
-                self.cursor_y = (self.cursor_y as i32 + dy)
-                    .max(0)
-                    .min(self.grid.height() as i32 - 1) as usize;
+                self.cursor_y = (self.cursor_y as i32)
+                    .saturating_add(dy)
+                    .clamp(0, self.grid.height() as i32 - 1)
+                    as usize;
+                let columns = self.grid.row_columns(self.cursor_y);
+                self.cursor_x = (self.cursor_x as i32)
+                    .saturating_add(dx)
+                    .clamp(0, columns as i32 - 1) as usize;
+                // CUB/CUF never leave the cursor inside a grapheme cluster
+                // (a wide char's spacer, the tail of a ZWJ sequence). BS is
+                // left alone: shells step over wide chars one column at a time.
+                let (x, y) = (self.cursor_x, self.cursor_y);
+                if dx < 0 {
+                    self.cursor_x = self.grid.cluster_start(x, y);
+                } else if dx > 0 && self.grid.continues_cluster(x, y) {
+                    let end = self.grid.cluster_end(x, y);
+                    self.cursor_x = end.min(columns - 1);
+                }
             }
             Command::ClearScreen => {
-                self.grid.clear();
+                if self.scrollback_on_clear
+                    && let Some(last) = self.grid.last_nonblank_row()
+                {
+                    self.scroll_up(last + 1);
+                }
+                let (width, height) = (self.grid.width(), self.grid.height());
+                let blank = self.blank_cell();
+                self.grid.fill_region(0, 0, width, height, blank);
+                self.grid.reset_line_attrs(0, height);
                 self.cursor_x = 0;
                 self.cursor_y = 0;
             }
             Command::ClearLine => {
-                for x in 0..self.grid.width() {
-                    let _ = self.grid.set(x, self.cursor_y, Cell::default());
-                }
+                self.erase_row(0, usize::MAX);
             }
             Command::EraseDisplay(mode) => {
                 let width = self.grid.width();
                 let height = self.grid.height();
                 match mode {
+                    // fill_region clamps, so a cursor left past the edge by a
+                    // resize or tab can't index out of bounds
                     0 => {
-                        for x in self.cursor_x..width {
-                            let _ = self.grid.set(x, self.cursor_y, Cell::default());
-                        }
-                        for y in (self.cursor_y + 1)..height {
-                            for x in 0..width {
-                                let _ = self.grid.set(x, y, Cell::default());
-                            }
-                        }
+                        self.erase_row(self.cursor_x, width);
+                        let blank = self.blank_cell();
+                        self.grid
+                            .fill_region(0, self.cursor_y + 1, width, height, blank);
+                        self.grid.reset_line_attrs(self.cursor_y + 1, height);
                     }
                     1 => {
-                        for y in 0..self.cursor_y {
-                            for x in 0..width {
-                                let _ = self.grid.set(x, y, Cell::default());
-                            }
-                        }
-                        for x in 0..=self.cursor_x.min(width.saturating_sub(1)) {
-                            let _ = self.grid.set(x, self.cursor_y, Cell::default());
-                        }
+                        let blank = self.blank_cell();
+                        self.grid.fill_region(0, 0, width, self.cursor_y, blank);
+                        self.grid.reset_line_attrs(0, self.cursor_y);
+                        self.erase_row(0, self.cursor_x.saturating_add(1));
                     }
                     _ => {}
                 }
             }
             Command::EraseLine(mode) => {
                 let width = self.grid.width();
+                match mode {
+                    0 => self.erase_row(self.cursor_x, width),
+                    1 => self.erase_row(0, self.cursor_x.saturating_add(1)),
+                    2 => self.erase_row(0, width),
+                    _ => {}
+                }
+            }
+            Command::SelectiveEraseDisplay(mode) => {
+                let (x, y) = (self.cursor_x, self.cursor_y);
+                let (width, height) = (self.grid.width(), self.grid.height());
                 match mode {
                     0 => {
-                        for x in self.cursor_x..width {
-                            let _ = self.grid.set(x, self.cursor_y, Cell::default());
-                        }
+                        self.selective_erase(x, y, width, y + 1);
+                        self.selective_erase(0, y + 1, width, height);
                     }
                     1 => {
-                        for x in 0..=self.cursor_x.min(width.saturating_sub(1)) {
-                            let _ = self.grid.set(x, self.cursor_y, Cell::default());
-                        }
-                    }
-                    2 => {
-                        for x in 0..width {
-                            let _ = self.grid.set(x, self.cursor_y, Cell::default());
-                        }
+                        self.selective_erase(0, 0, width, y);
+                        self.selective_erase(0, y, x.saturating_add(1), y + 1);
                     }
+                    2 => self.selective_erase(0, 0, width, height),
+                    _ => {}
+                }
+            }
+            Command::SelectiveEraseLine(mode) => {
+                let (x, y) = (self.cursor_x, self.cursor_y);
+                let width = self.grid.width();
+                match mode {
+                    0 => self.selective_erase(x, y, width, y + 1),
+                    1 => self.selective_erase(0, y, x.saturating_add(1), y + 1),
+                    2 => self.selective_erase(0, y, width, y + 1),
                     _ => {}
                 }
             }
+            Command::SetCharacterProtection(enabled) => {
+                self.character_protection = enabled;
+            }
             Command::SetStyle(style) => {
                 self.current_style = style;
             }
@@ -441,27 +1707,35 @@ impl TerminalBuffer {
             Command::ScrollDown(n) => {
                 self.scroll_down(n);
             }
+            // IL/DL shift the rows from the cursor to the bottom margin, and
+            // do nothing outside the scroll region. Counts past the region
+            // just blank it.
             Command::InsertLine(n) => {
-                self.scroll_down(n);
+                if let Some(bottom) = self.bottom_margin_below_cursor() {
+                    self.grid.scroll_down_region(self.cursor_y, bottom, n);
+                }
             }
             Command::DeleteLine(n) => {
-                self.scroll_up(n);
+                if let Some(bottom) = self.bottom_margin_below_cursor() {
+                    self.grid.scroll_up_region(self.cursor_y, bottom, n);
+                }
             }
             Command::EraseChars(n) => {
-                for i in 0..n {
-                    if self.cursor_x + i < self.grid.width() {
-                        let _ = self
-                            .grid
-                            .set(self.cursor_x + i, self.cursor_y, Cell::default());
-                    }
-                }
+                self.erase_row(self.cursor_x, self.cursor_x.saturating_add(n));
             }
             Command::Reset => {
                 self.grid.clear();
                 self.cursor_x = 0;
                 self.cursor_y = 0;
-                self.current_style = CellStyle::default();
+                self.current_style = self.default_style;
                 self.saved_cursor = None;
+                self.synchronized_output = false;
+                self.synchronized_since = None;
+                self.character_protection = false;
+                self.c1_8bit = false;
+                self.cursor_visible = true;
+                self.insert_mode = false;
+                self.line_feed_mode = false;
             }
             Command::EnterAlternateScreen => {
                 self.enter_alternate_screen();
@@ -470,15 +1744,25 @@ impl TerminalBuffer {
                 self.exit_alternate_screen();
             }
             Command::SetTitle(_title) => {}
+            Command::UnknownPrivateMode { .. } => {}
+            Command::SetWorkingDirectory(_path) => {}
             Command::SetCursorStyle(style) => {
                 self.cursor_style = style;
             }
+            Command::ResetCursorStyle => {
+                self.cursor_style = self.default_cursor_style;
+            }
             Command::SetBracketedPaste(enabled) => {
                 self.bracketed_paste = enabled;
             }
             Command::SetCharset(charset) => {
                 self.charset = charset;
             }
+            Command::SetLineAttr(attr) => {
+                self.grid.set_line_attr(self.cursor_y, attr);
+                let columns = self.grid.row_columns(self.cursor_y);
+                self.cursor_x = self.cursor_x.min(columns - 1);
+            }
             Command::SetTabStop => {
                 if self.cursor_x < self.tab_stops.len() {
                     self.tab_stops[self.cursor_x] = true;
@@ -492,13 +1776,32 @@ impl TerminalBuffer {
             Command::ClearAllTabStops => {
                 self.tab_stops.fill(false);
             }
-            Command::ShowCursor | Command::HideCursor => {}
+            Command::ShowCursor => self.cursor_visible = true,
+            Command::HideCursor => self.cursor_visible = false,
             Command::DeviceAttributeQuery => {
-                self.pending_responses.push(b"\x1b[?1;2c".to_vec());
+                let reply = CAPABILITIES.primary_da();
+                self.respond(reply);
+            }
+            Command::SecondaryDeviceAttributeQuery => {
+                let reply = CAPABILITIES.secondary_da();
+                self.respond(reply);
             }
             Command::SetHyperlink(link) => {
                 self.current_hyperlink = link;
             }
+            Command::SetPaletteColor(index, color) => {
+                if let Some(entry) = self.palette_overrides.get_mut(index as usize) {
+                    *entry = Some(color);
+                }
+            }
+            Command::ResetPaletteColor(Some(index)) => {
+                if let Some(entry) = self.palette_overrides.get_mut(index as usize) {
+                    *entry = None;
+                }
+            }
+            Command::ResetPaletteColor(None) => {
+                self.palette_overrides = [None; 16];
+            }
             Command::SetScrollRegion { top, bottom } => {
                 let height = self.grid.height();
                 let actual_bottom = if bottom >= height { height - 1 } else { bottom };
@@ -507,36 +1810,25 @@ impl TerminalBuffer {
                 } else {
                     self.scroll_region = None; // Reset to full screen
                 }
-                // DECSTBM also moves cursor to home
+                // DECSTBM also moves cursor to home, which is the region
+                // top in origin mode
                 self.cursor_x = 0;
-                self.cursor_y = 0;
+                self.cursor_y = self.home_row();
             }
             Command::SetMouseMode(mode) => {
                 self.mouse_mode = mode;
             }
             Command::InsertChars(n) => {
-                // Shift cells right from cursor, inserting blanks
-                let width = self.grid.width();
-                let y = self.cursor_y;
-                for x in (self.cursor_x..width).rev() {
-                    if x + n < width {
-                        if let Some(cell) = self.grid.get(x, y).cloned() {
-                            let _ = self.grid.set(x + n, y, cell);
-                        }
-                    }
-                }
-                // Fill with blanks
-                for x in self.cursor_x..self.cursor_x.saturating_add(n).min(width) {
-                    if let Some(cell) = self.grid.get_mut(x, y) {
-                        *cell = Cell::default();
-                        cell.style = self.current_style;
-                    }
-                }
+                self.insert_blanks(n);
             }
             Command::DeleteChars(n) => {
-                // Shift cells left from cursor, deleting n chars
+                // Shift cells left from cursor, deleting n chars. Clusters
+                // cut by either end are deleted whole.
                 let width = self.grid.width();
                 let y = self.cursor_y;
+                self.cursor_x = self.grid.cluster_start(self.cursor_x, y);
+                let last = (self.cursor_x + n.clamp(1, width) - 1).min(width.saturating_sub(1));
+                let n = self.grid.cluster_end(last, y) - self.cursor_x;
                 for x in self.cursor_x..width {
                     if x + n < width {
                         if let Some(cell) = self.grid.get(x + n, y).cloned() {
@@ -557,7 +1849,20 @@ impl TerminalBuffer {
             Command::CursorPositionReport => {
                 // Send cursor position as \x1b[row;colR (1-indexed)
                 let response = format!("\x1b[{};{}R", self.cursor_y + 1, self.cursor_x + 1);
-                self.pending_responses.push(response.into_bytes());
+                self.respond(response);
+            }
+            Command::ExtendedCursorPositionReport => {
+                // Like CPR, plus the page, which is always 1
+                let response = format!("\x1b[?{};{};1R", self.cursor_y + 1, self.cursor_x + 1);
+                self.respond(response);
+            }
+            Command::PrinterStatusReport => {
+                // Printer not ready
+                self.respond("\x1b[?11n");
+            }
+            Command::TextAreaPixelSizeReport => {
+                let (width, height) = self.pixel_size;
+                self.respond(format!("\x1b[4;{};{}t", height, width));
             }
             Command::SetFocusEvents(enabled) => {
                 // Store focus event reporting state
@@ -568,27 +1873,289 @@ impl TerminalBuffer {
                 self.origin_mode = enabled;
                 // DECOM also moves cursor to home position
                 self.cursor_x = 0;
-                self.cursor_y = if self.origin_mode {
-                    self.scroll_region.map(|(top, _)| top).unwrap_or(0)
-                } else {
-                    0
-                };
+                self.cursor_y = self.home_row();
             }
             Command::SetAutoWrapMode(enabled) => {
                 self.auto_wrap_mode = enabled;
             }
+            Command::SetLineFeedMode(enabled) => {
+                self.line_feed_mode = enabled;
+            }
+            Command::SetInsertMode(enabled) => {
+                self.insert_mode = enabled;
+            }
+            Command::SetSynchronizedOutput(enabled) => {
+                self.synchronized_output = enabled;
+                // A repeated `?2026h` doesn't extend the hold
+                self.synchronized_since = if enabled {
+                    self.synchronized_since.or_else(|| Some(Instant::now()))
+                } else {
+                    None
+                };
+            }
+            Command::SetApplicationCursorKeys(enabled) => {
+                self.application_cursor_keys = enabled;
+            }
+            Command::SetBackarrowKeyMode(enabled) => {
+                self.backarrow_sends_backspace = enabled;
+            }
+            Command::SetApplicationKeypad(enabled) => {
+                self.application_keypad = enabled;
+            }
+            Command::SetC1Transmission(eight_bit) => {
+                self.c1_8bit = eight_bit;
+            }
+            Command::SavePrivateModes(modes) => {
+                for mode in modes {
+                    if let Some(value) = self.private_mode(mode) {
+                        self.saved_private_modes.insert(mode, value);
+                    }
+                }
+            }
+            Command::RestorePrivateModes(modes) => {
+                for mode in modes {
+                    let Some(&value) = self.saved_private_modes.get(&mode) else {
+                        continue;
+                    };
+                    if self.private_mode(mode) != Some(value)
+                        && let Some(cmd) = Command::private_mode(mode, value)
+                    {
+                        self.execute_command(cmd)?;
+                    }
+                }
+            }
             Command::CopyToClipboard(content) => {
                 self.pending_clipboard.push(content);
             }
+            Command::RequestStatusString(request) => {
+                let response = match request.as_str() {
+                    " q" => format!("\x1bP1$r{} q\x1b\\", self.cursor_style.decscusr()),
+                    "m" => format!(
+                        "\x1bP1$r{}m\x1b\\",
+                        self.current_style.sgr_params_with(&self.palette_theme())
+                    ),
+                    "r" => {
+                        let (top, bottom) = self
+                            .scroll_region
+                            .unwrap_or((0, self.grid.height().saturating_sub(1)));
+                        format!("\x1bP1$r{};{}r\x1b\\", top + 1, bottom + 1)
+                    }
+                    _ => "\x1bP0$r\x1b\\".to_string(),
+                };
+                self.respond(response);
+            }
+            Command::QueryCursorColor => {
+                let Color { r, g, b } = self.theme.cursor;
+                self.respond(format!(
+                    "\x1b]12;rgb:{r:02x}{r:02x}/{g:02x}{g:02x}/{b:02x}{b:02x}\x1b\\"
+                ));
+            }
+            Command::RequestTermcap(names) => {
+                // One reply per name, each echoing the name hex-encoded
+                for name in names {
+                    let response = match CAPABILITIES.termcap(&name) {
+                        Some(value) if value.is_empty() => {
+                            format!("\x1bP1+r{}\x1b\\", encode_hex(&name))
+                        }
+                        Some(value) => {
+                            format!("\x1bP1+r{}={}\x1b\\", encode_hex(&name), encode_hex(&value))
+                        }
+                        None => format!("\x1bP0+r{}\x1b\\", encode_hex(&name)),
+                    };
+                    self.respond(response);
+                }
+            }
+        }
+        if self.cursor_pos() != cursor {
+            self.blink_phase = true;
         }
         Ok(())
     }
 
+    /// Resizes the screen. On the primary screen a width change rewraps
+    /// soft-wrapped lines and keeps the cursor on the same character; rows
+    /// that no longer fit above the cursor move into scrollback.
+    /// The alternate screen is only clipped; the primary screen saved behind
+    /// it is resized too, so it fits when the application exits. As with
+    /// [`new`](Self::new), zero dimensions are taken as 1.
     pub fn resize(&mut self, width: usize, height: usize) -> Result<()> {
+        let (width, height) = (width.max(1), height.max(1));
+        match self.alternate_state.take() {
+            Some(mut saved) => {
+                self.clip_screen(width, height);
+                self.swap_screen(&mut saved);
+                self.resize_primary(width, height);
+                self.swap_screen(&mut saved);
+                self.alternate_state = Some(saved);
+            }
+            None => self.resize_primary(width, height),
+        }
+        self.scroll_offset = self.scroll_offset.min(self.scrollback.len());
+        self.fit_scroll_region();
+        Ok(())
+    }
+
+    /// A width change rewraps through [`reflow`](Self::reflow), which
+    /// rebuilds the grid and places rows for the new height itself; only a
+    /// height-only change goes through [`Grid::resize_keep_bottom`].
+    /// `resize` has already clamped both sizes to at least 1.
+    fn resize_primary(&mut self, width: usize, height: usize) {
+        if width != self.grid.width() {
+            self.reflow(width, height);
+        } else {
+            self.resize_keep_bottom(height);
+        }
+    }
+
+    /// A height-only resize of the primary screen: rows above the cursor
+    /// move to scrollback when shrinking and come back when growing
+    fn resize_keep_bottom(&mut self, height: usize) {
+        let mut pulled = 0;
+        let pushed =
+            self.grid
+                .resize_keep_bottom(self.grid.width(), height, self.cursor_y + 1, || {
+                    let row = self.scrollback.pop_back();
+                    pulled += usize::from(row.is_some());
+                    row
+                });
+        let pushed_count = pushed.len();
+        self.scrollback.extend(pushed);
+        while self.scrollback.len() > self.scrollback_limit {
+            self.scrollback.pop_front();
+        }
+        self.cursor_y = (self.cursor_y + pulled)
+            .saturating_sub(pushed_count)
+            .min(height - 1);
+
+        // Keep a scrolled-back view on the same history lines
+        if self.scroll_offset > 0 {
+            self.scroll_offset = (self.scroll_offset + pushed_count).saturating_sub(pulled);
+        }
+    }
+
+    fn clip_screen(&mut self, width: usize, height: usize) {
         self.grid.resize(width, height);
         self.cursor_x = self.cursor_x.min(width.saturating_sub(1));
         self.cursor_y = self.cursor_y.min(height.saturating_sub(1));
-        Ok(())
+    }
+
+    /// Trades the showing screen for the one saved in `state`
+    fn swap_screen(&mut self, state: &mut AlternateState) {
+        std::mem::swap(&mut self.grid, &mut state.grid);
+        std::mem::swap(&mut self.cursor_x, &mut state.cursor_x);
+        std::mem::swap(&mut self.cursor_y, &mut state.cursor_y);
+        std::mem::swap(&mut self.scrollback, &mut state.scrollback);
+    }
+
+    /// Row CUP 1;1 lands on: the region top in origin mode, else 0
+    fn home_row(&self) -> usize {
+        match (self.origin_mode, self.scroll_region) {
+            (true, Some((top, _))) => top,
+            _ => 0,
+        }
+    }
+
+    /// Clamps the scroll region to the grid after a resize, dropping it if
+    /// nothing is left, and keeps an origin-mode cursor inside it
+    fn fit_scroll_region(&mut self) {
+        let last = self.grid.height().saturating_sub(1);
+        self.scroll_region = self
+            .scroll_region
+            .map(|(top, bottom)| (top, bottom.min(last)))
+            .filter(|&(top, bottom)| top < bottom);
+        let (top, bottom) = match (self.origin_mode, self.scroll_region) {
+            (true, Some(region)) => region,
+            _ => (0, last),
+        };
+        self.cursor_y = self.cursor_y.clamp(top, bottom);
+    }
+
+    fn reflow(&mut self, width: usize, height: usize) {
+        // Join soft-wrapped rows back into logical lines, remembering the
+        // cursor as (line, offset into line)
+        let mut lines: Vec<Vec<Cell>> = Vec::new();
+        let mut cursor = (0, 0);
+        let mut continues = false;
+        let row_width = self.grid.width();
+        for y in 0..self.grid.height() {
+            let full = lines
+                .last()
+                .is_some_and(|line| line.len() + row_width > self.reflow_line_limit);
+            if !continues || full {
+                lines.push(Vec::new());
+            }
+            let line_index = lines.len() - 1;
+            let line = &mut lines[line_index];
+            if y == self.cursor_y {
+                cursor = (line_index, line.len() + self.cursor_x);
+            }
+            if let Some(row) = self.grid.row(y) {
+                line.extend_from_slice(row);
+            }
+            continues = self.grid.is_row_wrapped(y);
+        }
+
+        let blank = self.grid.blank_cell();
+        let mut rows: Vec<ReflowRow> = Vec::new();
+        let mut cursor_row = 0;
+        let mut cursor_col = 0;
+        let mut last_content_row = 0;
+        for (i, mut line) in lines.into_iter().enumerate() {
+            let mut len = line
+                .iter()
+                .rposition(|cell| *cell != blank)
+                .map_or(0, |x| x + 1);
+            if i == cursor.0 {
+                len = len.max(cursor.1);
+            }
+            line.truncate(len);
+
+            let first_row = rows.len();
+            let offset = (i == cursor.0).then_some(cursor.1);
+            let (line_rows, position) = rewrap(&line, width, offset, &blank);
+            if let Some((row, col)) = position {
+                cursor_row = first_row + row;
+                cursor_col = col;
+            }
+            if !line.is_empty() {
+                last_content_row = first_row + line_rows.len() - 1;
+            }
+            rows.extend(line_rows);
+        }
+        rows.truncate(last_content_row.max(cursor_row) + 1);
+
+        // Rows above the cursor go to history first; whatever still doesn't
+        // fit below the screen is dropped
+        let pushed = rows.len().saturating_sub(height).min(cursor_row);
+        for (mut cells, _) in rows.drain(..pushed) {
+            cells.resize(width, blank.clone());
+            self.scrollback.push_back(cells);
+        }
+        while self.scrollback.len() > self.scrollback_limit {
+            self.scrollback.pop_front();
+        }
+        rows.truncate(height);
+
+        let mut grid = Grid::with_blank_style(width, height, self.default_style);
+        let mut line_direction = None;
+        for (y, (cells, wrapped)) in rows.into_iter().enumerate() {
+            let direction =
+                line_direction.or_else(|| cells.iter().find_map(|c| Direction::of(c.character)));
+            if let Some(row) = grid.row_mut(y) {
+                row[..cells.len()].clone_from_slice(&cells);
+            }
+            grid.set_row_wrapped(y, wrapped);
+            grid.set_row_direction(y, direction);
+            line_direction = if wrapped { direction } else { None };
+        }
+        self.grid = grid;
+        self.cursor_x = cursor_col.min(width - 1);
+        self.cursor_y = cursor_row - pushed;
+
+        // Keep a scrolled-back view on the same history lines
+        if self.scroll_offset > 0 {
+            self.scroll_offset += pushed;
+        }
     }
 
     pub fn clear(&mut self) {
@@ -605,7 +2172,10 @@ impl TerminalBuffer {
         let height = self.grid.height();
 
         let state = AlternateState {
-            grid: std::mem::replace(&mut self.grid, Grid::new(width, height)),
+            grid: std::mem::replace(
+                &mut self.grid,
+                Grid::with_blank_style(width, height, self.default_style),
+            ),
             cursor_x: self.cursor_x,
             cursor_y: self.cursor_y,
             current_style: self.current_style,
@@ -615,7 +2185,7 @@ impl TerminalBuffer {
         self.alternate_state = Some(Box::new(state));
         self.cursor_x = 0;
         self.cursor_y = 0;
-        self.current_style = CellStyle::default();
+        self.current_style = self.default_style;
     }
 
     pub fn exit_alternate_screen(&mut self) {
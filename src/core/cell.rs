@@ -1,5 +1,7 @@
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
 
 /// RGB Color representation
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -40,9 +42,9 @@ struct HyperlinkInner {
 impl Hyperlink {
     pub fn new(id: Option<&str>, uri: String) -> Self {
         let id = id.map(|s| s.to_string()).unwrap_or_else(|| {
-            use std::sync::atomic::{AtomicU32, Ordering};
+            use ::core::sync::atomic::{AtomicU32, Ordering};
             static COUNTER: AtomicU32 = AtomicU32::new(0);
-            format!("{}_rin", COUNTER.fetch_add(1, Ordering::Relaxed))
+            alloc::format!("{}_rin", COUNTER.fetch_add(1, Ordering::Relaxed))
         });
         Self {
             inner: Arc::new(HyperlinkInner { id, uri }),
@@ -83,6 +85,10 @@ pub struct CellStyle {
     pub strikethrough: bool,
     pub dim: bool,
     pub hidden: bool,
+    /// Font selected with SGR 10-19: 0 is the primary font, 1-9 the
+    /// alternates, for renderers with more than one face
+    #[serde(default)]
+    pub font: u8,
 }
 
 impl Default for CellStyle {
@@ -98,6 +104,20 @@ impl Default for CellStyle {
             strikethrough: false,
             dim: false,
             hidden: false,
+            font: 0,
+        }
+    }
+}
+
+impl CellStyle {
+    /// Swaps a foreground or background that is `old`'s for `new`'s, for
+    /// moving text in the default colors over to a new theme
+    pub fn follow_default(&mut self, old: &CellStyle, new: &CellStyle) {
+        if self.fg == old.fg {
+            self.fg = new.fg;
+        }
+        if self.bg == old.bg {
+            self.bg = new.bg;
         }
     }
 }
@@ -116,6 +136,9 @@ pub struct Cell {
     pub wide: bool,
     /// True if this is a spacer cell following a wide character
     pub wide_spacer: bool,
+    /// Written under DECSCA; selective erase (DECSED/DECSEL) skips it
+    #[serde(default)]
+    pub protected: bool,
 }
 
 impl Default for Cell {
@@ -127,6 +150,7 @@ impl Default for Cell {
             zerowidth: Vec::new(),
             wide: false,
             wide_spacer: false,
+            protected: false,
         }
     }
 }
@@ -140,6 +164,7 @@ impl Cell {
             zerowidth: Vec::new(),
             wide: false,
             wide_spacer: false,
+            protected: false,
         }
     }
 
@@ -1,26 +1,256 @@
-use super::cell::Cell;
-use anyhow::Result;
+use super::cell::{Cell, CellStyle};
+use crate::error::{Error, Result};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+/// Splits `row` into maximal spans of cells sharing a style
+pub fn style_runs(row: &[Cell]) -> Vec<(Range<usize>, CellStyle)> {
+    let mut runs: Vec<(Range<usize>, CellStyle)> = Vec::new();
+    for (x, cell) in row.iter().enumerate() {
+        match runs.last_mut() {
+            Some((range, style)) if *style == cell.style => range.end = x + 1,
+            _ => runs.push((x..x + 1, cell.style)),
+        }
+    }
+    runs
+}
+
+/// Cells stored as runs of identical neighbours, each kept once with its
+/// repeat count. Blank stretches collapse to a single entry, so snapshots
+/// of a mostly empty screen or scrollback stay small.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CellRuns(Vec<(u32, Cell)>);
+
+impl CellRuns {
+    pub fn encode(cells: &[Cell]) -> Self {
+        let mut runs: Vec<(u32, Cell)> = Vec::new();
+        for cell in cells {
+            match runs.last_mut() {
+                Some((count, run)) if run == cell => *count += 1,
+                _ => runs.push((1, cell.clone())),
+            }
+        }
+        Self(runs)
+    }
+
+    /// The cells, or `None` if there are more than `max` of them. The count
+    /// is checked before allocating, so corrupt input can't ask for an
+    /// enormous buffer.
+    pub fn decode(&self, max: usize) -> Option<Vec<Cell>> {
+        let len = self.len().filter(|&len| len <= max)?;
+        let mut cells = Vec::with_capacity(len);
+        for (count, cell) in &self.0 {
+            cells.extend(core::iter::repeat_n(cell, *count as usize).cloned());
+        }
+        Some(cells)
+    }
+
+    /// Number of cells once decoded, or `None` if that overflows `usize`
+    pub fn len(&self) -> Option<usize> {
+        self.0
+            .iter()
+            .try_fold(0usize, |len, (count, _)| len.checked_add(*count as usize))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Number of runs
+    pub fn runs(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Largest width or height a snapshot may claim
+const MAX_SNAPSHOT_SIDE: usize = u16::MAX as usize;
+
+/// Most cells a snapshot may claim in all. The sides alone still allow
+/// billions of cells, so a tiny snapshot could ask for gigabytes.
+const MAX_SNAPSHOT_CELLS: usize = 1 << 21;
+
+/// Serialized form of a [`Grid`]
+#[derive(Serialize, Deserialize)]
+struct GridSnapshot {
+    width: usize,
+    height: usize,
+    cells: CellRuns,
+    wrapped: Vec<bool>,
+    direction: Vec<Option<Direction>>,
+    blank_style: CellStyle,
+    line_attrs: Vec<LineAttr>,
+}
+
+impl From<Grid> for GridSnapshot {
+    fn from(grid: Grid) -> Self {
+        Self {
+            width: grid.width,
+            height: grid.height,
+            cells: CellRuns::encode(&grid.cells),
+            wrapped: grid.wrapped,
+            direction: grid.direction,
+            blank_style: grid.blank_style,
+            line_attrs: grid.line_attrs,
+        }
+    }
+}
+
+impl TryFrom<GridSnapshot> for Grid {
+    type Error = Error;
+
+    fn try_from(snapshot: GridSnapshot) -> Result<Self> {
+        let GridSnapshot {
+            width,
+            height,
+            cells,
+            wrapped,
+            direction,
+            blank_style,
+            line_attrs,
+        } = snapshot;
+        if !(1..=MAX_SNAPSHOT_SIDE).contains(&width) || !(1..=MAX_SNAPSHOT_SIDE).contains(&height) {
+            return Err(Error::InvalidSnapshot);
+        }
+        let size = width * height;
+        if size > MAX_SNAPSHOT_CELLS || cells.len() != Some(size) {
+            return Err(Error::InvalidSnapshot);
+        }
+        if wrapped.len() != height || direction.len() != height || line_attrs.len() != height {
+            return Err(Error::InvalidSnapshot);
+        }
+        let cells = cells.decode(size).ok_or(Error::InvalidSnapshot)?;
+        Ok(Self {
+            cells,
+            dirty_rows: vec![true; height],
+            wrapped,
+            direction,
+            line_attrs,
+            blank_style,
+            width,
+            height,
+        })
+    }
+}
+
+/// Base direction of a line of text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+impl Direction {
+    /// The strong direction of `c`, or `None` for neutral characters such
+    /// as digits, punctuation, spaces and combining marks. Only the major
+    /// right-to-left scripts are recognised, not the full bidi tables.
+    pub fn of(c: char) -> Option<Self> {
+        if !c.is_alphabetic() {
+            return None;
+        }
+        let rtl = matches!(c as u32,
+            0x0590..=0x08FF // Hebrew, Arabic, Syriac, Thaana, NKo and friends
+            | 0xFB1D..=0xFDFF // Hebrew and Arabic presentation forms A
+            | 0xFE70..=0xFEFF // Arabic presentation forms B
+            | 0x10800..=0x10FFF
+            | 0x1E800..=0x1EFFF);
+        Some(if rtl { Direction::Rtl } else { Direction::Ltr })
+    }
+}
+
+/// DEC line attribute of a row, set by `ESC # 3` to `ESC # 6`. Every
+/// attribute but `Single` doubles the width of the row's cells, so only
+/// the left half of the columns is usable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LineAttr {
+    #[default]
+    Single,
+    DoubleWidth,
+    /// Top half of a double-height line (DECDHL)
+    DoubleHeightTop,
+    /// Bottom half of a double-height line (DECDHL)
+    DoubleHeightBottom,
+}
+
+impl LineAttr {
+    pub fn is_double_width(self) -> bool {
+        self != LineAttr::Single
+    }
+}
+
+/// Serializes as [`CellRuns`] rather than cell by cell; every row comes
+/// back dirty.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "GridSnapshot", try_from = "GridSnapshot")]
 pub struct Grid {
     cells: Vec<Cell>,
     dirty_rows: Vec<bool>,
+    /// Rows that continue onto the next one because auto-wrap ran past
+    /// the last column, as opposed to ending in a line feed
+    wrapped: Vec<bool>,
+    /// Per-row direction hint, `None` until a strongly directional
+    /// character lands on the row
+    direction: Vec<Option<Direction>>,
+    line_attrs: Vec<LineAttr>,
+    /// Style of the blank cells clearing, resizing and scrolling leave
+    blank_style: CellStyle,
     width: usize,
     height: usize,
 }
 
 impl Grid {
     pub fn new(width: usize, height: usize) -> Self {
-        let cells = vec![Cell::default(); width * height];
+        Self::with_blank_style(width, height, CellStyle::default())
+    }
+
+    /// A grid whose blank cells, including the initial ones, are in `style`
+    pub fn with_blank_style(width: usize, height: usize, style: CellStyle) -> Self {
+        let blank = Cell {
+            style,
+            ..Cell::default()
+        };
+        let cells = vec![blank; width * height];
         let dirty_rows = vec![true; height]; // All rows dirty initially
         Self {
             cells,
             dirty_rows,
+            wrapped: vec![false; height],
+            direction: vec![None; height],
+            line_attrs: vec![LineAttr::Single; height],
+            blank_style: style,
             width,
             height,
         }
     }
 
+    pub fn blank_style(&self) -> CellStyle {
+        self.blank_style
+    }
+
+    /// Switches blank cells to `style`. Any cell, written text included,
+    /// whose foreground or background is the old blank one takes the new
+    /// one, so a theme change recolors everything in the default colors.
+    pub fn set_blank_style(&mut self, style: CellStyle) {
+        let old = core::mem::replace(&mut self.blank_style, style);
+        if old == style {
+            return;
+        }
+        for cell in &mut self.cells {
+            cell.style.follow_default(&old, &style);
+        }
+        self.dirty_rows.fill(true);
+    }
+
+    /// A blank cell in the grid's [`blank_style`](Self::blank_style)
+    pub fn blank_cell(&self) -> Cell {
+        Cell {
+            style: self.blank_style,
+            ..Cell::default()
+        }
+    }
+
     pub fn width(&self) -> usize {
         self.width
     }
@@ -47,7 +277,7 @@ impl Grid {
 
     pub fn set(&mut self, x: usize, y: usize, cell: Cell) -> Result<()> {
         if x >= self.width || y >= self.height {
-            anyhow::bail!("Position out of bounds: ({}, {})", x, y);
+            return Err(Error::OutOfBounds { x, y });
         }
         let idx = y * self.width + x;
         self.cells[idx] = cell;
@@ -56,12 +286,16 @@ impl Grid {
     }
 
     pub fn clear(&mut self) {
-        self.cells.fill(Cell::default());
+        let blank = self.blank_cell();
+        self.cells.fill(blank);
         self.dirty_rows.fill(true);
+        self.wrapped.fill(false);
+        self.direction.fill(None);
+        self.line_attrs.fill(LineAttr::Single);
     }
 
     pub fn resize(&mut self, new_width: usize, new_height: usize) {
-        let mut new_cells = vec![Cell::default(); new_width * new_height];
+        let mut new_cells = vec![self.blank_cell(); new_width * new_height];
 
         let copy_width = self.width.min(new_width);
         let copy_height = self.height.min(new_height);
@@ -76,10 +310,64 @@ impl Grid {
 
         self.cells = new_cells;
         self.dirty_rows = vec![true; new_height];
+        self.wrapped.resize(new_height, false);
+        self.direction.resize(new_height, None);
+        self.line_attrs.resize(new_height, LineAttr::Single);
         self.width = new_width;
         self.height = new_height;
     }
 
+    /// Resizes keeping the bottom of the content in place rather than the
+    /// top, so a prompt stays on screen. Shrinking first drops blank rows
+    /// below both the last occupied row and the first `min_rows` rows, then
+    /// moves rows off the top; those are returned, oldest first, for the
+    /// caller's scrollback. Growing pulls up to one row per added line back
+    /// in at the top from `pull`, newest first, until it returns `None`.
+    /// Columns are clipped or padded, not rewrapped.
+    pub fn resize_keep_bottom(
+        &mut self,
+        new_width: usize,
+        new_height: usize,
+        min_rows: usize,
+        mut pull: impl FnMut() -> Option<Vec<Cell>>,
+    ) -> Vec<Vec<Cell>> {
+        let used = self
+            .last_nonblank_row()
+            .map_or(0, |y| y + 1)
+            .max(min_rows)
+            .min(self.height);
+        let dropped = used.saturating_sub(new_height);
+        let pushed: Vec<Vec<Cell>> = (0..dropped)
+            .filter_map(|y| self.row(y).map(<[Cell]>::to_vec))
+            .collect();
+        let mut pulled = Vec::new();
+        while pulled.len() < new_height.saturating_sub(self.height) {
+            match pull() {
+                Some(row) => pulled.push(row),
+                None => break,
+            }
+        }
+
+        let mut grid = Grid::with_blank_style(new_width, new_height, self.blank_style);
+        let copy_width = self.width.min(new_width);
+        for (y, cells) in pulled.iter().rev().enumerate() {
+            let n = cells.len().min(new_width);
+            if let Some(row) = grid.row_mut(y) {
+                row[..n].clone_from_slice(&cells[..n]);
+            }
+        }
+        for (y, old_y) in (pulled.len()..new_height).zip(dropped..self.height) {
+            if let (Some(row), Some(old)) = (grid.row_mut(y), self.row(old_y)) {
+                row[..copy_width].clone_from_slice(&old[..copy_width]);
+            }
+            grid.wrapped[y] = self.wrapped[old_y];
+            grid.direction[y] = self.direction[old_y];
+            grid.line_attrs[y] = self.line_attrs[old_y];
+        }
+        *self = grid;
+        pushed
+    }
+
     pub fn row(&self, y: usize) -> Option<&[Cell]> {
         if y >= self.height {
             return None;
@@ -89,6 +377,206 @@ impl Grid {
         Some(&self.cells[start..end])
     }
 
+    /// An owned copy of every row, top to bottom, for handing a whole
+    /// frame across a boundary (JNI, IPC) in one go
+    pub fn snapshot_rows(&self) -> Vec<Vec<Cell>> {
+        (0..self.height)
+            .filter_map(|y| self.row(y).map(<[Cell]>::to_vec))
+            .collect()
+    }
+
+    /// First and last occupied column of row `y`, or `None` if the whole row
+    /// is blank. A space counts as occupied if its style differs from the
+    /// blank style, e.g. a background color.
+    pub fn row_extent(&self, y: usize) -> Option<(usize, usize)> {
+        let row = self.row(y)?;
+        let occupied = |cell: &Cell| {
+            cell.character != ' ' || cell.style != self.blank_style || !cell.zerowidth.is_empty()
+        };
+        let first = row.iter().position(occupied)?;
+        let last = row.iter().rposition(occupied)?;
+        Some((first, last))
+    }
+
+    /// True if the cell at (`x`, `y`) continues the grapheme cluster to its
+    /// left: the spacer half of a wide char, or a char joined to the
+    /// previous one by a ZWJ. Combining marks never start a cell, so they
+    /// need no check here.
+    pub fn continues_cluster(&self, x: usize, y: usize) -> bool {
+        let Some(cell) = self.get(x, y) else {
+            return false;
+        };
+        if x == 0 {
+            return false;
+        }
+        if cell.wide_spacer {
+            return true;
+        }
+        let mut prev = x - 1;
+        if prev > 0 && self.get(prev, y).is_some_and(|c| c.wide_spacer) {
+            prev -= 1;
+        }
+        self.get(prev, y)
+            .is_some_and(|c| c.zerowidth.last() == Some(&'\u{200D}'))
+    }
+
+    /// Column where the grapheme cluster covering (`x`, `y`) starts
+    pub fn cluster_start(&self, x: usize, y: usize) -> usize {
+        let mut x = x.min(self.width.saturating_sub(1));
+        while self.continues_cluster(x, y) {
+            x -= 1;
+        }
+        x
+    }
+
+    /// Column just past the grapheme cluster covering (`x`, `y`)
+    pub fn cluster_end(&self, x: usize, y: usize) -> usize {
+        let mut end = x + 1;
+        while end < self.width && self.continues_cluster(end, y) {
+            end += 1;
+        }
+        end.min(self.width)
+    }
+
+    /// Contiguous same-style spans of row `y`, left to right. Empty if `y`
+    /// is out of bounds.
+    pub fn style_runs(&self, y: usize) -> Vec<(Range<usize>, CellStyle)> {
+        self.row(y).map(style_runs).unwrap_or_default()
+    }
+
+    /// Index of the lowest row with any occupied cell, as defined by
+    /// [`row_extent`](Self::row_extent), or `None` if the grid is blank
+    pub fn last_nonblank_row(&self) -> Option<usize> {
+        (0..self.height)
+            .rev()
+            .find(|&y| self.row_extent(y).is_some())
+    }
+
+    /// Fills the rectangle of columns `x0..x1` and rows `y0..y1` (end
+    /// exclusive, clamped to the grid) with `cell`. Line attributes are
+    /// left alone; see [`reset_line_attrs`](Self::reset_line_attrs).
+    pub fn fill_region(&mut self, x0: usize, y0: usize, x1: usize, y1: usize, cell: Cell) {
+        let x1 = x1.min(self.width);
+        let y1 = y1.min(self.height);
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+        if x0 == 0 && x1 == self.width {
+            self.cells[y0 * self.width..y1 * self.width].fill(cell);
+            self.wrapped[y0..y1].fill(false);
+            self.direction[y0..y1].fill(None);
+        } else {
+            for y in y0..y1 {
+                let start = y * self.width;
+                self.cells[start + x0..start + x1].fill(cell.clone());
+            }
+        }
+        self.dirty_rows[y0..y1].fill(true);
+    }
+
+    /// Moves rows `top..=bottom` up by `n` in place; the `n` rows at the
+    /// bottom of the region become blank. Out-of-range bounds are clamped.
+    pub fn scroll_up_region(&mut self, top: usize, bottom: usize, n: usize) {
+        let Some(bottom) = self.clamp_region(top, bottom) else {
+            return;
+        };
+        let n = n.min(bottom + 1 - top);
+        self.cells[top * self.width..(bottom + 1) * self.width].rotate_left(n * self.width);
+        self.wrapped[top..=bottom].rotate_left(n);
+        self.direction[top..=bottom].rotate_left(n);
+        self.line_attrs[top..=bottom].rotate_left(n);
+        self.fill_region(0, bottom + 1 - n, self.width, bottom + 1, self.blank_cell());
+        self.reset_line_attrs(bottom + 1 - n, bottom + 1);
+        self.dirty_rows[top..=bottom].fill(true);
+    }
+
+    /// Moves rows `top..=bottom` down by `n` in place; the `n` rows at the
+    /// top of the region become blank. Out-of-range bounds are clamped.
+    pub fn scroll_down_region(&mut self, top: usize, bottom: usize, n: usize) {
+        let Some(bottom) = self.clamp_region(top, bottom) else {
+            return;
+        };
+        let n = n.min(bottom + 1 - top);
+        self.cells[top * self.width..(bottom + 1) * self.width].rotate_right(n * self.width);
+        self.wrapped[top..=bottom].rotate_right(n);
+        self.direction[top..=bottom].rotate_right(n);
+        self.line_attrs[top..=bottom].rotate_right(n);
+        self.fill_region(0, top, self.width, top + n, self.blank_cell());
+        self.reset_line_attrs(top, top + n);
+        self.dirty_rows[top..=bottom].fill(true);
+    }
+
+    /// Clamps `bottom` to the grid, `None` if the region is empty
+    fn clamp_region(&self, top: usize, bottom: usize) -> Option<usize> {
+        let bottom = bottom.min(self.height.checked_sub(1)?);
+        (top <= bottom).then_some(bottom)
+    }
+
+    /// Mutable access to a whole row; marks it dirty
+    pub fn row_mut(&mut self, y: usize) -> Option<&mut [Cell]> {
+        if y >= self.height {
+            return None;
+        }
+        self.dirty_rows[y] = true;
+        let start = y * self.width;
+        Some(&mut self.cells[start..start + self.width])
+    }
+
+    pub fn is_row_wrapped(&self, y: usize) -> bool {
+        self.wrapped.get(y).copied().unwrap_or(false)
+    }
+
+    pub fn set_row_wrapped(&mut self, y: usize, wrapped: bool) {
+        if y < self.height {
+            self.wrapped[y] = wrapped;
+        }
+    }
+
+    pub fn row_direction(&self, y: usize) -> Option<Direction> {
+        self.direction.get(y).copied().flatten()
+    }
+
+    pub fn set_row_direction(&mut self, y: usize, direction: Option<Direction>) {
+        if y < self.height {
+            self.direction[y] = direction;
+        }
+    }
+
+    pub fn line_attr(&self, y: usize) -> LineAttr {
+        self.line_attrs.get(y).copied().unwrap_or_default()
+    }
+
+    pub fn set_line_attr(&mut self, y: usize, attr: LineAttr) {
+        if y < self.height && self.line_attrs[y] != attr {
+            self.line_attrs[y] = attr;
+            self.dirty_rows[y] = true;
+        }
+    }
+
+    /// Sets rows `y0..y1` (end exclusive, clamped) back to single width and
+    /// height, as when they are erased whole
+    pub fn reset_line_attrs(&mut self, y0: usize, y1: usize) {
+        for y in y0..y1.min(self.height) {
+            self.set_line_attr(y, LineAttr::Single);
+        }
+    }
+
+    /// Columns usable on row `y`: the left half of the grid on
+    /// double-width rows, at least one
+    pub fn row_columns(&self, y: usize) -> usize {
+        if self.line_attr(y).is_double_width() {
+            (self.width / 2).max(1)
+        } else {
+            self.width
+        }
+    }
+
+    /// True if row `y` should be laid out right to left: right-aligned,
+    /// with its visual run reversed
+    pub fn is_rtl(&self, y: usize) -> bool {
+        self.row_direction(y) == Some(Direction::Rtl)
+    }
+
     pub fn is_row_dirty(&self, y: usize) -> bool {
         self.dirty_rows.get(y).copied().unwrap_or(false)
     }
@@ -106,6 +594,22 @@ impl Grid {
     pub fn clear_dirty(&mut self) {
         self.dirty_rows.fill(false);
     }
+
+    /// Clears the dirty flags and hands back the old ones, so a caller can
+    /// see which rows a batch of changes touches on its own, then put them
+    /// back with [`merge_dirty`](Self::merge_dirty)
+    #[cfg(feature = "std")]
+    pub(crate) fn take_dirty(&mut self) -> Vec<bool> {
+        core::mem::replace(&mut self.dirty_rows, vec![false; self.height])
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn merge_dirty(&mut self, rows: &[bool]) {
+        for (dirty, &was) in self.dirty_rows.iter_mut().zip(rows) {
+            *dirty |= was;
+        }
+    }
+
     pub fn has_dirty_rows(&self) -> bool {
         self.dirty_rows.iter().any(|&d| d)
     }
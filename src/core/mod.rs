@@ -1,7 +1,12 @@
+#[cfg(feature = "std")]
 pub mod buffer;
 pub mod cell;
 pub mod grid;
 
-pub use buffer::TerminalBuffer;
+#[cfg(test)]
+mod tests;
+
+#[cfg(feature = "std")]
+pub use buffer::{ControlCharPolicy, TerminalBuffer};
 pub use cell::{Cell, CellStyle, Color, Hyperlink, UnderlineStyle};
-pub use grid::Grid;
+pub use grid::{CellRuns, Direction, Grid, LineAttr};
@@ -0,0 +1,364 @@
+// Built with and without the `std` feature. The no_std core is checked with
+// `cargo check-no-std` and these tests run against it with `cargo test-no-std`
+// (aliases in .cargo/config.toml). A bare `cargo test --no-default-features`
+// doesn't link: the cdylib and staticlib targets need std.
+
+#[cfg(test)]
+mod no_std_grid_tests {
+    use crate::core::{Cell, CellStyle, Color, Grid};
+    use crate::error::Error;
+
+    #[test]
+    fn test_grid_set_get() {
+        let mut grid = Grid::new(4, 3);
+        let style = CellStyle {
+            fg: Color::RED,
+            ..CellStyle::default()
+        };
+        grid.set(1, 2, Cell::new('x').with_style(style)).unwrap();
+
+        let cell = grid.get(1, 2).unwrap();
+        assert_eq!(cell.character, 'x');
+        assert_eq!(cell.style.fg, Color::RED);
+        assert!(grid.get(4, 0).is_none());
+    }
+
+    #[test]
+    fn test_grid_set_out_of_bounds() {
+        let mut grid = Grid::new(4, 3);
+        assert!(matches!(
+            grid.set(4, 0, Cell::new('x')),
+            Err(Error::OutOfBounds { x: 4, y: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_grid_resize_and_clear() {
+        let mut grid = Grid::new(4, 3);
+        grid.set(0, 0, Cell::new('a')).unwrap();
+        grid.set(3, 2, Cell::new('b')).unwrap();
+
+        grid.resize(2, 2);
+        assert_eq!(grid.row(0).unwrap()[0].character, 'a');
+        assert!(grid.get(3, 2).is_none());
+
+        grid.clear_dirty();
+        grid.clear();
+        assert!(grid.has_dirty_rows());
+        assert_eq!(grid.get(0, 0).unwrap().character, ' ');
+    }
+}
+
+#[cfg(test)]
+mod grid_scroll_region_tests {
+    use crate::core::{Cell, Grid};
+
+    #[test]
+    fn test_wrapped_flags_move_with_rows() {
+        let mut grid = Grid::new(3, 4);
+        grid.set_row_wrapped(1, true);
+        grid.scroll_up_region(0, 3, 1);
+        assert!(grid.is_row_wrapped(0));
+        assert!(!grid.is_row_wrapped(1));
+
+        grid.scroll_down_region(0, 3, 2);
+        assert!(grid.is_row_wrapped(2));
+        assert!(!grid.is_row_wrapped(0));
+
+        grid.fill_region(0, 2, 3, 3, Cell::default());
+        assert!(!grid.is_row_wrapped(2));
+    }
+
+    fn numbered_grid(width: usize, height: usize) -> Grid {
+        let mut grid = Grid::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = char::from(b'a' + ((y * width + x) % 26) as u8);
+                grid.set(x, y, Cell::new(c)).unwrap();
+            }
+        }
+        grid
+    }
+
+    /// The per-cell copy the buffer used before the in-place methods
+    fn naive_scroll(grid: &mut Grid, top: usize, bottom: usize, n: usize, up: bool) {
+        let width = grid.width();
+        let n = n.min(bottom - top + 1);
+        let rows: Vec<usize> = if up {
+            (top..bottom + 1 - n).collect()
+        } else {
+            (top + n..=bottom).rev().collect()
+        };
+        for y in rows {
+            let src = if up { y + n } else { y - n };
+            for x in 0..width {
+                let cell = grid.get(x, src).unwrap().clone();
+                grid.set(x, y, cell).unwrap();
+            }
+        }
+        let blank = if up {
+            bottom + 1 - n..bottom + 1
+        } else {
+            top..top + n
+        };
+        for y in blank {
+            for x in 0..width {
+                grid.set(x, y, Cell::default()).unwrap();
+            }
+        }
+    }
+
+    fn assert_same(a: &Grid, b: &Grid) {
+        for y in 0..a.height() {
+            assert_eq!(a.row(y), b.row(y), "row {}", y);
+        }
+    }
+
+    #[test]
+    fn test_scroll_regions_match_naive_copy() {
+        for (top, bottom) in [(0, 5), (1, 4), (2, 2)] {
+            for n in 0..=7 {
+                for up in [true, false] {
+                    let mut fast = numbered_grid(4, 6);
+                    let mut naive = fast.clone();
+                    if up {
+                        fast.scroll_up_region(top, bottom, n);
+                    } else {
+                        fast.scroll_down_region(top, bottom, n);
+                    }
+                    naive_scroll(&mut naive, top, bottom, n, up);
+                    assert_same(&fast, &naive);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_scroll_region_marks_only_region_dirty() {
+        let mut grid = numbered_grid(4, 6);
+        grid.clear_dirty();
+
+        grid.scroll_up_region(1, 3, 1);
+        let dirty: Vec<bool> = (0..6).map(|y| grid.is_row_dirty(y)).collect();
+        assert_eq!(dirty, [false, true, true, true, false, false]);
+
+        // Bounds past the grid are clamped, empty regions are ignored
+        grid.scroll_down_region(4, 100, 1);
+        assert_eq!(grid.get(0, 4).unwrap().character, ' ');
+        assert_eq!(grid.get(0, 5).unwrap().character, 'q');
+        grid.scroll_down_region(5, 2, 1);
+        assert_eq!(grid.get(0, 5).unwrap().character, 'q');
+    }
+}
+
+#[cfg(test)]
+mod grid_fill_region_tests {
+    use crate::core::{Cell, Grid};
+
+    #[test]
+    fn test_fill_sub_rectangle() {
+        let mut grid = Grid::new(6, 5);
+        grid.clear_dirty();
+
+        grid.fill_region(1, 1, 4, 3, Cell::new('#'));
+
+        for y in 0..5 {
+            for x in 0..6 {
+                let inside = (1..4).contains(&x) && (1..3).contains(&y);
+                let expected = if inside { '#' } else { ' ' };
+                assert_eq!(
+                    grid.get(x, y).unwrap().character,
+                    expected,
+                    "({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+        let dirty: Vec<bool> = (0..5).map(|y| grid.is_row_dirty(y)).collect();
+        assert_eq!(dirty, [false, true, true, false, false]);
+    }
+
+    #[test]
+    fn test_fill_full_rows_and_clamping() {
+        let mut grid = Grid::new(3, 3);
+
+        grid.fill_region(0, 1, 3, 2, Cell::new('-'));
+        assert!(grid.row(1).unwrap().iter().all(|c| c.character == '-'));
+        assert!(grid.row(0).unwrap().iter().all(|c| c.character == ' '));
+
+        grid.fill_region(2, 2, usize::MAX, usize::MAX, Cell::new('x'));
+        assert_eq!(grid.get(2, 2).unwrap().character, 'x');
+        assert_eq!(grid.get(1, 2).unwrap().character, ' ');
+
+        // Empty rectangles are a no-op
+        grid.fill_region(2, 0, 1, 3, Cell::new('!'));
+        assert!((0..3).all(|y| grid.get(1, y).unwrap().character != '!'));
+    }
+}
+
+#[cfg(test)]
+mod grid_row_extent_tests {
+    use crate::core::{Cell, CellStyle, Color, Grid};
+
+    #[test]
+    fn test_blank_row_has_no_extent() {
+        let grid = Grid::new(8, 2);
+        assert_eq!(grid.row_extent(0), None);
+        assert_eq!(grid.row_extent(5), None);
+    }
+
+    #[test]
+    fn test_text_in_middle_of_row() {
+        let mut grid = Grid::new(8, 1);
+        grid.set(2, 0, Cell::new('a')).unwrap();
+        grid.set(3, 0, Cell::new(' ')).unwrap();
+        grid.set(4, 0, Cell::new('b')).unwrap();
+        assert_eq!(grid.row_extent(0), Some((2, 4)));
+    }
+
+    #[test]
+    fn test_colored_trailing_space_is_occupied() {
+        let mut grid = Grid::new(8, 1);
+        let style = CellStyle {
+            bg: Color::BLUE,
+            ..CellStyle::default()
+        };
+        grid.set(1, 0, Cell::new('x')).unwrap();
+        grid.set(6, 0, Cell::new(' ').with_style(style)).unwrap();
+        assert_eq!(grid.row_extent(0), Some((1, 6)));
+    }
+    #[test]
+    fn test_last_nonblank_row() {
+        let mut grid = Grid::new(8, 5);
+        assert_eq!(grid.last_nonblank_row(), None);
+
+        grid.set(0, 0, Cell::new('a')).unwrap();
+        grid.set(4, 3, Cell::new('b')).unwrap();
+        assert_eq!(grid.last_nonblank_row(), Some(3));
+
+        // A blank with a background color counts as content
+        let style = CellStyle {
+            bg: Color::BLUE,
+            ..CellStyle::default()
+        };
+        grid.set(7, 4, Cell::new(' ').with_style(style)).unwrap();
+        assert_eq!(grid.last_nonblank_row(), Some(4));
+    }
+}
+
+#[cfg(test)]
+mod grid_style_run_tests {
+    use crate::core::{Cell, CellStyle, Color, Grid};
+
+    #[test]
+    fn test_three_segments_give_three_runs() {
+        let mut grid = Grid::new(6, 1);
+        let red = CellStyle {
+            fg: Color::RED,
+            ..CellStyle::default()
+        };
+        let bold = CellStyle {
+            bold: true,
+            ..CellStyle::default()
+        };
+        for x in 0..2 {
+            grid.set(x, 0, Cell::new('r').with_style(red)).unwrap();
+        }
+        for x in 2..5 {
+            grid.set(x, 0, Cell::new('b').with_style(bold)).unwrap();
+        }
+
+        assert_eq!(
+            grid.style_runs(0),
+            vec![(0..2, red), (2..5, bold), (5..6, CellStyle::default())]
+        );
+        assert!(grid.style_runs(1).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod grid_snapshot_rows_tests {
+    use crate::core::{Cell, CellStyle, Color, Grid};
+
+    #[test]
+    fn test_snapshot_matches_get() {
+        let mut grid = Grid::new(4, 3);
+        let red = CellStyle {
+            fg: Color::RED,
+            ..CellStyle::default()
+        };
+        grid.set(1, 0, Cell::new('a')).unwrap();
+        grid.set(3, 2, Cell::new('z').with_style(red)).unwrap();
+
+        let rows = grid.snapshot_rows();
+        assert_eq!(rows.len(), 3);
+        for (y, row) in rows.iter().enumerate() {
+            assert_eq!(row.len(), 4);
+            for (x, cell) in row.iter().enumerate() {
+                assert_eq!(Some(cell), grid.get(x, y));
+            }
+        }
+        assert_eq!(rows[2][3].style, red);
+    }
+}
+
+#[cfg(test)]
+mod grid_resize_keep_bottom_tests {
+    use crate::core::{Cell, Grid};
+    use alloc::vec::Vec;
+
+    fn grid_with_rows(rows: &str, height: usize) -> Grid {
+        let mut grid = Grid::new(4, height);
+        for (y, c) in rows.chars().enumerate() {
+            grid.set(0, y, Cell::new(c)).unwrap();
+        }
+        grid
+    }
+
+    fn first_column(grid: &Grid) -> Vec<char> {
+        (0..grid.height())
+            .map(|y| grid.get(0, y).unwrap().character)
+            .collect()
+    }
+
+    #[test]
+    fn test_shrinking_keeps_bottom_rows() {
+        let mut grid = grid_with_rows("abcd", 4);
+        grid.set_row_wrapped(3, true);
+        let pushed = grid.resize_keep_bottom(4, 2, 0, || None);
+
+        assert_eq!(first_column(&grid), ['c', 'd']);
+        assert!(grid.is_row_wrapped(1));
+        let pushed: Vec<char> = pushed.iter().map(|row| row[0].character).collect();
+        assert_eq!(pushed, ['a', 'b']);
+    }
+
+    #[test]
+    fn test_blank_rows_go_first() {
+        let mut grid = grid_with_rows("ab", 5);
+        assert!(grid.resize_keep_bottom(4, 3, 0, || None).is_empty());
+        assert_eq!(first_column(&grid), ['a', 'b', ' ']);
+
+        // Rows up to `min_rows` count as used even when blank
+        let pushed = grid.resize_keep_bottom(4, 2, 3, || None);
+        assert_eq!(pushed.len(), 1);
+        assert_eq!(first_column(&grid), ['b', ' ']);
+    }
+
+    #[test]
+    fn test_growing_pulls_rows_back() {
+        let mut grid = grid_with_rows("abcd", 4);
+        let mut history = grid.resize_keep_bottom(4, 2, 0, || None);
+        history.insert(0, alloc::vec![Cell::new('z'); 2]);
+
+        grid.resize_keep_bottom(4, 4, 0, || history.pop());
+        assert_eq!(first_column(&grid), ['a', 'b', 'c', 'd']);
+        assert_eq!(history.len(), 1);
+
+        // Short rows from history are padded to the width
+        grid.resize_keep_bottom(4, 6, 0, || history.pop());
+        assert_eq!(first_column(&grid), ['z', 'a', 'b', 'c', 'd', ' ']);
+        assert_eq!(grid.get(3, 0), Some(&Cell::default()));
+    }
+}
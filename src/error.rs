@@ -0,0 +1,30 @@
+use thiserror::Error;
+
+/// Errors returned by the rin library
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// A grid write landed outside the grid
+    #[error("Position out of bounds: ({x}, {y})")]
+    OutOfBounds { x: usize, y: usize },
+
+    /// A deserialized grid's cells don't add up to its dimensions
+    #[error("Grid snapshot does not match its dimensions")]
+    InvalidSnapshot,
+
+    /// Opening, spawning or talking to the PTY failed
+    #[cfg(any(feature = "pty", feature = "android"))]
+    #[error("{context}")]
+    Pty {
+        context: &'static str,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// Reading or writing a session recording failed
+    #[cfg(feature = "std")]
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = ::core::result::Result<T, Error>;
@@ -0,0 +1,32 @@
+use crate::parser::Command;
+
+/// Notifications from [`TerminalEngine::write`](crate::TerminalEngine::write)
+/// for embedders that would rather not poll. Every method defaults to a no-op.
+pub trait EventSink: Send {
+    /// OSC 0 / OSC 2
+    fn on_title(&mut self, _title: &str) {}
+
+    /// OSC 7, with the path taken from the `file://` URI
+    fn on_cwd(&mut self, _cwd: &str) {}
+
+    fn on_bell(&mut self) {}
+
+    /// OSC 52, with the base64 payload as the application sent it
+    fn on_clipboard(&mut self, _content: &str) {}
+
+    /// `CSI ? mode h` (`set`) or `CSI ? mode l` for a mode the engine
+    /// doesn't implement, for embedders that want to handle or log it
+    fn on_unknown_private_mode(&mut self, _mode: u16, _set: bool) {}
+}
+
+/// Forwards `cmd` to the sink if it is one it cares about
+pub(crate) fn dispatch(sink: &mut dyn EventSink, cmd: &Command) {
+    match cmd {
+        Command::SetTitle(title) => sink.on_title(title),
+        Command::SetWorkingDirectory(path) => sink.on_cwd(path),
+        Command::Bell => sink.on_bell(),
+        Command::CopyToClipboard(content) => sink.on_clipboard(content),
+        Command::UnknownPrivateMode { mode, set } => sink.on_unknown_private_mode(*mode, *set),
+        _ => {}
+    }
+}
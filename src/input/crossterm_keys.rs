@@ -0,0 +1,47 @@
+use super::handler::{Key, KeyEvent, Modifiers};
+use crossterm::event::{self as ct, KeyCode, KeyModifiers};
+
+impl From<KeyModifiers> for Modifiers {
+    fn from(modifiers: KeyModifiers) -> Self {
+        Self {
+            ctrl: modifiers.contains(KeyModifiers::CONTROL),
+            alt: modifiers.contains(KeyModifiers::ALT),
+            shift: modifiers.contains(KeyModifiers::SHIFT),
+        }
+    }
+}
+
+/// Fails with the key code for keys rin has no encoding for, such as media
+/// keys or bare modifier presses.
+impl TryFrom<ct::KeyEvent> for KeyEvent {
+    type Error = KeyCode;
+
+    fn try_from(event: ct::KeyEvent) -> Result<Self, KeyCode> {
+        let mut modifiers = Modifiers::from(event.modifiers);
+        let key = match event.code {
+            KeyCode::Char(c) => Key::Char(c),
+            KeyCode::Enter => Key::Enter,
+            KeyCode::Backspace => Key::Backspace,
+            KeyCode::Tab => Key::Tab,
+            // crossterm reports Shift+Tab as its own key
+            KeyCode::BackTab => {
+                modifiers.shift = true;
+                Key::Tab
+            }
+            KeyCode::Esc => Key::Escape,
+            KeyCode::Up => Key::Up,
+            KeyCode::Down => Key::Down,
+            KeyCode::Left => Key::Left,
+            KeyCode::Right => Key::Right,
+            KeyCode::Home => Key::Home,
+            KeyCode::End => Key::End,
+            KeyCode::PageUp => Key::PageUp,
+            KeyCode::PageDown => Key::PageDown,
+            KeyCode::Delete => Key::Delete,
+            KeyCode::Insert => Key::Insert,
+            KeyCode::F(n) => Key::F(n),
+            code => return Err(code),
+        };
+        Ok(KeyEvent::with_modifiers(key, modifiers))
+    }
+}
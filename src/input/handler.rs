@@ -1,4 +1,4 @@
-use anyhow::Result;
+use crate::error::Result;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Key {
@@ -18,26 +18,114 @@ pub enum Key {
     Delete,
     Insert,
     F(u8),
+    Keypad(KeypadKey),
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Keys of the numeric keypad, which send their own sequences in
+/// application keypad mode (DECKPAM)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeypadKey {
+    /// 0..=9
+    Digit(u8),
+    Enter,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Decimal,
+    Separator,
+    Equal,
+}
+
+impl KeypadKey {
+    /// The character the key types in numeric keypad mode
+    fn character(self) -> u8 {
+        match self {
+            KeypadKey::Digit(n) => b'0' + n.min(9),
+            KeypadKey::Enter => b'\r',
+            KeypadKey::Plus => b'+',
+            KeypadKey::Minus => b'-',
+            KeypadKey::Multiply => b'*',
+            KeypadKey::Divide => b'/',
+            KeypadKey::Decimal => b'.',
+            KeypadKey::Separator => b',',
+            KeypadKey::Equal => b'=',
+        }
+    }
+
+    /// The final byte of its `ESC O x` sequence in application keypad mode
+    fn ss3_letter(self) -> u8 {
+        match self {
+            KeypadKey::Digit(n) => b'p' + n.min(9),
+            KeypadKey::Enter => b'M',
+            KeypadKey::Plus => b'k',
+            KeypadKey::Minus => b'm',
+            KeypadKey::Multiply => b'j',
+            KeypadKey::Divide => b'o',
+            KeypadKey::Decimal => b'n',
+            KeypadKey::Separator => b'l',
+            KeypadKey::Equal => b'X',
+        }
+    }
+
+    fn from_ss3_letter(letter: u8) -> Option<Self> {
+        let key = match letter {
+            b'p'..=b'y' => KeypadKey::Digit(letter - b'p'),
+            b'M' => KeypadKey::Enter,
+            b'k' => KeypadKey::Plus,
+            b'm' => KeypadKey::Minus,
+            b'j' => KeypadKey::Multiply,
+            b'o' => KeypadKey::Divide,
+            b'n' => KeypadKey::Decimal,
+            b'l' => KeypadKey::Separator,
+            b'X' => KeypadKey::Equal,
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+
+/// Terminal modes that change what keys send
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeyModes {
+    backspace: u8,
+    application_keypad: bool,
+}
+
+impl Default for KeyModes {
+    fn default() -> Self {
+        Self {
+            backspace: 0x7f,
+            application_keypad: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Modifiers {
     pub ctrl: bool,
     pub alt: bool,
     pub shift: bool,
 }
 
-impl Default for Modifiers {
-    fn default() -> Self {
+impl Modifiers {
+    /// xterm modifier parameter (`1 + shift + 2*alt + 4*ctrl`), `None` when unmodified
+    fn param(self) -> Option<u8> {
+        let bits = self.shift as u8 | (self.alt as u8) << 1 | (self.ctrl as u8) << 2;
+        (bits != 0).then_some(bits + 1)
+    }
+
+    fn from_param(param: u16) -> Self {
+        let bits = param.saturating_sub(1);
         Self {
-            ctrl: false,
-            alt: false,
-            shift: false,
+            shift: bits & 1 != 0,
+            alt: bits & 2 != 0,
+            ctrl: bits & 4 != 0,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KeyEvent {
     pub key: Key,
     pub modifiers: Modifiers,
@@ -55,54 +143,374 @@ impl KeyEvent {
         Self { key, modifiers }
     }
 
+    /// The bytes this key sends with every mode at its default; keypad
+    /// keys type their characters
     pub fn to_ansi(&self) -> Vec<u8> {
+        self.encode(KeyModes::default())
+    }
+
+    fn encode(&self, modes: KeyModes) -> Vec<u8> {
+        let modifier = self.modifiers.param();
         match self.key {
             Key::Char(c) => {
+                let mut bytes = Vec::new();
+                if self.modifiers.alt {
+                    bytes.push(0x1b);
+                }
                 if self.modifiers.ctrl {
-                    if c >= 'a' && c <= 'z' {
-                        return vec![(c as u8) - b'a' + 1];
-                    } else if c >= 'A' && c <= 'Z' {
-                        return vec![(c as u8) - b'A' + 1];
+                    if c.is_ascii_lowercase() {
+                        bytes.push((c as u8) - b'a' + 1);
+                        return bytes;
+                    } else if c.is_ascii_uppercase() {
+                        bytes.push((c as u8) - b'A' + 1);
+                        return bytes;
                     }
                 }
-                c.to_string().into_bytes()
+                bytes.extend_from_slice(c.to_string().as_bytes());
+                bytes
             }
             Key::Enter => vec![b'\r'],
-            Key::Backspace => vec![0x7f],
+            Key::Backspace => vec![modes.backspace],
+            Key::Tab if self.modifiers.shift => vec![0x1b, b'[', b'Z'],
             Key::Tab => vec![b'\t'],
             Key::Escape => vec![0x1b],
-            Key::Up => vec![0x1b, b'[', b'A'],
-            Key::Down => vec![0x1b, b'[', b'B'],
-            Key::Right => vec![0x1b, b'[', b'C'],
-            Key::Left => vec![0x1b, b'[', b'D'],
-            Key::Home => vec![0x1b, b'[', b'H'],
-            Key::End => vec![0x1b, b'[', b'F'],
-            Key::PageUp => vec![0x1b, b'[', b'5', b'~'],
-            Key::PageDown => vec![0x1b, b'[', b'6', b'~'],
-            Key::Delete => vec![0x1b, b'[', b'3', b'~'],
-            Key::Insert => vec![0x1b, b'[', b'2', b'~'],
-            Key::F(n) => {
-                if n >= 1 && n <= 4 {
-                    vec![0x1b, b'O', b'P' + (n - 1)]
-                } else {
-                    vec![]
+            Key::Up => csi_letter(b'A', modifier),
+            Key::Down => csi_letter(b'B', modifier),
+            Key::Right => csi_letter(b'C', modifier),
+            Key::Left => csi_letter(b'D', modifier),
+            Key::Home => csi_letter(b'H', modifier),
+            Key::End => csi_letter(b'F', modifier),
+            Key::PageUp => csi_tilde(5, modifier),
+            Key::PageDown => csi_tilde(6, modifier),
+            Key::Delete => csi_tilde(3, modifier),
+            Key::Insert => csi_tilde(2, modifier),
+            Key::F(n) => match n {
+                1..=4 if modifier.is_some() => csi_letter(b'P' + (n - 1), modifier),
+                1..=4 => vec![0x1b, b'O', b'P' + (n - 1)],
+                5..=12 => csi_tilde(FUNCTION_KEY_CODES[(n - 5) as usize], modifier),
+                _ => vec![],
+            },
+            Key::Keypad(key) if modes.application_keypad => vec![0x1b, b'O', key.ss3_letter()],
+            Key::Keypad(key) => vec![key.character()],
+        }
+    }
+}
+
+/// `CSI n ~` codes for F5..=F12
+const FUNCTION_KEY_CODES: [u8; 8] = [15, 17, 18, 19, 20, 21, 23, 24];
+
+fn csi_letter(letter: u8, modifier: Option<u8>) -> Vec<u8> {
+    match modifier {
+        Some(m) => format!("\x1b[1;{}{}", m, letter as char).into_bytes(),
+        None => vec![0x1b, b'[', letter],
+    }
+}
+
+fn csi_tilde(code: u8, modifier: Option<u8>) -> Vec<u8> {
+    match modifier {
+        Some(m) => format!("\x1b[{};{}~", code, m).into_bytes(),
+        None => format!("\x1b[{}~", code).into_bytes(),
+    }
+}
+
+enum Decoded {
+    Event(InputEvent, usize),
+    Skip(usize),
+    Incomplete,
+}
+
+/// Decodes the first key or mouse report in `bytes`
+fn decode_one(bytes: &[u8]) -> Decoded {
+    if bytes[0] != 0x1b {
+        return decode_plain(bytes);
+    }
+    match bytes.get(1) {
+        // A trailing ESC may start a sequence split across reads; the
+        // caller decides when it is the Escape key, see `InputHandler::flush`
+        None => Decoded::Incomplete,
+        Some(0x1b) => Decoded::Event(KeyEvent::new(Key::Escape).into(), 1),
+        Some(b'[') => decode_csi(bytes),
+        Some(b'O') => decode_ss3(bytes),
+        Some(_) => match decode_plain(&bytes[1..]) {
+            Decoded::Event(InputEvent::Key(mut event), len) => {
+                event.modifiers.alt = true;
+                Decoded::Event(event.into(), len + 1)
+            }
+            other => other,
+        },
+    }
+}
+
+fn decode_plain(bytes: &[u8]) -> Decoded {
+    let key = match bytes[0] {
+        b'\r' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        b @ 0x01..=0x1a => {
+            let c = (b - 1 + b'a') as char;
+            let modifiers = Modifiers {
+                ctrl: true,
+                ..Modifiers::default()
+            };
+            return Decoded::Event(KeyEvent::with_modifiers(Key::Char(c), modifiers).into(), 1);
+        }
+        0x00..=0x1f => return Decoded::Skip(1),
+        b => {
+            let len = match b {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            if bytes.len() < len {
+                return Decoded::Incomplete;
+            }
+            match std::str::from_utf8(&bytes[..len]) {
+                Ok(s) => {
+                    let c = s.chars().next().unwrap_or('\u{fffd}');
+                    return Decoded::Event(KeyEvent::new(Key::Char(c)).into(), len);
                 }
+                Err(_) => return Decoded::Skip(1),
             }
         }
+    };
+    Decoded::Event(KeyEvent::new(key).into(), 1)
+}
+
+fn decode_ss3(bytes: &[u8]) -> Decoded {
+    let Some(&letter) = bytes.get(2) else {
+        return Decoded::Incomplete;
+    };
+    let key = letter_key(letter).or_else(|| KeypadKey::from_ss3_letter(letter).map(Key::Keypad));
+    match key {
+        Some(key) => Decoded::Event(KeyEvent::new(key).into(), 3),
+        None => Decoded::Skip(3),
+    }
+}
+
+/// Longest CSI we wait for before giving up on a malformed sequence
+const MAX_CSI_LEN: usize = 32;
+
+fn decode_csi(bytes: &[u8]) -> Decoded {
+    match bytes.get(2) {
+        Some(b'M') => return decode_legacy_mouse(bytes),
+        Some(b'<') => return decode_sgr_mouse(bytes),
+        _ => {}
+    }
+    let Some(end) = bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b)) else {
+        return if bytes.len() > MAX_CSI_LEN {
+            Decoded::Skip(bytes.len())
+        } else {
+            Decoded::Incomplete
+        };
+    };
+    let len = end + 3;
+    let final_byte = bytes[len - 1];
+    let params: Vec<u16> = std::str::from_utf8(&bytes[2..len - 1])
+        .unwrap_or("")
+        .split(';')
+        .map(|p| p.parse().unwrap_or(0))
+        .collect();
+    let modifiers = Modifiers::from_param(params.get(1).copied().unwrap_or(1));
+
+    let key = match final_byte {
+        b'Z' => {
+            let modifiers = Modifiers {
+                shift: true,
+                ..Modifiers::default()
+            };
+            return Decoded::Event(KeyEvent::with_modifiers(Key::Tab, modifiers).into(), len);
+        }
+        b'~' => match params[0] {
+            1 | 7 => Some(Key::Home),
+            2 => Some(Key::Insert),
+            3 => Some(Key::Delete),
+            4 | 8 => Some(Key::End),
+            5 => Some(Key::PageUp),
+            6 => Some(Key::PageDown),
+            11..=14 => Some(Key::F((params[0] - 10) as u8)),
+            code => FUNCTION_KEY_CODES
+                .iter()
+                .position(|&c| c as u16 == code)
+                .map(|i| Key::F(i as u8 + 5)),
+        },
+        letter => letter_key(letter),
+    };
+    match key {
+        Some(key) => Decoded::Event(KeyEvent::with_modifiers(key, modifiers).into(), len),
+        None => Decoded::Skip(len),
+    }
+}
+
+/// Legacy X10/normal report: `CSI M Cb Cx Cy`, each byte offset by 32
+fn decode_legacy_mouse(bytes: &[u8]) -> Decoded {
+    if bytes.len() < 6 {
+        return Decoded::Incomplete;
+    }
+    let code = bytes[3].wrapping_sub(32) as u16;
+    let x = bytes[4].wrapping_sub(33) as usize;
+    let y = bytes[5].wrapping_sub(33) as usize;
+    // Button 3 is "released", without saying which button
+    let released = code & 0b1110_0011 == 3;
+    Decoded::Event(MouseEvent::from_code(code, x, y, released).into(), 6)
+}
+
+/// SGR report: `CSI < b ; x ; y M` (press) or `... m` (release)
+fn decode_sgr_mouse(bytes: &[u8]) -> Decoded {
+    let Some(end) = bytes[3..].iter().position(|b| (0x40..=0x7e).contains(b)) else {
+        return if bytes.len() > MAX_CSI_LEN {
+            Decoded::Skip(bytes.len())
+        } else {
+            Decoded::Incomplete
+        };
+    };
+    let len = end + 4;
+    let final_byte = bytes[len - 1];
+    let params: Vec<u16> = std::str::from_utf8(&bytes[3..len - 1])
+        .unwrap_or("")
+        .split(';')
+        .map(|p| p.parse().unwrap_or(0))
+        .collect();
+    if !matches!(final_byte, b'M' | b'm') || params.len() != 3 {
+        return Decoded::Skip(len);
+    }
+    let x = params[1].saturating_sub(1) as usize;
+    let y = params[2].saturating_sub(1) as usize;
+    let event = MouseEvent::from_code(params[0], x, y, final_byte == b'm');
+    Decoded::Event(event.into(), len)
+}
+
+fn letter_key(letter: u8) -> Option<Key> {
+    match letter {
+        b'A' => Some(Key::Up),
+        b'B' => Some(Key::Down),
+        b'C' => Some(Key::Right),
+        b'D' => Some(Key::Left),
+        b'H' => Some(Key::Home),
+        b'F' => Some(Key::End),
+        b'P'..=b'S' => Some(Key::F(letter - b'P' + 1)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Press,
+    Release,
+    /// Motion, with `button` held if any
+    Move,
+}
+
+/// A decoded mouse report; `x`/`y` are 0-based cell coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    /// `None` for buttonless motion and legacy releases
+    pub button: Option<MouseButton>,
+    pub x: usize,
+    pub y: usize,
+    pub modifiers: Modifiers,
+}
+
+impl MouseEvent {
+    fn from_code(code: u16, x: usize, y: usize, released: bool) -> Self {
+        let button = match (code & 0b11, code & 64 != 0) {
+            (0, false) => Some(MouseButton::Left),
+            (1, false) => Some(MouseButton::Middle),
+            (2, false) => Some(MouseButton::Right),
+            (0, true) => Some(MouseButton::WheelUp),
+            (1, true) => Some(MouseButton::WheelDown),
+            _ => None,
+        };
+        let kind = if released {
+            MouseEventKind::Release
+        } else if code & 32 != 0 {
+            MouseEventKind::Move
+        } else {
+            MouseEventKind::Press
+        };
+        Self {
+            kind,
+            button,
+            x,
+            y,
+            modifiers: Modifiers {
+                shift: code & 4 != 0,
+                alt: code & 8 != 0,
+                ctrl: code & 16 != 0,
+            },
+        }
+    }
+}
+
+/// Anything [`InputHandler::decode`] can produce
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+}
+
+impl From<KeyEvent> for InputEvent {
+    fn from(event: KeyEvent) -> Self {
+        InputEvent::Key(event)
+    }
+}
+
+impl From<MouseEvent> for InputEvent {
+    fn from(event: MouseEvent) -> Self {
+        InputEvent::Mouse(event)
     }
 }
 
 pub struct InputHandler {
     buffer: Vec<u8>,
+    /// The incomplete sequence at the end of the last `decode` input
+    pending_input: Vec<u8>,
+    modes: KeyModes,
 }
 
 impl InputHandler {
     pub fn new() -> Self {
-        Self { buffer: Vec::new() }
+        Self {
+            buffer: Vec::new(),
+            pending_input: Vec::new(),
+            modes: KeyModes::default(),
+        }
+    }
+
+    /// What Backspace sends: DEL (0x7f, the default) or BS (0x08), e.g.
+    /// [`TerminalBuffer::backspace_byte`](crate::TerminalBuffer::backspace_byte)
+    /// to follow DECBKM.
+    pub fn set_backspace_byte(&mut self, byte: u8) {
+        self.modes.backspace = byte;
+    }
+
+    pub fn backspace_byte(&self) -> u8 {
+        self.modes.backspace
+    }
+
+    /// Whether keypad keys send `ESC O x` (DECKPAM) instead of their
+    /// characters, e.g. following
+    /// [`TerminalBuffer::is_application_keypad`](crate::TerminalBuffer::is_application_keypad)
+    pub fn set_application_keypad(&mut self, enabled: bool) {
+        self.modes.application_keypad = enabled;
+    }
+
+    pub fn is_application_keypad(&self) -> bool {
+        self.modes.application_keypad
     }
 
     pub fn handle_key(&mut self, event: KeyEvent) -> Result<Vec<u8>> {
-        Ok(event.to_ansi())
+        Ok(event.encode(self.modes))
     }
 
     pub fn push_bytes(&mut self, bytes: &[u8]) {
@@ -112,6 +520,42 @@ impl InputHandler {
     pub fn drain(&mut self) -> Vec<u8> {
         self.buffer.drain(..).collect()
     }
+
+    /// Decodes raw input bytes back into key events, the inverse of
+    /// [`KeyEvent::to_ansi`], and SGR or legacy mouse reports into mouse
+    /// events. An incomplete CSI/SS3 sequence, UTF-8 character or lone ESC
+    /// at the end is kept until the next call, apart from the bytes queued
+    /// for [`drain`](Self::drain).
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<InputEvent> {
+        self.pending_input.extend_from_slice(bytes);
+
+        let mut events = Vec::new();
+        let mut pos = 0;
+        while pos < self.pending_input.len() {
+            match decode_one(&self.pending_input[pos..]) {
+                Decoded::Event(event, len) => {
+                    events.push(event);
+                    pos += len;
+                }
+                Decoded::Skip(len) => pos += len,
+                Decoded::Incomplete => break,
+            }
+        }
+        self.pending_input.drain(..pos);
+        events
+    }
+
+    /// Ends whatever [`decode`](Self::decode) is still holding, e.g. once no
+    /// more input has arrived for a short while. A lone ESC is the Escape
+    /// key; any other partial sequence is dropped.
+    pub fn flush(&mut self) -> Vec<InputEvent> {
+        let pending = std::mem::take(&mut self.pending_input);
+        if pending == [0x1b] {
+            vec![KeyEvent::new(Key::Escape).into()]
+        } else {
+            Vec::new()
+        }
+    }
 }
 
 impl Default for InputHandler {
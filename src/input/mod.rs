@@ -1,4 +1,9 @@
+#[cfg(feature = "crossterm")]
+mod crossterm_keys;
 pub mod handler;
 
 // Re-export semua public types
-pub use handler::{InputHandler, KeyEvent, Key, Modifiers};
+pub use handler::{
+    InputEvent, InputHandler, Key, KeyEvent, KeypadKey, Modifiers, MouseButton, MouseEvent,
+    MouseEventKind,
+};
@@ -1,26 +1,124 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod capabilities;
 pub mod core;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "std")]
 pub mod input;
+#[cfg(feature = "std")]
 pub mod parser;
+#[cfg(feature = "std")]
+pub mod recording;
+#[cfg(feature = "std")]
 pub mod renderer;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests;
 
-pub use core::{Cell, CellStyle, Color, Grid, TerminalBuffer};
-pub use input::{InputHandler, Key, KeyEvent, Modifiers};
-pub use parser::{AnsiParser, Command, ParseResult};
-pub use renderer::{AndroidRenderer, RenderContext, Renderer, ScreenRenderer};
+#[cfg(feature = "std")]
+pub use capabilities::{CAPABILITIES, Capabilities};
+pub use core::{Cell, CellStyle, Color, Grid};
+#[cfg(feature = "std")]
+pub use core::{ControlCharPolicy, TerminalBuffer};
+pub use error::{Error, Result};
+#[cfg(feature = "std")]
+pub use events::EventSink;
+#[cfg(feature = "std")]
+pub use input::{
+    InputEvent, InputHandler, Key, KeyEvent, KeypadKey, Modifiers, MouseButton, MouseEvent,
+    MouseEventKind,
+};
+#[cfg(feature = "std")]
+pub use parser::{AnsiParser, Command, ParseResult, Theme};
+#[cfg(feature = "std")]
+pub use recording::{Frame, Pacing, SessionPlayer, SessionRecorder};
+#[cfg(feature = "std")]
+pub use renderer::{
+    AndroidRenderer, FontFace, GlyphChoice, GlyphFallback, GlyphStyle, RenderContext, Renderer,
+    ScreenRenderer, to_html,
+};
+
+/// Side effects of one [`TerminalEngine::process`] call
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOutcome {
+    /// The data changed something on screen: a grid row, the cursor's
+    /// position or visibility, the active screen, the viewport or a mode,
+    /// so a render is due. Queries and title changes alone leave it false.
+    pub dirty: bool,
+    /// Replies are waiting in [`TerminalBuffer::drain_responses`]
+    pub has_responses: bool,
+    pub title_changed: bool,
+}
+
+/// How [`TerminalEngine`] decodes the bytes it is given
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputEncoding {
+    /// Invalid sequences become U+FFFD
+    #[default]
+    Utf8,
+    /// Every byte is one character, for legacy programs
+    Latin1,
+}
+
+/// Parses `data` and applies it to `buffer` in one call. No input makes this
+/// panic, however malformed: huge counts and coordinates are clamped to the
+/// screen. Suitable as a `cargo fuzz` target.
+#[cfg(feature = "std")]
+pub fn feed(buffer: &mut TerminalBuffer, parser: &mut AnsiParser, data: &[u8]) -> Result<()> {
+    buffer.execute_commands(parser.parse(data)?)
+}
+
+/// What a render depends on besides the grid rows, compared across
+/// [`TerminalEngine::apply`] to tell whether a batch changed the screen
+#[cfg(feature = "std")]
+#[derive(PartialEq, Eq)]
+struct ScreenState {
+    cursor: (usize, usize),
+    cursor_visible: bool,
+    cursor_style: parser::CursorStyle,
+    alternate: bool,
+    scroll_offset: usize,
+    modes: Vec<(u16, bool)>,
+    ansi_modes: Vec<(u16, bool)>,
+}
 
-use anyhow::Result;
+#[cfg(feature = "std")]
+impl ScreenState {
+    fn of(buffer: &TerminalBuffer) -> Self {
+        Self {
+            cursor: buffer.cursor_pos(),
+            cursor_visible: buffer.is_cursor_visible(),
+            cursor_style: buffer.cursor_style(),
+            alternate: buffer.is_alternate_screen(),
+            scroll_offset: buffer.scroll_offset(),
+            modes: buffer.active_modes(),
+            ansi_modes: buffer.active_ansi_modes(),
+        }
+    }
+}
 
+#[cfg(feature = "std")]
 pub struct TerminalEngine {
     buffer: TerminalBuffer,
     parser: AnsiParser,
     renderer: Box<dyn Renderer + Send>,
     width: usize,
     height: usize,
+    pending_frame: bool,
+    event_sink: Option<Box<dyn EventSink>>,
+    encoding: InputEncoding,
+    transcoded: Vec<u8>,
+    raw_log: Option<Box<dyn std::io::Write + Send>>,
 }
 
+#[cfg(feature = "std")]
 impl TerminalEngine {
     pub fn new(width: usize, height: usize, renderer: Box<dyn Renderer + Send>) -> Self {
         Self {
@@ -29,17 +127,104 @@ impl TerminalEngine {
             renderer,
             width,
             height,
+            pending_frame: true,
+            event_sink: None,
+            encoding: InputEncoding::default(),
+            transcoded: Vec::new(),
+            raw_log: None,
         }
     }
 
+    pub fn input_encoding(&self) -> InputEncoding {
+        self.encoding
+    }
+
+    pub fn set_input_encoding(&mut self, encoding: InputEncoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn theme(&self) -> &Theme {
+        self.parser.theme()
+    }
+
+    /// See [`AnsiParser::set_theme`] and [`TerminalBuffer::set_theme`]: SGR
+    /// colors resolve to the theme, and blank cells take its foreground and
+    /// background.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.buffer.set_theme(theme);
+        self.parser.set_theme(theme);
+    }
+
+    /// Install a sink notified of title, cwd, bell and clipboard commands as
+    /// [`write`](Self::write) applies them.
+    pub fn set_event_sink(&mut self, sink: Box<dyn EventSink>) {
+        self.event_sink = Some(sink);
+    }
+
+    /// Copy every byte given to [`write`](Self::write) or
+    /// [`process`](Self::process) to `log`, unparsed, e.g. to attach the
+    /// raw session to a bug report. `None` stops logging. The log never
+    /// gets in the way of the terminal: if writing to it fails, it is
+    /// dropped and the data is processed anyway.
+    pub fn set_raw_log(&mut self, log: Option<Box<dyn std::io::Write + Send>>) {
+        self.raw_log = log;
+    }
+
     pub fn write(&mut self, data: &[u8]) -> Result<()> {
-        let commands = self.parser.parse(data)?;
+        self.process(data).map(|_| ())
+    }
 
-        for cmd in commands {
-            self.buffer.execute_command(cmd)?;
+    /// Like [`write`](Self::write), but reports what the data changed so
+    /// the frontend can skip rendering or draining responses.
+    pub fn process(&mut self, data: &[u8]) -> Result<WriteOutcome> {
+        if let Some(log) = &mut self.raw_log
+            && let Err(e) = log.write_all(data)
+        {
+            log::warn!("Raw log write failed, no longer logging: {}", e);
+            self.raw_log = None;
         }
+        let commands = match self.encoding {
+            InputEncoding::Utf8 => self.parser.parse(data)?,
+            InputEncoding::Latin1 => {
+                // Latin-1 maps each byte to the code point of the same value
+                self.transcoded.clear();
+                let mut utf8 = [0; 2];
+                for &b in data {
+                    let c = char::from(b);
+                    self.transcoded
+                        .extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                }
+                self.parser.parse(&self.transcoded)?
+            }
+        };
+        self.apply(commands)
+    }
 
-        Ok(())
+    /// Applies already-parsed commands, bypassing the parser, e.g. when
+    /// replaying a recorded session. Stops at the first error.
+    pub fn apply(&mut self, commands: impl IntoIterator<Item = Command>) -> Result<WriteOutcome> {
+        let mut outcome = WriteOutcome::default();
+        let before = ScreenState::of(&self.buffer);
+        let was_dirty = self.buffer.grid_mut().take_dirty();
+
+        let sink = &mut self.event_sink;
+        let result = self.buffer.execute_commands_with(commands, |cmd| {
+            if let Some(sink) = sink.as_deref_mut() {
+                events::dispatch(sink, cmd);
+            }
+            outcome.title_changed |= matches!(cmd, Command::SetTitle(_));
+        });
+
+        outcome.dirty =
+            self.buffer.grid().has_dirty_rows() || ScreenState::of(&self.buffer) != before;
+        self.buffer.grid_mut().merge_dirty(&was_dirty);
+        if outcome.dirty {
+            self.pending_frame = true;
+        }
+        result?;
+
+        outcome.has_responses = self.buffer.has_pending_responses();
+        Ok(outcome)
     }
 
     pub fn render(&mut self) -> Result<()> {
@@ -49,15 +234,59 @@ impl TerminalEngine {
             height: self.height,
         };
 
-        self.renderer.render(&context)
+        self.renderer.render(&context)?;
+        self.mark_rendered();
+        Ok(())
+    }
+
+    /// True if anything changed since the last frame. Frontends should check
+    /// this once per vsync and skip the frame otherwise, so a burst of writes
+    /// coalesces into a single render. Held at false while the application
+    /// has synchronized output (mode 2026) on, so its frame lands at once,
+    /// up to [`TerminalBuffer::is_frame_held`]'s timeout.
+    pub fn needs_render(&self) -> bool {
+        !self.buffer.is_frame_held() && (self.pending_frame || self.buffer.grid().has_dirty_rows())
+    }
+
+    /// Render only if [`needs_render`](Self::needs_render); returns whether a
+    /// frame was drawn.
+    pub fn render_if_needed(&mut self) -> Result<bool> {
+        if !self.needs_render() {
+            return Ok(false);
+        }
+        self.render()?;
+        Ok(true)
+    }
+
+    /// Clear the change tracking after a frontend has drawn a frame itself.
+    pub fn mark_rendered(&mut self) {
+        self.pending_frame = false;
+        self.buffer.grid_mut().clear_dirty();
     }
 
     pub fn resize(&mut self, width: usize, height: usize) -> Result<()> {
         self.width = width;
         self.height = height;
+        self.pending_frame = true;
         self.buffer.resize(width, height)
     }
 
+    /// The renderer's cell size in px, if it draws to pixels
+    pub fn cell_size(&self) -> Option<(f32, f32)> {
+        self.renderer.cell_size()
+    }
+
+    /// Pass the cell size the frontend measured on to the renderer; see
+    /// [`Renderer::set_cell_size`].
+    pub fn set_cell_size(&mut self, width: f32, height: f32) {
+        self.renderer.set_cell_size(width, height);
+    }
+
+    /// Forward a window focus change; see [`TerminalBuffer::focus_changed`].
+    pub fn focus_changed(&mut self, focused: bool) {
+        self.buffer.focus_changed(focused);
+    }
+
     pub fn buffer(&self) -> &TerminalBuffer {
         &self.buffer
     }
@@ -78,4 +307,4 @@ pub mod android;
 pub mod pty;
 
 #[cfg(any(feature = "pty", feature = "android"))]
-pub use pty::Pty;
+pub use pty::{Pty, PtyCoalescer, PtyOptions};
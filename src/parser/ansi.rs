@@ -1,6 +1,7 @@
 use crate::core::cell::UnderlineStyle;
-use crate::core::{CellStyle, Color, Hyperlink};
-use anyhow::Result;
+use crate::core::{CellStyle, Color, Hyperlink, LineAttr};
+use crate::error::Result;
+use crate::parser::Theme;
 use vte::{Params, Parser, Perform};
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum CursorStyle {
@@ -13,6 +14,27 @@ pub enum CursorStyle {
     SteadyBar,
 }
 
+impl CursorStyle {
+    /// The DECSCUSR parameter selecting this style
+    pub fn decscusr(self) -> u16 {
+        match self {
+            CursorStyle::BlinkBlock => 1,
+            CursorStyle::SteadyBlock => 2,
+            CursorStyle::BlinkUnderline => 3,
+            CursorStyle::SteadyUnderline => 4,
+            CursorStyle::BlinkBar => 5,
+            CursorStyle::SteadyBar => 6,
+        }
+    }
+
+    pub fn is_blinking(self) -> bool {
+        matches!(
+            self,
+            CursorStyle::BlinkBlock | CursorStyle::BlinkUnderline | CursorStyle::BlinkBar
+        )
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Charset {
     #[default]
@@ -23,6 +45,7 @@ pub enum Charset {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Command {
     Print(char),
+    PrintStr(String), // A run of printable chars, see TerminalBuffer::write_str
     Execute(u8),
     MoveCursor(usize, usize),
     MoveCursorRelative(i32, i32),
@@ -40,17 +63,24 @@ pub enum Command {
     EraseChars(usize),
     EraseDisplay(u8),
     EraseLine(u8),
+    SelectiveEraseDisplay(u8),    // DECSED, CSI ? J
+    SelectiveEraseLine(u8),       // DECSEL, CSI ? K
+    SetCharacterProtection(bool), // DECSCA
     Reset,
     EnterAlternateScreen,
     ExitAlternateScreen,
     SetTitle(String),
+    SetWorkingDirectory(String), // OSC 7, the path part of the file:// URI
     SetCursorStyle(CursorStyle),
+    ResetCursorStyle, // DECSCUSR 0
     SetBracketedPaste(bool),
     SetCharset(Charset),
+    SetLineAttr(LineAttr), // DECDHL (ESC # 3/4), DECSWL (ESC # 5), DECDWL (ESC # 6)
     SetTabStop,
     ClearTabStop,
     ClearAllTabStops,
     DeviceAttributeQuery,
+    SecondaryDeviceAttributeQuery, // DA2, CSI > c
     ShowCursor,
     HideCursor,
     SetHyperlink(Option<Hyperlink>),
@@ -60,10 +90,60 @@ pub enum Command {
     DeleteChars(usize),
     Bell,
     CursorPositionReport,
+    ExtendedCursorPositionReport, // DECXCPR, CSI ? 6 n
+    PrinterStatusReport,          // DECDSR, CSI ? 15 n
+    TextAreaPixelSizeReport,      // XTWINOPS, CSI 14 t
     SetFocusEvents(bool),
     SetOriginMode(bool),
     SetAutoWrapMode(bool),
+    SetLineFeedMode(bool),                       // LNM (mode 20)
+    SetInsertMode(bool),                         // IRM (mode 4)
+    SetSynchronizedOutput(bool),                 // Mode 2026
+    SetApplicationCursorKeys(bool),              // DECCKM (mode 1)
+    SetBackarrowKeyMode(bool),                   // DECBKM (mode 67)
+    SetApplicationKeypad(bool), // DECKPAM/DECKPNM (ESC = / ESC >), DECNKM (mode 66)
+    SetC1Transmission(bool),    // S8C1T (true, ESC SP G) / S7C1T (ESC SP F)
+    SavePrivateModes(Vec<u16>), // XTSAVE, CSI ? Pm s
+    RestorePrivateModes(Vec<u16>), // XTRESTORE, CSI ? Pm r
+    UnknownPrivateMode { mode: u16, set: bool }, // CSI ? mode h/l for a mode we don't implement
     CopyToClipboard(String),
+    RequestStatusString(String),   // DECRQSS, e.g. " q" for DECSCUSR
+    RequestTermcap(Vec<String>),   // XTGETTCAP, the capability names hex-decoded
+    QueryCursorColor,              // OSC 12 ; ?
+    SetPaletteColor(u8, Color),    // OSC 4 ; index ; spec
+    ResetPaletteColor(Option<u8>), // OSC 104, `None` for every entry
+}
+
+impl Command {
+    /// The command for `CSI ? mode h` (`enabled`) or `CSI ? mode l`, if the
+    /// mode is supported
+    pub fn private_mode(mode: u16, enabled: bool) -> Option<Command> {
+        let cmd = match (mode, enabled) {
+            (1, _) => Command::SetApplicationCursorKeys(enabled),
+            (66, _) => Command::SetApplicationKeypad(enabled),
+            (67, _) => Command::SetBackarrowKeyMode(enabled),
+            (47 | 1047 | 1049, true) => Command::EnterAlternateScreen,
+            (47 | 1047 | 1049, false) => Command::ExitAlternateScreen,
+            (2004, _) => Command::SetBracketedPaste(enabled),
+            (25, true) => Command::ShowCursor,
+            (25, false) => Command::HideCursor,
+            // Mouse modes
+            (9 | 1000, true) => Command::SetMouseMode(MouseMode::ReportClick),
+            (1002, true) => Command::SetMouseMode(MouseMode::ReportMotion),
+            (1003, true) => Command::SetMouseMode(MouseMode::ReportAll),
+            (9 | 1000 | 1002 | 1003, false) => Command::SetMouseMode(MouseMode::None),
+            // Focus events
+            (1004, _) => Command::SetFocusEvents(enabled),
+            // Origin Mode (DECOM)
+            (6, _) => Command::SetOriginMode(enabled),
+            // Auto-Wrap Mode (DECAWM)
+            (7, _) => Command::SetAutoWrapMode(enabled),
+            // Synchronized output
+            (2026, _) => Command::SetSynchronizedOutput(enabled),
+            _ => return None,
+        };
+        Some(cmd)
+    }
 }
 
 /// Mouse tracking modes
@@ -90,6 +170,9 @@ pub type ParseResult = Vec<Command>;
 pub struct AnsiParser {
     parser: Parser,
     performer: AnsiPerformer,
+    c1: C1Translator,
+    scratch: Vec<u8>,
+    sequence: SequenceState,
 }
 
 impl AnsiParser {
@@ -97,27 +180,202 @@ impl AnsiParser {
         Self {
             parser: Parser::new(),
             performer: AnsiPerformer::new(),
+            c1: C1Translator::default(),
+            scratch: Vec::new(),
+            sequence: SequenceState::Ground,
+        }
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.performer.theme
+    }
+
+    /// Use `theme` for SGR colors from here on. Palette entries set with
+    /// OSC 4 keep taking precedence; cells already written keep their colors.
+    /// A current style still in the old default colors takes the new ones.
+    pub fn set_theme(&mut self, theme: Theme) {
+        let old = self.performer.reset_style();
+        self.performer.theme = theme;
+        let new = self.performer.reset_style();
+        self.performer.current_style.follow_default(&old, &new);
+    }
+
+    /// True if the last `parse` ended inside an escape sequence or a
+    /// multi-byte character, i.e. bytes are buffered waiting for the rest.
+    pub fn has_pending(&self) -> bool {
+        self.sequence != SequenceState::Ground || !self.c1.is_idle()
+    }
+
+    /// Ends whatever [`has_pending`](Self::has_pending) is waiting on, as if
+    /// the sequence had been cancelled with CAN: an unterminated OSC or DCS
+    /// is dispatched with what it has, a partial escape or CSI is dropped,
+    /// and a partial UTF-8 character becomes U+FFFD. For frontends that
+    /// give up on a stalled sequence after a timeout.
+    pub fn flush_pending(&mut self) -> ParseResult {
+        if !self.has_pending() {
+            return Vec::new();
         }
+        self.performer.commands.clear();
+
+        let mut bytes = Vec::new();
+        if self.c1.pending_c2 {
+            // A lone C2 lead byte, held back in case a C1 control followed
+            bytes.push(0xc2);
+        }
+        self.c1 = C1Translator::default();
+        bytes.push(0x18);
+        self.parser.advance(&mut self.performer, &bytes);
+        self.sequence = SequenceState::Ground;
+
+        let mut commands = std::mem::take(&mut self.performer.commands);
+        commands.retain(|cmd| *cmd != Command::Execute(0x18));
+        commands
     }
 
     pub fn parse(&mut self, data: &[u8]) -> Result<ParseResult> {
         self.performer.commands.clear();
 
-        self.parser.advance(&mut self.performer, data);
+        if self.c1.is_idle() && data.is_ascii() {
+            self.parser.advance(&mut self.performer, data);
+            self.sequence = self.sequence.after(data);
+        } else {
+            self.scratch.clear();
+            self.c1.translate(data, &mut self.scratch);
+            self.parser.advance(&mut self.performer, &self.scratch);
+            self.sequence = self.sequence.after(&self.scratch);
+        }
 
         Ok(self.performer.commands.clone())
     }
 }
 
+/// A coarse mirror of vte's state machine, enough to tell whether it is
+/// mid-sequence (vte keeps its own state private)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SequenceState {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    Csi,
+    Osc,
+    /// DCS, SOS, PM and APC bodies, which only ST ends
+    String,
+}
+
+impl SequenceState {
+    fn after(self, data: &[u8]) -> Self {
+        // ESC restarts a sequence from any state, so only the bytes after
+        // the last one matter
+        let (mut state, rest) = match data.iter().rposition(|&b| b == 0x1b) {
+            Some(i) => (SequenceState::Escape, &data[i + 1..]),
+            None => (self, data),
+        };
+        for &byte in rest {
+            state = state.advance(byte);
+        }
+        state
+    }
+
+    fn advance(self, byte: u8) -> Self {
+        use SequenceState::*;
+        if matches!(byte, 0x18 | 0x1a) {
+            return Ground;
+        }
+        match (self, byte) {
+            (Ground, _) => Ground,
+            (Escape, b'[') => Csi,
+            (Escape, b']') => Osc,
+            (Escape, b'P' | b'X' | b'^' | b'_') => String,
+            (Escape, 0x20..=0x2f) | (EscapeIntermediate, 0x20..=0x2f) => EscapeIntermediate,
+            (Escape | EscapeIntermediate, 0x30..=0x7e) => Ground,
+            (Csi, 0x40..=0x7e) => Ground,
+            (Osc, 0x07) => Ground,
+            (state, _) => state,
+        }
+    }
+}
+
+/// Rewrites 8-bit C1 controls (e.g. 0x9B CSI, 0x9D OSC, 0x9C ST) into their
+/// 7-bit `ESC Fe` equivalents, which is all vte understands. Both the raw
+/// byte and its UTF-8 encoding (`C2 9B`) are accepted; bytes that are part
+/// of a multi-byte UTF-8 character are left alone. State carries across
+/// calls so sequences split between reads are handled.
+#[derive(Debug, Default)]
+struct C1Translator {
+    utf8_remaining: u8,
+    pending_c2: bool,
+}
+
+impl C1Translator {
+    fn is_idle(&self) -> bool {
+        self.utf8_remaining == 0 && !self.pending_c2
+    }
+
+    fn translate(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        for &byte in data {
+            if self.pending_c2 {
+                self.pending_c2 = false;
+                if (0x80..=0x9f).contains(&byte) {
+                    out.extend_from_slice(&[0x1b, byte - 0x40]);
+                    continue;
+                }
+                out.push(0xc2);
+                if (0xa0..=0xbf).contains(&byte) {
+                    out.push(byte);
+                    continue;
+                }
+            }
+
+            if self.utf8_remaining > 0 && (0x80..=0xbf).contains(&byte) {
+                self.utf8_remaining -= 1;
+                out.push(byte);
+                continue;
+            }
+
+            self.utf8_remaining = 0;
+            match byte {
+                0x80..=0x9f => out.extend_from_slice(&[0x1b, byte - 0x40]),
+                0xc2 => self.pending_c2 = true,
+                0xc3..=0xdf => {
+                    self.utf8_remaining = 1;
+                    out.push(byte);
+                }
+                0xe0..=0xef => {
+                    self.utf8_remaining = 2;
+                    out.push(byte);
+                }
+                0xf0..=0xf7 => {
+                    self.utf8_remaining = 3;
+                    out.push(byte);
+                }
+                _ => out.push(byte),
+            }
+        }
+    }
+}
+
 impl Default for AnsiParser {
     fn default() -> Self {
         Self::new()
     }
 }
 
+const MAX_DCS_LEN: usize = 256;
+
+/// The DCS requests we collect the body of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DcsRequest {
+    StatusString, // DCS $ q
+    Termcap,      // DCS + q
+}
+
 struct AnsiPerformer {
     commands: Vec<Command>,
     current_style: CellStyle,
+    dcs: Option<(DcsRequest, Vec<u8>)>,
+    theme: Theme,
+    /// Palette entries changed by OSC 4, layered over `theme`
+    overrides: [Option<Color>; 256],
 }
 
 impl AnsiPerformer {
@@ -125,12 +383,46 @@ impl AnsiPerformer {
         Self {
             commands: Vec::new(),
             current_style: CellStyle::default(),
+            dcs: None,
+            theme: Theme::default(),
+            overrides: [None; 256],
+        }
+    }
+
+    fn palette(&self, n: u8) -> Color {
+        self.overrides[n as usize].unwrap_or_else(|| self.theme.color(n))
+    }
+
+    /// The style SGR 0 returns to under the current theme
+    fn reset_style(&self) -> CellStyle {
+        CellStyle {
+            fg: self.theme.foreground,
+            bg: self.theme.background,
+            ..CellStyle::default()
         }
     }
 }
 
 impl Perform for AnsiPerformer {
     fn print(&mut self, c: char) {
+        // Coalesce runs of printable chars so the buffer can write them in bulk
+        if !c.is_control() {
+            match self.commands.last_mut() {
+                Some(Command::PrintStr(run)) => {
+                    run.push(c);
+                    return;
+                }
+                Some(last @ Command::Print(_)) => {
+                    if let Command::Print(prev) = *last
+                        && !prev.is_control()
+                    {
+                        *last = Command::PrintStr([prev, c].iter().collect());
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
         self.commands.push(Command::Print(c));
     }
 
@@ -142,49 +434,121 @@ impl Perform for AnsiPerformer {
         }
     }
 
-    fn hook(&mut self, _params: &Params, _intermediates: &[u8], _ignore: bool, _c: char) {}
+    fn hook(&mut self, _params: &Params, intermediates: &[u8], _ignore: bool, c: char) {
+        self.dcs = match (intermediates, c) {
+            (b"$", 'q') => Some((DcsRequest::StatusString, Vec::new())),
+            (b"+", 'q') => Some((DcsRequest::Termcap, Vec::new())),
+            _ => None,
+        };
+    }
 
-    fn put(&mut self, _byte: u8) {}
+    fn put(&mut self, byte: u8) {
+        if let Some((_, data)) = &mut self.dcs
+            && data.len() < MAX_DCS_LEN
+        {
+            data.push(byte);
+        }
+    }
 
-    fn unhook(&mut self) {}
+    fn unhook(&mut self) {
+        match self.dcs.take() {
+            Some((DcsRequest::StatusString, data)) => {
+                let request = String::from_utf8_lossy(&data).into_owned();
+                self.commands.push(Command::RequestStatusString(request));
+            }
+            Some((DcsRequest::Termcap, data)) => {
+                // Names are hex-encoded and separated by `;`
+                let names = data.split(|&b| b == b';').filter_map(decode_hex).collect();
+                self.commands.push(Command::RequestTermcap(names));
+            }
+            None => {}
+        }
+    }
 
+    // BEL, ESC \ and 8-bit ST (rewritten to ESC \ by C1Translator) all end
+    // up here alike; nothing we handle replies, so the terminator doesn't
+    // need remembering
     fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
         if let Some(cmd) = params.first() {
             match *cmd {
                 b"0" | b"2" => {
-                    if let Some(title_bytes) = params.get(1) {
-                        if let Ok(title) = std::str::from_utf8(title_bytes) {
-                            self.commands.push(Command::SetTitle(title.to_string()));
-                        }
+                    if let Some(title_bytes) = params.get(1)
+                        && let Ok(title) = std::str::from_utf8(title_bytes)
+                    {
+                        self.commands.push(Command::SetTitle(title.to_string()));
+                    }
+                }
+                b"7" => {
+                    // OSC 7: file://host/path
+                    if let Some(uri) = params.get(1).and_then(|b| std::str::from_utf8(b).ok()) {
+                        let path = match uri.strip_prefix("file://") {
+                            Some(rest) => rest.find('/').map_or("/", |i| &rest[i..]),
+                            None => uri,
+                        };
+                        self.commands
+                            .push(Command::SetWorkingDirectory(path.to_string()));
                     }
                 }
                 b"8" => {
-                    // OSC 8 hyperlink: params[1] = id/params, params[2] = URI
-                    let uri = params.get(2).and_then(|b| std::str::from_utf8(b).ok());
-                    if let Some(uri) = uri {
-                        if uri.is_empty() {
-                            // Empty URI = clear hyperlink
-                            self.commands.push(Command::SetHyperlink(None));
-                        } else {
-                            // Parse id from params[1] (format: id=VALUE;...)
-                            let id = params.get(1).and_then(|b| {
-                                std::str::from_utf8(b).ok().and_then(|s| {
-                                    s.split(';').find_map(|kv| kv.strip_prefix("id="))
-                                })
-                            });
-                            let link = Hyperlink::new(id, uri.to_string());
-                            self.commands.push(Command::SetHyperlink(Some(link)));
+                    // OSC 8 ; key=value:key=value ; URI. vte splits on every
+                    // `;`, so a URI containing one arrives in several params
+                    // and is joined back. A missing or empty URI ends the link.
+                    let uri = params
+                        .get(2..)
+                        .map(|parts| parts.join(&b';'))
+                        .and_then(|b| String::from_utf8(b).ok())
+                        .filter(|uri| !uri.is_empty());
+                    let link = uri.map(|uri| {
+                        let id = params
+                            .get(1)
+                            .and_then(|b| std::str::from_utf8(b).ok())
+                            .and_then(|s| s.split(':').find_map(|kv| kv.strip_prefix("id=")))
+                            .filter(|id| !id.is_empty());
+                        Hyperlink::new(id, uri)
+                    });
+                    self.commands.push(Command::SetHyperlink(link));
+                }
+                b"4" => {
+                    // OSC 4 ; index ; spec, repeated. `?` queries are not answered.
+                    for pair in params[1..].chunks(2) {
+                        let index = std::str::from_utf8(pair[0])
+                            .ok()
+                            .and_then(|s| s.parse::<u8>().ok());
+                        let color = pair.get(1).and_then(|spec| parse_color_spec(spec));
+                        if let (Some(index), Some(color)) = (index, color) {
+                            self.overrides[index as usize] = Some(color);
+                            self.commands.push(Command::SetPaletteColor(index, color));
                         }
                     }
                 }
-                b"52" => {
-                    if let Some(data_bytes) = params.get(2) {
-                        if let Ok(data) = std::str::from_utf8(data_bytes) {
-                            self.commands
-                                .push(Command::CopyToClipboard(data.to_string()));
+                b"104" => {
+                    // OSC 104 resets the listed entries, or all of them
+                    if params.len() == 1 {
+                        self.overrides = [None; 256];
+                        self.commands.push(Command::ResetPaletteColor(None));
+                    }
+                    for index in params[1..].iter() {
+                        if let Some(index) = std::str::from_utf8(index)
+                            .ok()
+                            .and_then(|s| s.parse::<u8>().ok())
+                        {
+                            self.overrides[index as usize] = None;
+                            self.commands.push(Command::ResetPaletteColor(Some(index)));
                         }
                     }
                 }
+                // Only the query; the cursor color comes from the theme
+                b"12" if params.get(1) == Some(&&b"?"[..]) => {
+                    self.commands.push(Command::QueryCursorColor);
+                }
+                b"52" => {
+                    if let Some(data_bytes) = params.get(2)
+                        && let Ok(data) = std::str::from_utf8(data_bytes)
+                    {
+                        self.commands
+                            .push(Command::CopyToClipboard(data.to_string()));
+                    }
+                }
                 _ => {}
             }
         }
@@ -195,6 +559,18 @@ impl Perform for AnsiPerformer {
             self.handle_private_mode(params, c);
             return;
         }
+        if intermediates.first() == Some(&b'"') {
+            self.handle_quote_intermediate(params, c);
+            return;
+        }
+        if intermediates.first() == Some(&b'>') {
+            // Only DA2; xterm's other `>` sequences (modifyOtherKeys and
+            // friends) must not fall through to SGR and the like
+            if c == 'c' {
+                self.commands.push(Command::SecondaryDeviceAttributeQuery);
+            }
+            return;
+        }
 
         match c {
             'A' => {
@@ -256,6 +632,11 @@ impl Perform for AnsiPerformer {
                 let n = *params.iter().next().and_then(|p| p.first()).unwrap_or(&1) as usize;
                 self.commands.push(Command::DeleteChars(n));
             }
+            'X' => {
+                // ECH - Erase Characters; 0 means 1
+                let n = *params.iter().next().and_then(|p| p.first()).unwrap_or(&1) as usize;
+                self.commands.push(Command::EraseChars(n.max(1)));
+            }
             'S' => {
                 let n = *params.iter().next().and_then(|p| p.first()).unwrap_or(&1) as usize;
                 self.commands.push(Command::ScrollUp(n));
@@ -267,17 +648,24 @@ impl Perform for AnsiPerformer {
             's' => self.commands.push(Command::SaveCursor),
             'u' => self.commands.push(Command::RestoreCursor),
             'g' => {
+                // TBC. Tab stops are shared by every line, so ECMA-48's
+                // "all stops in the active line" (2) and "all stops" (5)
+                // mean the same as 3. Line tabulation (1, 4) isn't supported.
                 let n = *params.iter().next().and_then(|p| p.first()).unwrap_or(&0);
                 match n {
                     0 => self.commands.push(Command::ClearTabStop),
-                    3 => self.commands.push(Command::ClearAllTabStops),
+                    2 | 3 | 5 => self.commands.push(Command::ClearAllTabStops),
                     _ => {}
                 }
             }
             'q' if intermediates.first() == Some(&b' ') => {
                 let n = *params.iter().next().and_then(|p| p.first()).unwrap_or(&0);
+                if n == 0 {
+                    self.commands.push(Command::ResetCursorStyle);
+                    return;
+                }
                 let style = match n {
-                    0 | 1 => CursorStyle::BlinkBlock,
+                    1 => CursorStyle::BlinkBlock,
                     2 => CursorStyle::SteadyBlock,
                     3 => CursorStyle::BlinkUnderline,
                     4 => CursorStyle::SteadyUnderline,
@@ -290,12 +678,28 @@ impl Perform for AnsiPerformer {
             'c' => {
                 self.commands.push(Command::DeviceAttributeQuery);
             }
+            'h' | 'l' => {
+                let enabled = c == 'h';
+                for param in params.iter() {
+                    match param.first() {
+                        Some(4) => self.commands.push(Command::SetInsertMode(enabled)),
+                        Some(20) => self.commands.push(Command::SetLineFeedMode(enabled)),
+                        _ => {}
+                    }
+                }
+            }
             'n' => {
                 let n = *params.iter().next().and_then(|p| p.first()).unwrap_or(&0);
                 if n == 6 {
                     self.commands.push(Command::CursorPositionReport);
                 }
             }
+            't' => {
+                let n = *params.iter().next().and_then(|p| p.first()).unwrap_or(&0);
+                if n == 14 {
+                    self.commands.push(Command::TextAreaPixelSizeReport);
+                }
+            }
             'r' => {
                 // DECSTBM - Set Top and Bottom Margins
                 let mut iter = params.iter();
@@ -327,6 +731,19 @@ impl Perform for AnsiPerformer {
                     self.commands.push(Command::SetCharset(Charset::Ascii));
                     return;
                 }
+                (b'#', b'3'..=b'6') => {
+                    self.commands.push(Command::SetLineAttr(match byte {
+                        b'3' => LineAttr::DoubleHeightTop,
+                        b'4' => LineAttr::DoubleHeightBottom,
+                        b'5' => LineAttr::Single,
+                        _ => LineAttr::DoubleWidth,
+                    }));
+                    return;
+                }
+                (b' ', b'F' | b'G') => {
+                    self.commands.push(Command::SetC1Transmission(byte == b'G'));
+                    return;
+                }
                 _ => {}
             }
         }
@@ -336,6 +753,9 @@ impl Perform for AnsiPerformer {
             b'7' => self.commands.push(Command::SaveCursor), // DECSC
             b'8' => self.commands.push(Command::RestoreCursor), // DECRC
             b'H' => self.commands.push(Command::SetTabStop), // HTS
+            b'E' => self.commands.push(Command::Execute(0x85)), // NEL
+            b'=' => self.commands.push(Command::SetApplicationKeypad(true)), // DECKPAM
+            b'>' => self.commands.push(Command::SetApplicationKeypad(false)), // DECKPNM
             _ => {}
         }
     }
@@ -343,215 +763,314 @@ impl Perform for AnsiPerformer {
 
 impl AnsiPerformer {
     fn handle_private_mode(&mut self, params: &Params, c: char) {
-        let mode = params
-            .iter()
-            .next()
-            .and_then(|p| p.first())
-            .copied()
-            .unwrap_or(0);
-        match (mode, c) {
-            (1049, 'h') => self.commands.push(Command::EnterAlternateScreen),
-            (1049, 'l') => self.commands.push(Command::ExitAlternateScreen),
-            (47, 'h') | (1047, 'h') => self.commands.push(Command::EnterAlternateScreen),
-            (47, 'l') | (1047, 'l') => self.commands.push(Command::ExitAlternateScreen),
-            (2004, 'h') => self.commands.push(Command::SetBracketedPaste(true)),
-            (2004, 'l') => self.commands.push(Command::SetBracketedPaste(false)),
-            (25, 'h') => self.commands.push(Command::ShowCursor),
-            (25, 'l') => self.commands.push(Command::HideCursor),
-            // Mouse modes
-            (9, 'h') | (1000, 'h') => self
-                .commands
-                .push(Command::SetMouseMode(MouseMode::ReportClick)),
-            (1002, 'h') => self
+        let mut modes = params.iter().filter_map(|p| p.first().copied());
+        match c {
+            'h' | 'l' => {
+                let set = c == 'h';
+                for mode in modes {
+                    let cmd = Command::private_mode(mode, set)
+                        .unwrap_or(Command::UnknownPrivateMode { mode, set });
+                    self.commands.push(cmd);
+                }
+            }
+            'J' | 'K' => {
+                let n = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(0) as u8;
+                self.commands.push(if c == 'J' {
+                    Command::SelectiveEraseDisplay(n)
+                } else {
+                    Command::SelectiveEraseLine(n)
+                });
+            }
+            // DECDSR
+            'n' => match modes.next() {
+                Some(6) => self.commands.push(Command::ExtendedCursorPositionReport),
+                Some(15) => self.commands.push(Command::PrinterStatusReport),
+                _ => {}
+            },
+            // XTSAVE / XTRESTORE
+            's' => self
                 .commands
-                .push(Command::SetMouseMode(MouseMode::ReportMotion)),
-            (1003, 'h') => self
+                .push(Command::SavePrivateModes(modes.collect())),
+            'r' => self
                 .commands
-                .push(Command::SetMouseMode(MouseMode::ReportAll)),
-            (9, 'l') | (1000, 'l') | (1002, 'l') | (1003, 'l') => {
-                self.commands.push(Command::SetMouseMode(MouseMode::None))
-            }
-            // Focus events
-            (1004, 'h') => self.commands.push(Command::SetFocusEvents(true)),
-            (1004, 'l') => self.commands.push(Command::SetFocusEvents(false)),
-            // Origin Mode (DECOM)
-            (6, 'h') => self.commands.push(Command::SetOriginMode(true)),
-            (6, 'l') => self.commands.push(Command::SetOriginMode(false)),
-            // Auto-Wrap Mode (DECAWM)
-            (7, 'h') => self.commands.push(Command::SetAutoWrapMode(true)),
-            (7, 'l') => self.commands.push(Command::SetAutoWrapMode(false)),
+                .push(Command::RestorePrivateModes(modes.collect())),
+            _ => {}
+        }
+    }
+
+    /// CSI with the `"` intermediate. Kept separate so these never fall
+    /// through to the plain CSI finals (`"p` is not CSI p).
+    fn handle_quote_intermediate(&mut self, params: &Params, c: char) {
+        let n = *params.iter().next().and_then(|p| p.first()).unwrap_or(&0);
+        match c {
+            // DECSCA: 1 protects, 0 and 2 unprotect
+            'q' => self.commands.push(Command::SetCharacterProtection(n == 1)),
+            // DECSCL: we always behave as a VT500-level terminal
+            'p' => {}
             _ => {}
         }
     }
 
     fn handle_sgr(&mut self, params: &Params) {
+        // Keep colon subparameters grouped with their parameter
+        let groups: Vec<&[u16]> = params.iter().collect();
+        let before = self.current_style;
+        let reset = self.reset_style();
+        let mut style = self.current_style;
+        style.apply_sgr_groups_with(&groups, &|n| self.palette(n), reset);
+        self.current_style = style;
+
+        if self.current_style.fg != before.fg {
+            self.commands
+                .push(Command::SetForeground(self.current_style.fg));
+        }
+        if self.current_style.bg != before.bg {
+            self.commands
+                .push(Command::SetBackground(self.current_style.bg));
+        }
+        self.commands.push(Command::SetStyle(self.current_style));
+    }
+}
+
+impl CellStyle {
+    /// Apply a flat list of semicolon-separated SGR parameters (the numbers
+    /// between `CSI` and `m`) to this style. An empty list resets, like `CSI m`.
+    pub fn apply_sgr(&mut self, params: &[u16]) {
+        let groups: Vec<&[u16]> = params.chunks(1).collect();
+        self.apply_sgr_groups(&groups);
+    }
+
+    /// The SGR parameters that set this style from any state, starting with
+    /// a reset: `0;1;31` for bold red. Colors from the 16-color palette use
+    /// their short codes; anything else is sent as truecolor.
+    pub fn sgr_params(&self) -> String {
+        self.sgr_params_with(&Theme::default())
+    }
+
+    /// [`sgr_params`](Self::sgr_params) against `theme`: its foreground and
+    /// background need no parameter, and its 16 colors get the short codes
+    pub fn sgr_params_with(&self, theme: &Theme) -> String {
+        let default = CellStyle {
+            fg: theme.foreground,
+            bg: theme.background,
+            ..CellStyle::default()
+        };
+        let mut params = vec!["0".to_string()];
+        let flags = [(self.bold, "1"), (self.dim, "2"), (self.italic, "3")];
+        params.extend(
+            flags
+                .iter()
+                .filter(|(on, _)| *on)
+                .map(|(_, p)| p.to_string()),
+        );
+        match self.underline {
+            UnderlineStyle::None => {}
+            UnderlineStyle::Single => params.push("4".to_string()),
+            UnderlineStyle::Double => params.push("4:2".to_string()),
+            UnderlineStyle::Curly => params.push("4:3".to_string()),
+            UnderlineStyle::Dotted => params.push("4:4".to_string()),
+            UnderlineStyle::Dashed => params.push("4:5".to_string()),
+        }
+        let flags = [
+            (self.reverse, "7"),
+            (self.hidden, "8"),
+            (self.strikethrough, "9"),
+        ];
+        params.extend(
+            flags
+                .iter()
+                .filter(|(on, _)| *on)
+                .map(|(_, p)| p.to_string()),
+        );
+        if self.font != 0 {
+            params.push((10 + u16::from(self.font)).to_string());
+        }
+        if self.fg != default.fg {
+            params.push(color_param(self.fg, 30, &theme.ansi));
+        }
+        if self.bg != default.bg {
+            params.push(color_param(self.bg, 40, &theme.ansi));
+        }
+        if let Some(c) = self.underline_color {
+            params.push(format!("58;2;{};{};{}", c.r, c.g, c.b));
+        }
+        params.join(";")
+    }
+
+    /// Apply SGR parameters with their colon subparameters kept together,
+    /// e.g. `CSI 4:3;38:2::255:0:0 m` is `[[4, 3], [38, 2, 0, 255, 0, 0]]`.
+    pub fn apply_sgr_groups(&mut self, params: &[&[u16]]) {
+        let theme = Theme::default();
+        self.apply_sgr_groups_with(params, &|n| theme.color(n), CellStyle::default());
+    }
+
+    /// [`apply_sgr_groups`](Self::apply_sgr_groups) with palette colors
+    /// looked up in `palette`, and SGR 0/39/49 restoring the colors of `reset`
+    pub(crate) fn apply_sgr_groups_with(
+        &mut self,
+        params: &[&[u16]],
+        palette: &dyn Fn(u8) -> Color,
+        reset: CellStyle,
+    ) {
         if params.is_empty() {
-            self.current_style = CellStyle::default();
-            self.commands.push(Command::SetStyle(self.current_style));
+            *self = reset;
             return;
         }
 
-        let flat: Vec<u16> = params.iter().flat_map(|p| p.iter().copied()).collect();
         let mut i = 0;
-
-        while i < flat.len() {
-            let p = flat[i];
+        while i < params.len() {
+            let group = params[i];
+            let Some(&p) = group.first() else {
+                i += 1;
+                continue;
+            };
             match p {
-                0 => self.current_style = CellStyle::default(),
-                1 => self.current_style.bold = true,
-                2 => self.current_style.dim = true,
-                3 => self.current_style.italic = true,
+                0 => *self = reset,
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
                 4 => {
-                    // Check for SGR 4:x subparam
-                    if i + 1 < flat.len() && flat[i + 1] <= 5 {
-                        let sub = flat[i + 1];
-                        self.current_style.underline = match sub {
-                            0 => UnderlineStyle::None,
-                            1 => UnderlineStyle::Single,
-                            2 => UnderlineStyle::Double,
-                            3 => UnderlineStyle::Curly,
-                            4 => UnderlineStyle::Dotted,
-                            5 => UnderlineStyle::Dashed,
-                            _ => UnderlineStyle::Single,
-                        };
-                        i += 1;
-                    } else {
-                        self.current_style.underline = UnderlineStyle::Single;
-                    }
+                    // SGR 4:x picks the underline style
+                    self.underline = match group.get(1) {
+                        Some(0) => UnderlineStyle::None,
+                        Some(2) => UnderlineStyle::Double,
+                        Some(3) => UnderlineStyle::Curly,
+                        Some(4) => UnderlineStyle::Dotted,
+                        Some(5) => UnderlineStyle::Dashed,
+                        _ => UnderlineStyle::Single,
+                    };
                 }
-                7 => self.current_style.reverse = true,
-                8 => self.current_style.hidden = true,
-                9 => self.current_style.strikethrough = true,
+                7 => self.reverse = true,
+                8 => self.hidden = true,
+                9 => self.strikethrough = true,
+                10..=19 => self.font = (p - 10) as u8,
                 22 => {
-                    self.current_style.bold = false;
-                    self.current_style.dim = false;
-                }
-                23 => self.current_style.italic = false,
-                24 => self.current_style.underline = UnderlineStyle::None,
-                27 => self.current_style.reverse = false,
-                28 => self.current_style.hidden = false,
-                29 => self.current_style.strikethrough = false,
-                30..=37 => {
-                    let color = ansi_color(p - 30);
-                    self.current_style.fg = color;
-                    self.commands.push(Command::SetForeground(color));
+                    self.bold = false;
+                    self.dim = false;
                 }
+                23 => self.italic = false,
+                24 => self.underline = UnderlineStyle::None,
+                27 => self.reverse = false,
+                28 => self.hidden = false,
+                29 => self.strikethrough = false,
+                30..=37 => self.fg = palette((p - 30) as u8),
                 38 => {
-                    if let Some(color) = self.parse_extended_color(&flat, &mut i) {
-                        self.current_style.fg = color;
-                        self.commands.push(Command::SetForeground(color));
+                    if let Some(color) = parse_extended_color(params, &mut i, palette) {
+                        self.fg = color;
                     }
                 }
-                39 => {
-                    self.current_style.fg = Color::WHITE;
-                    self.commands.push(Command::SetForeground(Color::WHITE));
-                }
-                40..=47 => {
-                    let color = ansi_color(p - 40);
-                    self.current_style.bg = color;
-                    self.commands.push(Command::SetBackground(color));
-                }
+                39 => self.fg = reset.fg,
+                40..=47 => self.bg = palette((p - 40) as u8),
                 48 => {
-                    if let Some(color) = self.parse_extended_color(&flat, &mut i) {
-                        self.current_style.bg = color;
-                        self.commands.push(Command::SetBackground(color));
+                    if let Some(color) = parse_extended_color(params, &mut i, palette) {
+                        self.bg = color;
                     }
                 }
-                49 => {
-                    self.current_style.bg = Color::BLACK;
-                    self.commands.push(Command::SetBackground(Color::BLACK));
-                }
+                49 => self.bg = reset.bg,
                 58 => {
-                    if let Some(color) = self.parse_extended_color(&flat, &mut i) {
-                        self.current_style.underline_color = Some(color);
+                    if let Some(color) = parse_extended_color(params, &mut i, palette) {
+                        self.underline_color = Some(color);
                     }
                 }
-                59 => self.current_style.underline_color = None,
-                90..=97 => {
-                    let color = ansi_bright_color(p - 90);
-                    self.current_style.fg = color;
-                    self.commands.push(Command::SetForeground(color));
-                }
-                100..=107 => {
-                    let color = ansi_bright_color(p - 100);
-                    self.current_style.bg = color;
-                    self.commands.push(Command::SetBackground(color));
-                }
+                59 => self.underline_color = None,
+                90..=97 => self.fg = palette((p - 90 + 8) as u8),
+                100..=107 => self.bg = palette((p - 100 + 8) as u8),
                 _ => {}
             }
             i += 1;
         }
-
-        self.commands.push(Command::SetStyle(self.current_style));
     }
-    fn parse_extended_color(&self, params: &[u16], i: &mut usize) -> Option<Color> {
-        let mode = params.get(*i + 1)?;
-        match *mode {
-            5 => {
-                let n = *params.get(*i + 2)? as u8;
-                *i += 2;
-                Some(color_256(n))
-            }
+}
+
+fn parse_extended_color(
+    params: &[&[u16]],
+    i: &mut usize,
+    palette: &dyn Fn(u8) -> Color,
+) -> Option<Color> {
+    let group = params[*i];
+    if group.len() > 1 {
+        // ISO 8613-6 colon form: 38:5:n or 38:2:[colorspace]:r:g:b. The
+        // colorspace id may be left out entirely, as many apps send 38:2:r:g:b.
+        return match group[1] {
+            5 => group.get(2).map(|&n| palette(n as u8)),
             2 => {
-                let r = *params.get(*i + 2)? as u8;
-                let g = *params.get(*i + 3)? as u8;
-                let b = *params.get(*i + 4)? as u8;
-                *i += 4;
-                Some(Color::new(r, g, b))
+                let rgb = if group.len() >= 6 {
+                    &group[3..6]
+                } else {
+                    group.get(2..5)?
+                };
+                Some(Color::new(rgb[0] as u8, rgb[1] as u8, rgb[2] as u8))
             }
             _ => None,
-        }
+        };
     }
-}
 
-fn ansi_color(n: u16) -> Color {
-    match n {
-        0 => Color::new(0, 0, 0),       // Black
-        1 => Color::new(205, 49, 49),   // Red
-        2 => Color::new(13, 188, 121),  // Green
-        3 => Color::new(229, 229, 16),  // Yellow
-        4 => Color::new(36, 114, 200),  // Blue
-        5 => Color::new(188, 63, 188),  // Magenta
-        6 => Color::new(17, 168, 205),  // Cyan
-        7 => Color::new(229, 229, 229), // White
-        _ => Color::WHITE,
+    // Legacy semicolon form: 38;5;n or 38;2;r;g;b
+    let base = *i;
+    let arg = |k: usize| params.get(base + k).and_then(|g| g.first()).copied();
+    match arg(1)? {
+        5 => {
+            let n = arg(2)? as u8;
+            *i += 2;
+            Some(palette(n))
+        }
+        2 => {
+            let (r, g, b) = (arg(2)? as u8, arg(3)? as u8, arg(4)? as u8);
+            *i += 4;
+            Some(Color::new(r, g, b))
+        }
+        _ => None,
     }
 }
 
-fn ansi_bright_color(n: u16) -> Color {
-    match n {
-        0 => Color::new(102, 102, 102), // Bright Black
-        1 => Color::new(241, 76, 76),   // Bright Red
-        2 => Color::new(35, 209, 139),  // Bright Green
-        3 => Color::new(245, 245, 67),  // Bright Yellow
-        4 => Color::new(59, 142, 234),  // Bright Blue
-        5 => Color::new(214, 112, 214), // Bright Magenta
-        6 => Color::new(41, 184, 219),  // Bright Cyan
-        7 => Color::new(255, 255, 255), // Bright White
-        _ => Color::WHITE,
+/// SGR for `color` as a foreground (`base` 30) or background (`base` 40),
+/// by its index in `ansi` if it's one of the 16 palette colors
+fn color_param(color: Color, base: u16, ansi: &[Color; 16]) -> String {
+    if let Some(n) = ansi[..8].iter().position(|&c| c == color) {
+        format!("{}", base + n as u16)
+    } else if let Some(n) = ansi[8..].iter().position(|&c| c == color) {
+        format!("{}", base + 60 + n as u16)
+    } else {
+        format!("{};2;{};{};{}", base + 8, color.r, color.g, color.b)
     }
 }
 
-fn color_256(n: u8) -> Color {
-    match n {
-        0..=15 => {
-            if n < 8 {
-                ansi_color(n as u16)
-            } else {
-                ansi_bright_color((n - 8) as u16)
-            }
-        }
-        16..=231 => {
-            let n = n - 16;
-            let r = (n / 36) % 6;
-            let g = (n / 6) % 6;
-            let b = n % 6;
-            let to_rgb = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
-            Color::new(to_rgb(r), to_rgb(g), to_rgb(b))
+/// X11 color specs as sent in OSC 4: `rgb:r/g/b` with 1-4 hex digits per
+/// channel, or `#rrggbb`
+fn parse_color_spec(spec: &[u8]) -> Option<Color> {
+    let spec = std::str::from_utf8(spec).ok()?;
+    if let Some(hex) = spec.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
         }
-        232..=255 => {
-            let gray = 8 + (n - 232) * 10;
-            Color::new(gray, gray, gray)
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Color::new(channel(0)?, channel(2)?, channel(4)?));
+    }
+    let mut channels = spec.strip_prefix("rgb:")?.split('/').map(|c| {
+        if c.is_empty() || c.len() > 4 {
+            return None;
         }
+        // Scale to 8 bits, so `f`, `ff` and `ffff` are all 255
+        let value = u32::from_str_radix(c, 16).ok()?;
+        let max = (1u32 << (4 * c.len())) - 1;
+        Some((value * 255 / max) as u8)
+    });
+    let color = Color::new(channels.next()??, channels.next()??, channels.next()??);
+    channels.next().is_none().then_some(color)
+}
+
+fn decode_hex(hex: &[u8]) -> Option<String> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
     }
+    let bytes = hex
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
 }
@@ -1,3 +1,5 @@
 pub mod ansi;
+pub mod theme;
 
 pub use ansi::{AnsiParser, Charset, Command, CursorStyle, MouseMode, ParseResult};
+pub use theme::Theme;
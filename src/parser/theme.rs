@@ -0,0 +1,64 @@
+use crate::core::Color;
+
+/// The colors SGR sequences resolve to: the 16-color ANSI palette and the
+/// colors SGR 39/49 restore, which blank cells also take. The cursor color
+/// is for renderers and OSC 12 queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// Black, red, green, yellow, blue, magenta, cyan, white, then the
+    /// bright variants in the same order
+    pub ansi: [Color; 16],
+    pub foreground: Color,
+    pub background: Color,
+    pub cursor: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            ansi: [
+                Color::new(0, 0, 0),
+                Color::new(205, 49, 49),
+                Color::new(13, 188, 121),
+                Color::new(229, 229, 16),
+                Color::new(36, 114, 200),
+                Color::new(188, 63, 188),
+                Color::new(17, 168, 205),
+                Color::new(229, 229, 229),
+                Color::new(102, 102, 102),
+                Color::new(241, 76, 76),
+                Color::new(35, 209, 139),
+                Color::new(245, 245, 67),
+                Color::new(59, 142, 234),
+                Color::new(214, 112, 214),
+                Color::new(41, 184, 219),
+                Color::new(255, 255, 255),
+            ],
+            foreground: Color::WHITE,
+            background: Color::BLACK,
+            cursor: Color::WHITE,
+        }
+    }
+}
+
+impl Theme {
+    /// Entry `n` of the 256-color palette: the theme's 16 colors, then the
+    /// fixed 6x6x6 cube and grayscale ramp.
+    pub fn color(&self, n: u8) -> Color {
+        match n {
+            0..=15 => self.ansi[n as usize],
+            16..=231 => {
+                let n = n - 16;
+                let r = (n / 36) % 6;
+                let g = (n / 6) % 6;
+                let b = n % 6;
+                let to_rgb = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+                Color::new(to_rgb(r), to_rgb(g), to_rgb(b))
+            }
+            232..=255 => {
+                let gray = 8 + (n - 232) * 10;
+                Color::new(gray, gray, gray)
+            }
+        }
+    }
+}
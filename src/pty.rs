@@ -1,6 +1,83 @@
-use anyhow::{Context, Result};
+use crate::capabilities::CAPABILITIES;
+use crate::error::{Error, Result};
 use portable_pty::{CommandBuilder, MasterPty, PtySize, native_pty_system};
+use std::collections::BTreeMap;
 use std::io::{Read, Write};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError, channel};
+use std::time::{Duration, Instant};
+
+fn pty_error<E>(context: &'static str) -> impl FnOnce(E) -> Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    move |e| Error::Pty {
+        context,
+        source: e.into(),
+    }
+}
+
+/// What [`Pty::spawn_with`] runs and the environment it runs it in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PtyOptions {
+    pub shell: String,
+    pub cols: u16,
+    pub rows: u16,
+    pub cwd: Option<String>,
+    pub env: BTreeMap<String, String>,
+}
+
+impl PtyOptions {
+    /// Runs `shell` with only `TERM` set
+    pub fn new(shell: &str, cols: u16, rows: u16) -> Self {
+        let mut env = BTreeMap::new();
+        env.insert("TERM".to_string(), CAPABILITIES.term.to_string());
+        Self {
+            shell: shell.to_string(),
+            cols,
+            rows,
+            cwd: None,
+            env,
+        }
+    }
+
+    /// Starts in `home` and sets `HOME`, plus `ENV` so mksh sources
+    /// `.mkshrc` from it
+    pub fn home(mut self, home: &str) -> Self {
+        self.cwd = Some(home.to_string());
+        self.env.insert("HOME".to_string(), home.to_string());
+        self.env
+            .insert("ENV".to_string(), format!("{}/.mkshrc", home));
+        self
+    }
+
+    pub fn user(self, user: &str) -> Self {
+        self.var("USER", user)
+    }
+
+    /// Points `PREFIX`, `PATH` and `LD_LIBRARY_PATH` at a package prefix so
+    /// binaries installed under `prefix/usr` run
+    pub fn prefix(self, prefix: &str) -> Self {
+        self.var("PREFIX", &format!("{}/usr", prefix))
+            .var("PATH", &format!("{}/usr/bin:/system/bin", prefix))
+            .var("LD_LIBRARY_PATH", &format!("{}/usr/lib", prefix))
+    }
+
+    pub fn var(mut self, key: &str, value: &str) -> Self {
+        self.env.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets every variable in `vars`, overriding earlier values
+    pub fn vars<K, V>(mut self, vars: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env
+            .extend(vars.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+}
 
 pub struct Pty {
     master: Box<dyn MasterPty + Send>,
@@ -17,41 +94,50 @@ impl Pty {
         home_dir: Option<&str>,
         username: Option<&str>,
     ) -> Result<Self> {
+        let mut options = PtyOptions::new(shell, cols, rows);
+        if let Some(home) = home_dir {
+            options = options.home(home);
+        }
+        if let Some(user) = username {
+            options = options.user(user);
+        }
+        Self::spawn_with(&options)
+    }
+
+    pub fn spawn_with(options: &PtyOptions) -> Result<Self> {
         let pty_system = native_pty_system();
 
         let size = PtySize {
-            rows,
-            cols,
+            rows: options.rows,
+            cols: options.cols,
             pixel_width: 0,
             pixel_height: 0,
         };
 
-        let pair = pty_system.openpty(size).context("Failed to open pty")?;
-
-        let mut cmd = CommandBuilder::new(shell);
-        cmd.env("TERM", "xterm-256color");
+        let pair = pty_system
+            .openpty(size)
+            .map_err(pty_error("Failed to open pty"))?;
 
-        if let Some(home) = home_dir {
-            cmd.env("HOME", home);
-            cmd.cwd(home);
-            // Set ENV for mksh to source .mkshrc
-            let mkshrc_path = format!("{}/.mkshrc", home);
-            cmd.env("ENV", &mkshrc_path);
+        let mut cmd = CommandBuilder::new(&options.shell);
+        for (key, value) in &options.env {
+            cmd.env(key, value);
         }
-
-        if let Some(user) = username {
-            cmd.env("USER", user);
+        if let Some(cwd) = &options.cwd {
+            cmd.cwd(cwd);
         }
 
         pair.slave
             .spawn_command(cmd)
-            .context("Failed to spawn shell")?;
+            .map_err(pty_error("Failed to spawn shell"))?;
 
         let reader = pair
             .master
             .try_clone_reader()
-            .context("Failed to clone reader")?;
-        let writer = pair.master.take_writer().context("Failed to take writer")?;
+            .map_err(pty_error("Failed to clone reader"))?;
+        let writer = pair
+            .master
+            .take_writer()
+            .map_err(pty_error("Failed to take writer"))?;
 
         Ok(Self {
             master: pair.master,
@@ -67,21 +153,115 @@ impl Pty {
     }
 
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        self.reader.read(buf).context("PTY read failed")
+        self.reader.read(buf).map_err(pty_error("PTY read failed"))
     }
 
     pub fn write(&mut self, data: &[u8]) -> Result<()> {
-        self.writer.write_all(data).context("PTY write failed")?;
-        self.writer.flush().context("PTY flush failed")
+        self.writer
+            .write_all(data)
+            .map_err(pty_error("PTY write failed"))?;
+        self.writer.flush().map_err(pty_error("PTY flush failed"))
     }
 
     pub fn resize(&mut self, cols: u16, rows: u16) -> Result<()> {
         self.size.cols = cols;
         self.size.rows = rows;
-        self.master.resize(self.size).context("PTY resize failed")
+        self.master
+            .resize(self.size)
+            .map_err(pty_error("PTY resize failed"))
+    }
+
+    /// Like [`resize`](Self::resize), also setting the window size in pixels
+    /// that programs read with `TIOCGWINSZ`
+    pub fn resize_pixels(
+        &mut self,
+        cols: u16,
+        rows: u16,
+        pixel_width: u16,
+        pixel_height: u16,
+    ) -> Result<()> {
+        self.size.pixel_width = pixel_width;
+        self.size.pixel_height = pixel_height;
+        self.resize(cols, rows)
     }
 
     pub fn size(&self) -> (u16, u16) {
         (self.size.cols, self.size.rows)
     }
+
+    /// Window size in pixels as (width, height); zero until
+    /// [`resize_pixels`](Self::resize_pixels) sets it
+    pub fn pixel_size(&self) -> (u16, u16) {
+        (self.size.pixel_width, self.size.pixel_height)
+    }
+}
+
+/// Batches PTY output so a burst of small reads reaches the engine, and
+/// the renderer, as one chunk. Once a chunk arrives, more are collected
+/// until `window` has passed since the first or `max_bytes` is reached.
+pub struct PtyCoalescer {
+    receiver: Receiver<Vec<u8>>,
+    window: Duration,
+    max_bytes: usize,
+}
+
+impl PtyCoalescer {
+    pub fn new(receiver: Receiver<Vec<u8>>, window: Duration, max_bytes: usize) -> Self {
+        Self {
+            receiver,
+            window,
+            max_bytes,
+        }
+    }
+
+    /// Reads `reader` (usually from [`Pty::take_reader`]) on a background
+    /// thread until it hits EOF or an error
+    pub fn spawn(mut reader: Box<dyn Read + Send>, window: Duration, max_bytes: usize) -> Self {
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if sender.send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Self::new(receiver, window, max_bytes)
+    }
+
+    /// Waits for output. `None` once the reader has finished and
+    /// everything it read has been handed out.
+    pub fn recv(&self) -> Option<Vec<u8>> {
+        let first = self.receiver.recv().ok()?;
+        Some(self.gather(first))
+    }
+
+    /// Like [`recv`](Self::recv), but returns `None` straight away when
+    /// no output is waiting. If some is, this still blocks for up to the
+    /// window to collect the rest of the burst.
+    pub fn try_recv(&self) -> Option<Vec<u8>> {
+        match self.receiver.try_recv() {
+            Ok(first) => Some(self.gather(first)),
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => None,
+        }
+    }
+
+    fn gather(&self, mut data: Vec<u8>) -> Vec<u8> {
+        let deadline = Instant::now() + self.window;
+        while data.len() < self.max_bytes {
+            let Some(wait) = deadline.checked_duration_since(Instant::now()) else {
+                break;
+            };
+            match self.receiver.recv_timeout(wait) {
+                Ok(chunk) => data.extend_from_slice(&chunk),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        data
+    }
 }
@@ -0,0 +1,108 @@
+use crate::TerminalEngine;
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::time::{Duration, Instant};
+
+/// One chunk of PTY output, stamped with the time since recording started.
+/// Stored one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Frame {
+    pub elapsed_ms: u64,
+    pub data: Vec<u8>,
+}
+
+/// Tees the bytes fed to a [`TerminalEngine`] into a recording
+pub struct SessionRecorder<W: Write> {
+    writer: W,
+    start: Instant,
+}
+
+impl<W: Write> SessionRecorder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            start: Instant::now(),
+        }
+    }
+
+    /// Appends `data` as a frame stamped with the current elapsed time
+    pub fn record(&mut self, data: &[u8]) -> Result<()> {
+        let frame = Frame {
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+            data: data.to_vec(),
+        };
+        serde_json::to_writer(&mut self.writer, &frame).map_err(std::io::Error::from)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Records `data`, then writes it to `engine`
+    pub fn write(&mut self, engine: &mut TerminalEngine, data: &[u8]) -> Result<()> {
+        self.record(data)?;
+        engine.write(data)
+    }
+
+    pub fn into_inner(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// How [`SessionPlayer::play`] spaces out frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pacing {
+    /// Sleep so frames land at their recorded times
+    #[default]
+    Recorded,
+    /// Feed everything back to back
+    Instant,
+}
+
+/// Reads a recording made by [`SessionRecorder`]
+pub struct SessionPlayer<R: BufRead> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> SessionPlayer<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+        }
+    }
+
+    /// The next frame, or `None` at the end of the recording. Blank lines
+    /// are skipped.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            if self.line.trim().is_empty() {
+                continue;
+            }
+            let frame = serde_json::from_str(&self.line).map_err(std::io::Error::from)?;
+            return Ok(Some(frame));
+        }
+    }
+
+    /// Writes every remaining frame to `engine`; returns how many were played
+    pub fn play(&mut self, engine: &mut TerminalEngine, pacing: Pacing) -> Result<usize> {
+        let start = Instant::now();
+        let mut played = 0;
+        while let Some(frame) = self.next_frame()? {
+            if pacing == Pacing::Recorded {
+                let due = Duration::from_millis(frame.elapsed_ms);
+                if let Some(wait) = due.checked_sub(start.elapsed()) {
+                    std::thread::sleep(wait);
+                }
+            }
+            engine.write(&frame.data)?;
+            played += 1;
+        }
+        Ok(played)
+    }
+}
@@ -0,0 +1,121 @@
+use crate::core::grid::style_runs;
+use crate::core::{Cell, CellStyle, Color, TerminalBuffer, UnderlineStyle};
+use std::fmt::Write;
+
+/// Dump the visible screen as a `<pre>` block of styled `<span>` runs.
+///
+/// Cells in the buffer's default style are emitted as plain text, and
+/// trailing blank cells on each row are dropped.
+pub fn to_html(buffer: &TerminalBuffer) -> String {
+    let grid = buffer.grid();
+    let default = buffer.default_style();
+    let mut html = String::from("<pre>");
+
+    for y in 0..grid.height() {
+        if y > 0 {
+            html.push('\n');
+        }
+        let Some(row) = grid.row(y) else {
+            continue;
+        };
+
+        let end = row
+            .iter()
+            .rposition(|c| c.character != ' ' || c.style != default)
+            .map_or(0, |i| i + 1);
+
+        let mut run = String::new();
+        for (range, style) in style_runs(&row[..end]) {
+            run.clear();
+            for cell in row[range].iter().filter(|c| !c.wide_spacer) {
+                push_cell_text(&mut run, cell);
+            }
+            if !run.is_empty() {
+                push_run(&mut html, &style, &default, &run);
+            }
+        }
+    }
+
+    html.push_str("</pre>");
+    html
+}
+
+fn push_cell_text(out: &mut String, cell: &Cell) {
+    for c in std::iter::once(cell.character).chain(cell.zerowidth.iter().copied()) {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+fn push_run(html: &mut String, style: &CellStyle, default: &CellStyle, text: &str) {
+    let css = style_css(style, default);
+    if css.is_empty() {
+        html.push_str(text);
+    } else {
+        let _ = write!(html, "<span style=\"{}\">{}</span>", css, text);
+    }
+}
+
+fn style_css(style: &CellStyle, default: &CellStyle) -> String {
+    let (mut fg, bg) = if style.reverse {
+        (style.bg, style.fg)
+    } else {
+        (style.fg, style.bg)
+    };
+    if style.hidden {
+        fg = bg;
+    }
+
+    let mut css = Vec::new();
+    if fg != default.fg || style.reverse || style.hidden {
+        css.push(format!("color:{}", hex(fg)));
+    }
+    if bg != default.bg || style.reverse {
+        css.push(format!("background:{}", hex(bg)));
+    }
+    if style.bold {
+        css.push("font-weight:bold".to_string());
+    }
+    if style.italic {
+        css.push("font-style:italic".to_string());
+    }
+    if style.dim {
+        css.push("opacity:0.5".to_string());
+    }
+
+    let mut decorations = Vec::new();
+    if style.underline != UnderlineStyle::None {
+        decorations.push("underline");
+    }
+    if style.strikethrough {
+        decorations.push("line-through");
+    }
+    if !decorations.is_empty() {
+        css.push(format!("text-decoration:{}", decorations.join(" ")));
+        let line_style = match style.underline {
+            UnderlineStyle::Double => Some("double"),
+            UnderlineStyle::Curly => Some("wavy"),
+            UnderlineStyle::Dotted => Some("dotted"),
+            UnderlineStyle::Dashed => Some("dashed"),
+            UnderlineStyle::None | UnderlineStyle::Single => None,
+        };
+        if let Some(line_style) = line_style {
+            css.push(format!("text-decoration-style:{}", line_style));
+        }
+        if let Some(color) = style.underline_color {
+            css.push(format!("text-decoration-color:{}", hex(color)));
+        }
+    }
+
+    css.join(";")
+}
+
+fn hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
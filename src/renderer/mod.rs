@@ -1,6 +1,8 @@
+pub mod html;
 pub mod screen;
+pub mod text;
 use crate::core::TerminalBuffer;
-use anyhow::Result;
+use crate::error::Result;
 
 pub struct RenderContext<'a> {
     pub buffer: &'a TerminalBuffer,
@@ -10,6 +12,18 @@ pub struct RenderContext<'a> {
 
 pub trait Renderer {
     fn render(&mut self, context: &RenderContext) -> Result<()>;
+
+    /// Cell width and height in px, for renderers that draw to pixels
+    fn cell_size(&self) -> Option<(f32, f32)> {
+        None
+    }
+
+    /// Tells a pixel renderer the cell size the frontend actually measured;
+    /// others ignore it
+    fn set_cell_size(&mut self, _width: f32, _height: f32) {}
 }
 
-pub use screen::{ScreenRenderer, AndroidRenderer};
+pub use html::to_html;
+pub use screen::{
+    AndroidRenderer, FontFace, GlyphChoice, GlyphFallback, GlyphStyle, ScreenRenderer,
+};
@@ -1,5 +1,5 @@
 use super::{RenderContext, Renderer};
-use anyhow::Result;
+use crate::error::Result;
 
 pub struct ScreenRenderer {
     dirty: bool,
@@ -39,10 +39,76 @@ impl Renderer for ScreenRenderer {
     }
 }
 
+/// Which embedded font face a glyph is drawn from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontFace {
+    Regular,
+    Bold,
+}
+
+/// Horizontal smear (px) used to embolden the regular face when no bold face is loaded
+pub const SYNTHETIC_BOLD_SMEAR: f32 = 1.0;
+/// Shear applied to italic glyphs, as passed to Android's `Paint.setTextSkewX`
+pub const ITALIC_SKEW_X: f32 = -0.25;
+
+/// How a cell's glyph should be drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlyphStyle {
+    pub face: FontFace,
+    /// Extra horizontal strike offset in px, 0 unless bold is synthesized
+    pub smear: f32,
+    /// Horizontal shear, 0 unless the cell is italic
+    pub skew_x: f32,
+}
+
+/// Monospace advance width as a fraction of the font size
+pub const CELL_WIDTH_RATIO: f32 = 0.6;
+/// Line height as a fraction of the font size
+pub const LINE_HEIGHT_RATIO: f32 = 1.2;
+
+/// Estimated cell width and height in px for a font size, until the
+/// frontend reports the size it measured
+pub fn cell_size(font_size: f32) -> (f32, f32) {
+    (font_size * CELL_WIDTH_RATIO, font_size * LINE_HEIGHT_RATIO)
+}
+
+/// Maps a pixel to the (column, row) of the cell containing it; negative
+/// coordinates clamp to 0
+pub fn cell_at_pixel((cell_width, cell_height): (f32, f32), px: f32, py: f32) -> (usize, usize) {
+    if cell_width <= 0.0 || cell_height <= 0.0 {
+        return (0, 0);
+    }
+    let col = (px / cell_width).floor().max(0.0) as usize;
+    let row = (py / cell_height).floor().max(0.0) as usize;
+    (col, row)
+}
+
+/// What to draw for a char the embedded font has no glyph for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlyphFallback {
+    /// The font's `.notdef` box
+    #[default]
+    Notdef,
+    Space,
+    /// A replacement char, falling back to `.notdef` if it is missing too
+    Replacement(char),
+}
+
+/// The glyph the renderer settled on for a cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlyphChoice {
+    Char(char),
+    Notdef,
+}
+
 #[warn(dead_code)]
 pub struct AndroidRenderer {
     canvas_ptr: Option<usize>,
     font_size: f32,
+    cell_size: (f32, f32),
+    regular_font: Option<&'static [u8]>,
+    bold_font: Option<&'static [u8]>,
+    glyph_fallback: GlyphFallback,
     dirty: bool,
 }
 
@@ -51,10 +117,72 @@ impl AndroidRenderer {
         Self {
             canvas_ptr: None,
             font_size,
+            cell_size: cell_size(font_size),
+            regular_font: None,
+            bold_font: None,
+            glyph_fallback: GlyphFallback::default(),
             dirty: true,
         }
     }
 
+    /// Sets the embedded font faces. Without a bold face, bold cells are
+    /// drawn from the regular face with a [`SYNTHETIC_BOLD_SMEAR`].
+    pub fn set_fonts(&mut self, regular: &'static [u8], bold: Option<&'static [u8]>) {
+        self.regular_font = Some(regular);
+        self.bold_font = bold;
+        self.dirty = true;
+    }
+
+    pub fn font_data(&self, face: FontFace) -> Option<&'static [u8]> {
+        match face {
+            FontFace::Regular => self.regular_font,
+            FontFace::Bold => self.bold_font,
+        }
+    }
+
+    pub fn glyph_fallback(&self) -> GlyphFallback {
+        self.glyph_fallback
+    }
+
+    pub fn set_glyph_fallback(&mut self, fallback: GlyphFallback) {
+        self.glyph_fallback = fallback;
+        self.dirty = true;
+    }
+
+    /// Picks what to draw for `c`; `has_glyph` reports whether the loaded
+    /// font covers a char. Control chars always take the fallback.
+    pub fn choose_glyph(&self, c: char, has_glyph: impl Fn(char) -> bool) -> GlyphChoice {
+        if !c.is_control() && has_glyph(c) {
+            return GlyphChoice::Char(c);
+        }
+        match self.glyph_fallback {
+            GlyphFallback::Notdef => GlyphChoice::Notdef,
+            GlyphFallback::Space => GlyphChoice::Char(' '),
+            GlyphFallback::Replacement(r) if !r.is_control() && has_glyph(r) => {
+                GlyphChoice::Char(r)
+            }
+            GlyphFallback::Replacement(_) => GlyphChoice::Notdef,
+        }
+    }
+
+    /// Picks the face, emboldening and shear for a cell from its style
+    pub fn glyph_style(&self, cell: &crate::core::Cell) -> GlyphStyle {
+        let (face, smear) = match (cell.style.bold, self.bold_font.is_some()) {
+            (true, true) => (FontFace::Bold, 0.0),
+            (true, false) => (FontFace::Regular, SYNTHETIC_BOLD_SMEAR),
+            (false, _) => (FontFace::Regular, 0.0),
+        };
+        GlyphStyle {
+            face,
+            smear,
+            skew_x: if cell.style.italic {
+                ITALIC_SKEW_X
+            } else {
+                0.0
+            },
+        }
+    }
+
     pub fn set_canvas(&mut self, canvas_ptr: usize) {
         self.canvas_ptr = Some(canvas_ptr);
         self.dirty = true;
@@ -63,6 +191,26 @@ impl AndroidRenderer {
     pub fn mark_dirty(&mut self) {
         self.dirty = true;
     }
+
+    pub fn font_size(&self) -> f32 {
+        self.font_size
+    }
+
+    /// The measured cell size if the frontend has set one, otherwise the
+    /// estimate from [`cell_size`]
+    pub fn cell_size(&self) -> (f32, f32) {
+        self.cell_size
+    }
+
+    pub fn set_cell_size(&mut self, width: f32, height: f32) {
+        self.cell_size = (width, height);
+        self.dirty = true;
+    }
+
+    /// The cell under a pixel, for touch handling; see [`cell_at_pixel`]
+    pub fn cell_at_pixel(&self, px: f32, py: f32) -> (usize, usize) {
+        cell_at_pixel(self.cell_size, px, py)
+    }
 }
 
 impl Renderer for AndroidRenderer {
@@ -90,6 +238,14 @@ impl Renderer for AndroidRenderer {
         self.dirty = false;
         Ok(())
     }
+
+    fn cell_size(&self) -> Option<(f32, f32)> {
+        Some(AndroidRenderer::cell_size(self))
+    }
+
+    fn set_cell_size(&mut self, width: f32, height: f32) {
+        AndroidRenderer::set_cell_size(self, width, height);
+    }
 }
 
 impl AndroidRenderer {
@@ -100,7 +256,8 @@ impl AndroidRenderer {
         cell: &crate::core::Cell,
         is_cursor: bool,
     ) -> Result<()> {
-        let _ = (x, y, cell, is_cursor);
+        let glyph = self.glyph_style(cell);
+        let _ = (x, y, glyph, is_cursor);
         Ok(())
     }
 }
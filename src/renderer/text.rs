@@ -0,0 +1,35 @@
+use crate::core::grid::style_runs;
+use crate::core::{Cell, CellStyle};
+use crate::parser::Theme;
+use std::fmt::Write;
+
+/// Append one grid row as text, optionally with SGR sequences for its
+/// styles against `theme`. Trailing blank cells in the theme's colors are
+/// dropped, as in [`to_html`](super::to_html), and a styled row always ends
+/// with the attributes reset.
+pub fn push_row(out: &mut String, row: &[Cell], include_styles: bool, theme: &Theme) {
+    let default = CellStyle {
+        fg: theme.foreground,
+        bg: theme.background,
+        ..CellStyle::default()
+    };
+    let end = row
+        .iter()
+        .rposition(|c| c.character != ' ' || c.style != default)
+        .map_or(0, |i| i + 1);
+
+    let mut current = default;
+    for (range, style) in style_runs(&row[..end]) {
+        if include_styles && style != current {
+            let _ = write!(out, "\x1b[{}m", style.sgr_params_with(theme));
+            current = style;
+        }
+        for cell in row[range].iter().filter(|c| !c.wide_spacer) {
+            out.push(cell.character);
+            out.extend(cell.zerowidth.iter());
+        }
+    }
+    if current != default {
+        out.push_str("\x1b[0m");
+    }
+}
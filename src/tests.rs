@@ -1,6 +1,7 @@
 #[cfg(test)]
 mod scrollback_tests {
     use crate::core::TerminalBuffer;
+    use crate::parser::Command;
 
     #[test]
     fn test_scrollback_initially_empty() {
@@ -45,6 +46,185 @@ mod scrollback_tests {
         buffer.scroll_to_bottom();
         assert_eq!(buffer.scroll_offset(), 0);
     }
+
+    #[test]
+    fn test_scroll_page_moves_by_height() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        for _ in 0..20 {
+            buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        }
+        assert_eq!(buffer.scrollback_len(), 18);
+
+        buffer.scroll_page_up();
+        assert_eq!(buffer.scroll_offset(), 3);
+        buffer.scroll_page_up();
+        assert_eq!(buffer.scroll_offset(), 6);
+        buffer.scroll_page_down();
+        assert_eq!(buffer.scroll_offset(), 3);
+    }
+
+    #[test]
+    fn test_scrolling_up_disables_follow_mode() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        for _ in 0..20 {
+            buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        }
+        assert!(buffer.auto_scroll());
+
+        buffer.scroll_by(5);
+        assert!(!buffer.auto_scroll());
+        assert!(!buffer.has_new_output_while_scrolled());
+
+        buffer.execute_command(Command::Print('x')).unwrap();
+        assert_eq!(buffer.scroll_offset(), 5);
+        assert!(buffer.has_new_output_while_scrolled());
+
+        buffer.scroll_to_bottom();
+        assert!(buffer.auto_scroll());
+        assert!(!buffer.has_new_output_while_scrolled());
+    }
+
+    #[test]
+    fn test_follow_mode_snaps_to_bottom() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        for _ in 0..20 {
+            buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        }
+
+        buffer.scroll_by(5);
+        buffer.set_auto_scroll(true);
+        buffer.execute_command(Command::Print('x')).unwrap();
+        assert_eq!(buffer.scroll_offset(), 0);
+        assert!(!buffer.has_new_output_while_scrolled());
+    }
+
+    #[test]
+    fn test_viewport_cursor() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        for _ in 0..10 {
+            buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        }
+        buffer.execute_command(Command::MoveCursor(3, 2)).unwrap();
+        assert_eq!(buffer.viewport_cursor(), Some((3, 2)));
+
+        buffer.scroll_by(2);
+        assert_eq!(buffer.viewport_cursor(), Some((3, 4)));
+
+        buffer.scroll_by(1);
+        assert_eq!(buffer.viewport_cursor(), None);
+    }
+
+    fn text(row: Option<&[crate::core::Cell]>) -> String {
+        let row = row.unwrap().iter().map(|c| c.character);
+        row.collect::<String>().trim_end().to_string()
+    }
+
+    fn write_lines(buffer: &mut TerminalBuffer, lines: impl IntoIterator<Item = String>) {
+        for line in lines {
+            buffer.write_str(&line).unwrap();
+            buffer.execute_command(Command::Execute(b'\r')).unwrap();
+            buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_resize_keeps_the_top_visible_line() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        write_lines(&mut buffer, (0..12).map(|i| format!("line {i:02}")));
+        // Long lines on screen so narrowing pushes extra rows into history
+        write_lines(&mut buffer, (0..3).map(|i| format!("{i}").repeat(7)));
+        buffer.scroll_by(3);
+        let top = text(buffer.viewport_row(0));
+        assert_eq!(top, "line 08");
+
+        buffer.resize(5, 5).unwrap();
+        assert!(buffer.scroll_offset() > 3);
+        assert_eq!(text(buffer.viewport_row(0)), top);
+
+        buffer.resize(10, 5).unwrap();
+        assert_eq!(text(buffer.viewport_row(0)), top);
+    }
+
+    #[test]
+    fn test_resize_at_scrollback_limit_keeps_the_top_visible_line() {
+        let mut buffer = TerminalBuffer::new(10, 4);
+        buffer.set_scrollback_limit(6);
+        write_lines(&mut buffer, (0..10).map(|i| format!("line {i:02}")));
+        write_lines(&mut buffer, (0..2).map(|i| format!("{i}").repeat(7)));
+        assert_eq!(buffer.scrollback_len(), 6);
+        buffer.scroll_by(2);
+        let top = text(buffer.viewport_row(0));
+        assert_eq!(top, "line 07");
+
+        // The three rows the reflow pushes evict the oldest history, which
+        // the view's top line survives
+        buffer.resize(5, 4).unwrap();
+        assert_eq!(buffer.scrollback_len(), 6);
+        assert_eq!(text(buffer.viewport_row(0)), top);
+
+        // Once the top line itself is evicted the view stops at the oldest
+        buffer.scroll_to(6);
+        buffer.resize(3, 4).unwrap();
+        assert_eq!(buffer.scroll_offset(), 6);
+    }
+
+    #[test]
+    fn test_scroll_page_clamps() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        for _ in 0..20 {
+            buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        }
+
+        buffer.scroll_to(17);
+        buffer.scroll_page_up();
+        assert_eq!(buffer.scroll_offset(), 18);
+
+        buffer.scroll_to(2);
+        buffer.scroll_page_down();
+        assert_eq!(buffer.scroll_offset(), 0);
+    }
+
+    #[test]
+    fn test_height_resize_keeps_prompt_on_screen() {
+        let mut parser = crate::parser::AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(10, 5);
+        crate::feed(&mut buffer, &mut parser, b"a\r\nb\r\nc\r\n$ ").unwrap();
+
+        buffer.resize(10, 2).unwrap();
+        assert_eq!(buffer.grid().get(0, 1).unwrap().character, '$');
+        assert_eq!(buffer.cursor_pos(), (2, 1));
+        assert_eq!(buffer.scrollback_len(), 2);
+
+        buffer.resize(10, 5).unwrap();
+        assert_eq!(buffer.scrollback_len(), 0);
+        assert_eq!(buffer.grid().get(0, 0).unwrap().character, 'a');
+        assert_eq!(buffer.cursor_pos(), (2, 3));
+    }
+
+    #[test]
+    fn test_clear_screen_can_keep_scrollback() {
+        let mut parser = crate::parser::AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(10, 5);
+        crate::feed(&mut buffer, &mut parser, b"one\r\ntwo\r\nthree\x1b[2J").unwrap();
+        assert_eq!(buffer.scrollback_len(), 0);
+
+        buffer.set_scrollback_on_clear(true);
+        crate::feed(&mut buffer, &mut parser, b"one\r\ntwo\r\nthree\x1b[2J").unwrap();
+        assert_eq!(buffer.scrollback_len(), 3);
+        assert_eq!(buffer.scrollback_row(0).unwrap()[0].character, 'o');
+        assert_eq!(buffer.scrollback_row(2).unwrap()[0].character, 't');
+        assert_eq!(buffer.grid().last_nonblank_row(), None);
+        assert_eq!(buffer.cursor_pos(), (0, 0));
+
+        // A blank screen adds nothing, and neither does the alternate screen
+        crate::feed(
+            &mut buffer,
+            &mut parser,
+            b"\x1b[2J\x1b[?1049hxyz\x1b[2J\x1b[?1049l",
+        )
+        .unwrap();
+        assert_eq!(buffer.scrollback_len(), 3);
+    }
 }
 
 #[cfg(test)]
@@ -122,6 +302,22 @@ mod alternate_screen_tests {
         assert_eq!(buffer.cursor_pos(), (x, y)); // Cursor restored
     }
 
+    #[test]
+    fn test_primary_grid_readable_from_alternate() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        buffer.write_str("$ vim").unwrap();
+        assert!(buffer.primary_grid().is_none());
+
+        buffer.enter_alternate_screen();
+        buffer.write_str("editor").unwrap();
+        let primary = buffer.primary_grid().unwrap();
+        assert_eq!(primary.get(2, 0).unwrap().character, 'v');
+        assert_eq!(buffer.grid().get(0, 0).unwrap().character, 'e');
+
+        buffer.exit_alternate_screen();
+        assert!(buffer.primary_grid().is_none());
+    }
+
     #[test]
     fn test_double_enter_noop() {
         let mut buffer = TerminalBuffer::new(80, 24);
@@ -132,6 +328,54 @@ mod alternate_screen_tests {
         buffer.exit_alternate_screen();
         assert!(!buffer.is_alternate_screen());
     }
+
+    #[test]
+    fn test_resize_on_alternate_screen_resizes_primary() {
+        let mut buffer = TerminalBuffer::new(20, 10);
+        buffer.write_str("primary").unwrap();
+        buffer
+            .execute_command(crate::parser::Command::MoveCursor(15, 8))
+            .unwrap();
+        buffer.enter_alternate_screen();
+        buffer.resize(8, 4).unwrap();
+        buffer.resize(12, 5).unwrap();
+        buffer.exit_alternate_screen();
+
+        assert_eq!(buffer.grid().width(), 12);
+        assert_eq!(buffer.grid().height(), 5);
+        let (x, y) = buffer.cursor_pos();
+        assert!(x < 12 && y < 5, "cursor at {:?}", (x, y));
+        buffer.write_str("ok").unwrap();
+
+        // The primary text survived, rewrapped or scrolled off as needed
+        let mut text: String = (0..buffer.scrollback_len())
+            .flat_map(|i| buffer.scrollback_row(i).unwrap().iter())
+            .chain((0..5).flat_map(|y| buffer.grid().row(y).unwrap().iter()))
+            .map(|c| c.character)
+            .collect();
+        text.retain(|c| c != ' ');
+        assert!(text.starts_with("primary"), "{}", text);
+    }
+
+    #[test]
+    fn test_alternate_screen_keeps_no_scrollback() {
+        use crate::parser::Command;
+
+        let mut buffer = TerminalBuffer::new(10, 3);
+        buffer.enter_alternate_screen();
+        for _ in 0..50 {
+            buffer.write_str("line").unwrap();
+            buffer.execute_command(Command::Execute(b'\r')).unwrap();
+            buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        }
+        assert_eq!(buffer.scrollback_len(), 0);
+
+        buffer.exit_alternate_screen();
+        for _ in 0..5 {
+            buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        }
+        assert_eq!(buffer.scrollback_len(), 3);
+    }
 }
 
 #[cfg(test)]
@@ -216,6 +460,7 @@ mod parser_tests {
 
 #[cfg(test)]
 mod priority_feature_tests {
+    use crate::core::TerminalBuffer;
     use crate::parser::{AnsiParser, Charset, Command, CursorStyle};
 
     #[test]
@@ -307,4 +552,3812 @@ mod priority_feature_tests {
             "Should parse CSI 3g as ClearAllTabStops"
         );
     }
+
+    #[test]
+    fn test_tbc_2_clears_the_line_stops() {
+        let mut parser = AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"\x1b[2g").unwrap(),
+            vec![Command::ClearAllTabStops]
+        );
+        assert!(parser.parse(b"\x1b[1g\x1b[4g").unwrap().is_empty());
+
+        let mut buffer = TerminalBuffer::new(40, 2);
+        buffer
+            .execute_commands(parser.parse(b"\x1b[2g\tx").unwrap())
+            .unwrap();
+        assert_eq!(buffer.grid().get(39, 0).unwrap().character, 'x');
+    }
+}
+
+#[cfg(test)]
+mod scroll_down_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::Command;
+
+    fn row_text(buffer: &TerminalBuffer, y: usize) -> String {
+        buffer
+            .grid()
+            .row(y)
+            .unwrap()
+            .iter()
+            .map(|c| c.character)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    fn filled(height: usize) -> TerminalBuffer {
+        let mut buffer = TerminalBuffer::new(4, height);
+        for y in 0..height {
+            buffer.execute_command(Command::MoveCursor(0, y)).unwrap();
+            buffer
+                .execute_command(Command::Print((b'a' + y as u8) as char))
+                .unwrap();
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_scroll_down_shifts_rows() {
+        let mut buffer = filled(4);
+        buffer.execute_command(Command::ScrollDown(1)).unwrap();
+        assert_eq!(row_text(&buffer, 0), "");
+        assert_eq!(row_text(&buffer, 1), "a");
+        assert_eq!(row_text(&buffer, 3), "c");
+    }
+
+    #[test]
+    fn test_scroll_down_more_than_height() {
+        let mut buffer = filled(4);
+        buffer.execute_command(Command::ScrollDown(100)).unwrap();
+        for y in 0..4 {
+            assert_eq!(row_text(&buffer, y), "");
+        }
+        assert_eq!(buffer.scrollback_len(), 0);
+    }
+
+    #[test]
+    fn test_scroll_down_more_than_region() {
+        let mut buffer = filled(5);
+        buffer
+            .execute_command(Command::SetScrollRegion { top: 1, bottom: 3 })
+            .unwrap();
+        buffer.execute_command(Command::ScrollDown(10)).unwrap();
+
+        assert_eq!(row_text(&buffer, 0), "a");
+        assert_eq!(row_text(&buffer, 1), "");
+        assert_eq!(row_text(&buffer, 2), "");
+        assert_eq!(row_text(&buffer, 3), "");
+        assert_eq!(row_text(&buffer, 4), "e");
+    }
+}
+
+#[cfg(test)]
+mod line_feed_mode_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    fn feed(buffer: &mut TerminalBuffer, parser: &mut AnsiParser, data: &[u8]) {
+        for cmd in parser.parse(data).unwrap() {
+            buffer.execute_command(cmd).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_lnm() {
+        let mut parser = AnsiParser::new();
+        assert!(
+            parser
+                .parse(b"\x1b[20h")
+                .unwrap()
+                .contains(&Command::SetLineFeedMode(true))
+        );
+        assert!(
+            parser
+                .parse(b"\x1b[20l")
+                .unwrap()
+                .contains(&Command::SetLineFeedMode(false))
+        );
+    }
+
+    #[test]
+    fn test_lf_keeps_column_without_lnm() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        let mut parser = AnsiParser::new();
+        feed(&mut buffer, &mut parser, b"abc\n");
+        assert_eq!(buffer.cursor_pos(), (3, 1));
+    }
+
+    #[test]
+    fn test_lf_returns_to_column_zero_with_lnm() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        let mut parser = AnsiParser::new();
+        feed(&mut buffer, &mut parser, b"\x1b[20habc\n");
+        assert!(buffer.is_line_feed_mode());
+        assert_eq!(buffer.cursor_pos(), (0, 1));
+
+        feed(&mut buffer, &mut parser, b"\x1b[20labc\n");
+        assert_eq!(buffer.cursor_pos(), (3, 2));
+    }
+
+    #[test]
+    fn test_ris_clears_lnm_and_irm() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        let mut parser = AnsiParser::new();
+        feed(&mut buffer, &mut parser, b"\x1b[20h\x1b[4h\x1bc");
+        assert!(!buffer.is_line_feed_mode());
+        assert!(!buffer.is_insert_mode());
+        feed(&mut buffer, &mut parser, b"abc\n");
+        assert_eq!(buffer.cursor_pos(), (3, 1));
+    }
+
+    #[test]
+    fn test_print_newline_follows_lnm() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        buffer.write_str("abc").unwrap();
+        buffer.execute_command(Command::Print('\n')).unwrap();
+        assert_eq!(buffer.cursor_pos(), (3, 1));
+
+        buffer
+            .execute_command(Command::SetLineFeedMode(true))
+            .unwrap();
+        buffer.execute_command(Command::Print('\n')).unwrap();
+        assert_eq!(buffer.cursor_pos(), (0, 2));
+    }
+}
+
+#[cfg(test)]
+mod html_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::AnsiParser;
+    use crate::to_html;
+
+    fn render(data: &[u8]) -> String {
+        let mut buffer = TerminalBuffer::new(20, 2);
+        let mut parser = AnsiParser::new();
+        for cmd in parser.parse(data).unwrap() {
+            buffer.execute_command(cmd).unwrap();
+        }
+        to_html(&buffer)
+    }
+
+    #[test]
+    fn test_red_text_span() {
+        assert_eq!(
+            render(b"\x1b[31mhi\x1b[0m"),
+            "<pre><span style=\"color:#cd3131\">hi</span>\n</pre>"
+        );
+    }
+
+    #[test]
+    fn test_runs_coalesce_and_escape() {
+        let html = render(b"a<b \x1b[1;4m&c\x1b[0m");
+        assert_eq!(
+            html,
+            "<pre>a&lt;b <span style=\"font-weight:bold;text-decoration:underline\">&amp;c</span>\n</pre>"
+        );
+    }
+
+    #[test]
+    fn test_reverse_swaps_colors() {
+        let html = render(b"\x1b[7mx");
+        assert!(html.contains("<span style=\"color:#000000;background:#ffffff\">x</span>"));
+    }
+}
+
+#[cfg(test)]
+mod sgr_tests {
+    use crate::core::{CellStyle, Color, UnderlineStyle};
+    use crate::parser::{AnsiParser, Command};
+
+    #[test]
+    fn test_apply_sgr_attributes() {
+        let mut style = CellStyle::default();
+        style.apply_sgr(&[1, 3, 4, 31]);
+        assert!(style.bold);
+        assert!(style.italic);
+        assert_eq!(style.underline, UnderlineStyle::Single);
+        assert_eq!(style.fg, Color::new(205, 49, 49));
+
+        style.apply_sgr(&[22, 24]);
+        assert!(!style.bold);
+        assert!(style.italic);
+        assert_eq!(style.underline, UnderlineStyle::None);
+    }
+
+    #[test]
+    fn test_apply_sgr_extended_colors() {
+        let mut style = CellStyle::default();
+        style.apply_sgr(&[38, 2, 10, 20, 30, 48, 5, 196]);
+        assert_eq!(style.fg, Color::new(10, 20, 30));
+        assert_eq!(style.bg, Color::new(255, 0, 0));
+    }
+
+    #[test]
+    fn test_apply_sgr_reset() {
+        let mut style = CellStyle::default();
+        style.apply_sgr(&[1, 7, 42]);
+        style.apply_sgr(&[]);
+        assert_eq!(style, CellStyle::default());
+
+        style.apply_sgr(&[9, 0, 3]);
+        assert!(!style.strikethrough);
+        assert!(style.italic);
+    }
+
+    fn parsed_style(data: &[u8]) -> CellStyle {
+        let mut parser = AnsiParser::new();
+        parser
+            .parse(data)
+            .unwrap()
+            .into_iter()
+            .rev()
+            .find_map(|cmd| match cmd {
+                Command::SetStyle(style) => Some(style),
+                _ => None,
+            })
+            .expect("no SetStyle")
+    }
+
+    #[test]
+    fn test_underline_subparameter_reset() {
+        assert_eq!(parsed_style(b"\x1b[4:3m").underline, UnderlineStyle::Curly);
+        assert_eq!(
+            parsed_style(b"\x1b[4:3m\x1b[4:0m").underline,
+            UnderlineStyle::None
+        );
+
+        // `4;2` is underline plus dim, not underline style 2
+        let style = parsed_style(b"\x1b[4;2m");
+        assert_eq!(style.underline, UnderlineStyle::Single);
+        assert!(style.dim);
+    }
+
+    #[test]
+    fn test_sgr_font_selection() {
+        assert_eq!(CellStyle::default().font, 0);
+        assert_eq!(parsed_style(b"\x1b[12m").font, 2);
+        assert_eq!(parsed_style(b"\x1b[19;1m").font, 9);
+        assert_eq!(parsed_style(b"\x1b[12m\x1b[10m").font, 0);
+        assert_eq!(parsed_style(b"\x1b[12m\x1b[0m").font, 0);
+
+        let style = parsed_style(b"\x1b[1;13m");
+        assert_eq!(style.sgr_params(), "0;1;13");
+    }
+
+    #[test]
+    fn test_iso_colon_rgb_with_colorspace() {
+        let style = parsed_style(b"\x1b[38:2::255:0:0;1m");
+        assert_eq!(style.fg, Color::new(255, 0, 0));
+        assert!(style.bold);
+
+        let style = parsed_style(b"\x1b[48:2:1:10:20:30m");
+        assert_eq!(style.bg, Color::new(10, 20, 30));
+    }
+
+    #[test]
+    fn test_iso_colon_rgb_without_colorspace() {
+        let style = parsed_style(b"\x1b[38:2:255:128:0;3m");
+        assert_eq!(style.fg, Color::new(255, 128, 0));
+        assert!(style.italic);
+
+        let style = parsed_style(b"\x1b[58:2:1:2:3m");
+        assert_eq!(style.underline_color, Some(Color::new(1, 2, 3)));
+    }
+
+    #[test]
+    fn test_colon_and_semicolon_256_color() {
+        assert_eq!(parsed_style(b"\x1b[38:5:196m").fg, Color::new(255, 0, 0));
+        assert_eq!(parsed_style(b"\x1b[38;5;196m").fg, Color::new(255, 0, 0));
+        assert_eq!(
+            parsed_style(b"\x1b[38;2;255;128;0m").fg,
+            Color::new(255, 128, 0)
+        );
+    }
+}
+
+#[cfg(test)]
+mod c1_control_tests {
+    use crate::core::Color;
+    use crate::parser::{AnsiParser, Command};
+
+    fn has_red_fg(cmds: &[Command]) -> bool {
+        cmds.iter().any(
+            |c| matches!(c, Command::SetForeground(color) if *color == Color::new(205, 49, 49)),
+        )
+    }
+
+    #[test]
+    fn test_raw_8bit_csi() {
+        let mut parser = AnsiParser::new();
+        let cmds = parser.parse(b"\x9b31mA").unwrap();
+        assert!(has_red_fg(&cmds));
+        assert!(cmds.contains(&Command::Print('A')));
+    }
+
+    #[test]
+    fn test_utf8_encoded_csi() {
+        let mut parser = AnsiParser::new();
+        let cmds = parser.parse("\u{9b}31m".as_bytes()).unwrap();
+        assert!(has_red_fg(&cmds));
+    }
+
+    #[test]
+    fn test_8bit_osc_and_st() {
+        let mut parser = AnsiParser::new();
+        let cmds = parser.parse(b"\x9d0;Title\x9c").unwrap();
+        assert!(cmds.contains(&Command::SetTitle("Title".to_string())));
+    }
+
+    #[test]
+    fn test_osc_terminators_agree() {
+        let title = || Command::SetTitle("caf\u{e9} \u{2603}".to_string());
+        for terminator in [&b"\x07"[..], b"\x1b\\", b"\x9c", b"\xc2\x9c"] {
+            let mut parser = AnsiParser::new();
+            let mut data = "\x1b]0;caf\u{e9} \u{2603}".as_bytes().to_vec();
+            data.extend_from_slice(terminator);
+            data.push(b'x');
+            assert_eq!(
+                parser.parse(&data).unwrap(),
+                vec![title(), Command::Print('x')],
+                "terminator {terminator:?}"
+            );
+            assert!(!parser.has_pending());
+
+            // Split right before the terminator
+            let split = data.len() - terminator.len() - 1;
+            let mut cmds = parser.parse(&data[..split]).unwrap();
+            assert!(parser.has_pending());
+            cmds.extend(parser.parse(&data[split..]).unwrap());
+            assert_eq!(cmds, vec![title(), Command::Print('x')]);
+        }
+    }
+
+    #[test]
+    fn test_utf8_continuation_bytes_untouched() {
+        let mut parser = AnsiParser::new();
+        // U+201B is E2 80 9B; the trailing 0x9B must not become CSI
+        let cmds = parser.parse("\u{201b}\u{e9}".as_bytes()).unwrap();
+        assert_eq!(cmds, vec![Command::PrintStr("\u{201b}\u{e9}".to_string())]);
+    }
+
+    #[test]
+    fn test_split_across_reads() {
+        let mut parser = AnsiParser::new();
+        assert!(parser.parse(b"\xc2").unwrap().is_empty());
+        let cmds = parser.parse(b"\x9b31m").unwrap();
+        assert!(has_red_fg(&cmds));
+
+        let mut cmds = parser.parse(b"\xe2\x80").unwrap();
+        cmds.extend(parser.parse(b"\x9b").unwrap());
+        assert_eq!(cmds, vec![Command::Print('\u{201b}')]);
+    }
+}
+
+#[cfg(test)]
+mod render_scheduling_tests {
+    use crate::{ScreenRenderer, TerminalEngine};
+
+    fn engine() -> TerminalEngine {
+        TerminalEngine::new(20, 5, Box::new(ScreenRenderer::new()))
+    }
+
+    #[test]
+    fn test_writes_coalesce_into_one_render() {
+        let mut engine = engine();
+        engine.render().unwrap();
+        assert!(!engine.needs_render());
+
+        engine.write(b"one").unwrap();
+        engine.write(b"\r\ntwo").unwrap();
+        engine.write(b"\x1b[31mthree").unwrap();
+        assert!(engine.needs_render());
+
+        engine.render().unwrap();
+        assert!(!engine.needs_render());
+        assert!(!engine.buffer().grid().has_dirty_rows());
+    }
+
+    #[test]
+    fn test_render_if_needed_skips_clean_frames() {
+        let mut engine = engine();
+        assert!(engine.render_if_needed().unwrap());
+        assert!(!engine.render_if_needed().unwrap());
+
+        // Cursor movement alone changes no cells but still needs a frame
+        engine.write(b"\x1b[3;3H").unwrap();
+        assert!(engine.render_if_needed().unwrap());
+        assert!(!engine.render_if_needed().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod cursor_style_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command, CursorStyle};
+
+    fn feed(buffer: &mut TerminalBuffer, data: &[u8]) {
+        let mut parser = AnsiParser::new();
+        for cmd in parser.parse(data).unwrap() {
+            buffer.execute_command(cmd).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_decscusr_reset() {
+        let mut parser = AnsiParser::new();
+        assert!(
+            parser
+                .parse(b"\x1b[0 q")
+                .unwrap()
+                .contains(&Command::ResetCursorStyle)
+        );
+        assert!(
+            parser
+                .parse(b"\x1b[ q")
+                .unwrap()
+                .contains(&Command::ResetCursorStyle)
+        );
+        assert!(
+            parser
+                .parse(b"\x1b[1 q")
+                .unwrap()
+                .contains(&Command::SetCursorStyle(CursorStyle::BlinkBlock))
+        );
+    }
+
+    #[test]
+    fn test_reset_restores_configured_default() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        buffer.set_default_cursor_style(CursorStyle::SteadyBar);
+
+        feed(&mut buffer, b"\x1b[3 q");
+        assert_eq!(buffer.cursor_style(), CursorStyle::BlinkUnderline);
+
+        feed(&mut buffer, b"\x1b[0 q");
+        assert_eq!(buffer.cursor_style(), CursorStyle::SteadyBar);
+    }
+
+    #[test]
+    fn test_decrqss_cursor_style() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        feed(&mut buffer, b"\x1b[4 q\x1bP$q q\x1b\\");
+        assert_eq!(
+            buffer.drain_responses(),
+            vec![b"\x1bP1$r4 q\x1b\\".to_vec()]
+        );
+
+        feed(&mut buffer, b"\x1bP$qbogus\x1b\\");
+        assert_eq!(buffer.drain_responses(), vec![b"\x1bP0$r\x1b\\".to_vec()]);
+    }
+
+    #[test]
+    fn test_cursor_movement_resets_blink_phase() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        assert!(buffer.cursor_blink_on());
+        buffer.set_blink_phase(false);
+        assert!(!buffer.cursor_blink_on());
+
+        // Commands that leave the cursor where it is don't touch the phase
+        feed(&mut buffer, b"\x1b[1m");
+        assert!(!buffer.cursor_blink_on());
+
+        feed(&mut buffer, b"\x1b[3;4H");
+        assert!(buffer.cursor_blink_on());
+
+        buffer.set_blink_phase(false);
+        buffer.write_str("ls").unwrap();
+        assert!(buffer.cursor_blink_on());
+    }
+
+    #[test]
+    fn test_steady_cursor_ignores_blink_phase() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        feed(&mut buffer, b"\x1b[2 q");
+        buffer.set_blink_phase(false);
+        assert!(buffer.cursor_blink_on());
+    }
+}
+
+#[cfg(test)]
+mod control_char_tests {
+    use crate::core::{ControlCharPolicy, TerminalBuffer};
+    use crate::parser::Command;
+
+    #[test]
+    fn test_form_feed_and_vertical_tab_advance_row() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.execute_command(Command::Print('a')).unwrap();
+        buffer.execute_command(Command::Print('\x0c')).unwrap();
+        assert_eq!(buffer.cursor_pos(), (1, 1));
+        buffer.execute_command(Command::Print('\x0b')).unwrap();
+        assert_eq!(buffer.cursor_pos(), (1, 2));
+        assert_eq!(buffer.grid().get(1, 0).unwrap().character, ' ');
+    }
+
+    #[test]
+    fn test_other_controls_are_skipped_by_default() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.execute_command(Command::Print('\x01')).unwrap();
+        buffer.execute_command(Command::Print('\u{85}')).unwrap();
+        assert_eq!(buffer.cursor_pos(), (0, 0));
+        assert!(buffer.grid().get(0, 0).unwrap().zerowidth.is_empty());
+    }
+
+    #[test]
+    fn test_controls_can_be_replaced() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.set_control_char_policy(ControlCharPolicy::Replace('\u{fffd}'));
+        buffer.execute_command(Command::Print('\x01')).unwrap();
+        assert_eq!(buffer.grid().get(0, 0).unwrap().character, '\u{fffd}');
+        assert_eq!(buffer.cursor_pos(), (1, 0));
+    }
+
+    #[test]
+    fn test_write_char_applies_control_policy() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.write_char('a').unwrap();
+        buffer.write_char('\x07').unwrap();
+        assert_eq!(buffer.cursor_pos(), (1, 0));
+        assert!(buffer.grid().get(0, 0).unwrap().zerowidth.is_empty());
+
+        buffer.set_control_char_policy(ControlCharPolicy::Replace('\u{fffd}'));
+        buffer.write_char('\x07').unwrap();
+        assert_eq!(buffer.grid().get(1, 0).unwrap().character, '\u{fffd}');
+        assert_eq!(buffer.cursor_pos(), (2, 0));
+
+        // A control replacement would loop, so it is skipped instead
+        buffer.set_control_char_policy(ControlCharPolicy::Replace('\x1b'));
+        buffer.write_char('\x07').unwrap();
+        assert_eq!(buffer.cursor_pos(), (2, 0));
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use crate::{Cell, Error, Grid};
+
+    #[test]
+    fn test_grid_set_out_of_bounds_variant() {
+        let mut grid = Grid::new(10, 5);
+
+        match grid.set(10, 2, Cell::new('x')) {
+            Err(Error::OutOfBounds { x, y }) => assert_eq!((x, y), (10, 2)),
+            other => panic!("expected OutOfBounds, got {:?}", other),
+        }
+        assert_eq!(
+            grid.set(0, 5, Cell::new('x')).unwrap_err().to_string(),
+            "Position out of bounds: (0, 5)"
+        );
+    }
+}
+
+#[cfg(test)]
+mod input_decode_tests {
+    use crate::{InputEvent, InputHandler, Key, KeyEvent, Modifiers};
+
+    fn round_trip(event: KeyEvent) {
+        let mut handler = InputHandler::new();
+        let bytes = event.to_ansi();
+        let mut events = handler.decode(&bytes);
+        events.extend(handler.flush());
+        assert_eq!(events, vec![InputEvent::Key(event.clone())], "{:?}", bytes);
+    }
+
+    #[test]
+    fn test_round_trip_plain_keys() {
+        for key in [
+            Key::Char('a'),
+            Key::Char('é'),
+            Key::Enter,
+            Key::Backspace,
+            Key::Tab,
+            Key::Escape,
+            Key::Up,
+            Key::Down,
+            Key::Left,
+            Key::Right,
+            Key::Home,
+            Key::End,
+            Key::PageUp,
+            Key::PageDown,
+            Key::Delete,
+            Key::Insert,
+            Key::F(1),
+            Key::F(4),
+            Key::F(5),
+            Key::F(12),
+        ] {
+            round_trip(KeyEvent::new(key));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_modified_keys() {
+        let ctrl_shift = Modifiers {
+            ctrl: true,
+            shift: true,
+            ..Modifiers::default()
+        };
+        let alt = Modifiers {
+            alt: true,
+            ..Modifiers::default()
+        };
+        let ctrl = Modifiers {
+            ctrl: true,
+            ..Modifiers::default()
+        };
+        let shift = Modifiers {
+            shift: true,
+            ..Modifiers::default()
+        };
+
+        round_trip(KeyEvent::with_modifiers(Key::Up, ctrl_shift));
+        round_trip(KeyEvent::with_modifiers(Key::Delete, alt));
+        round_trip(KeyEvent::with_modifiers(Key::F(2), ctrl));
+        round_trip(KeyEvent::with_modifiers(Key::F(9), shift));
+        round_trip(KeyEvent::with_modifiers(Key::Tab, shift));
+        round_trip(KeyEvent::with_modifiers(Key::Char('c'), ctrl));
+        round_trip(KeyEvent::with_modifiers(Key::Char('x'), alt));
+    }
+
+    #[test]
+    fn test_decode_split_sequence() {
+        let mut handler = InputHandler::new();
+
+        assert!(handler.decode(b"\x1b[1;").is_empty());
+        assert_eq!(
+            handler.decode(b"5Ahi"),
+            vec![
+                InputEvent::Key(KeyEvent::with_modifiers(
+                    Key::Up,
+                    Modifiers {
+                        ctrl: true,
+                        ..Modifiers::default()
+                    }
+                )),
+                InputEvent::Key(KeyEvent::new(Key::Char('h'))),
+                InputEvent::Key(KeyEvent::new(Key::Char('i'))),
+            ]
+        );
+
+        assert!(handler.decode(&[0xc3]).is_empty());
+        assert_eq!(
+            handler.decode(&[0xa9]),
+            vec![InputEvent::Key(KeyEvent::new(Key::Char('é')))]
+        );
+    }
+
+    #[test]
+    fn test_decode_split_after_escape() {
+        let mut handler = InputHandler::new();
+        assert!(handler.decode(b"\x1b").is_empty());
+        assert_eq!(
+            handler.decode(b"[A"),
+            vec![InputEvent::Key(KeyEvent::new(Key::Up))]
+        );
+
+        // With nothing following, the held ESC is the Escape key
+        assert!(handler.decode(b"\x1b").is_empty());
+        assert_eq!(
+            handler.flush(),
+            vec![InputEvent::Key(KeyEvent::new(Key::Escape))]
+        );
+        assert!(handler.flush().is_empty());
+        assert_eq!(
+            handler.decode(b"a"),
+            vec![InputEvent::Key(KeyEvent::new(Key::Char('a')))]
+        );
+    }
+
+    #[test]
+    fn test_decode_keeps_apart_from_outgoing_bytes() {
+        let mut handler = InputHandler::new();
+        assert_eq!(
+            handler.decode(b"x\x1b[1;"),
+            vec![InputEvent::Key(KeyEvent::new(Key::Char('x')))]
+        );
+        assert!(handler.drain().is_empty());
+
+        handler.push_bytes(b"ls\r");
+        assert_eq!(
+            handler.decode(b"5A"),
+            vec![InputEvent::Key(KeyEvent::with_modifiers(
+                Key::Up,
+                Modifiers {
+                    ctrl: true,
+                    ..Modifiers::default()
+                }
+            ))]
+        );
+        assert_eq!(handler.drain(), b"ls\r");
+    }
+}
+
+#[cfg(test)]
+mod mouse_decode_tests {
+    use crate::{
+        InputEvent, InputHandler, Key, KeyEvent, Modifiers, MouseButton, MouseEvent, MouseEventKind,
+    };
+
+    fn mouse(kind: MouseEventKind, button: Option<MouseButton>, x: usize, y: usize) -> InputEvent {
+        InputEvent::Mouse(MouseEvent {
+            kind,
+            button,
+            x,
+            y,
+            modifiers: Modifiers::default(),
+        })
+    }
+
+    #[test]
+    fn test_decode_sgr_press_and_release() {
+        let mut handler = InputHandler::new();
+
+        assert_eq!(
+            handler.decode(b"\x1b[<0;10;5M\x1b[<0;10;5m"),
+            vec![
+                mouse(MouseEventKind::Press, Some(MouseButton::Left), 9, 4),
+                mouse(MouseEventKind::Release, Some(MouseButton::Left), 9, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_sgr_modifiers_wheel_and_drag() {
+        let mut handler = InputHandler::new();
+
+        let events = handler.decode(b"\x1b[<18;1;1M\x1b[<65;3;4M\x1b[<34;7;8M");
+        let InputEvent::Mouse(ctrl_right) = events[0] else {
+            panic!("expected mouse event, got {:?}", events[0]);
+        };
+        assert_eq!(ctrl_right.button, Some(MouseButton::Right));
+        assert!(ctrl_right.modifiers.ctrl);
+        assert_eq!(
+            events[1],
+            mouse(MouseEventKind::Press, Some(MouseButton::WheelDown), 2, 3)
+        );
+        assert_eq!(
+            events[2],
+            mouse(MouseEventKind::Move, Some(MouseButton::Right), 6, 7)
+        );
+    }
+
+    #[test]
+    fn test_decode_legacy_report() {
+        let mut handler = InputHandler::new();
+
+        assert!(handler.decode(b"\x1b[M ").is_empty());
+        assert_eq!(
+            handler.decode(b"*%\x1b[M#$!a"),
+            vec![
+                mouse(MouseEventKind::Press, Some(MouseButton::Left), 9, 4),
+                mouse(MouseEventKind::Release, None, 3, 0),
+                InputEvent::Key(KeyEvent::new(Key::Char('a'))),
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod glyph_style_tests {
+    use crate::renderer::screen::{ITALIC_SKEW_X, SYNTHETIC_BOLD_SMEAR};
+    use crate::{AndroidRenderer, Cell, CellStyle, FontFace};
+
+    static REGULAR: &[u8] = b"regular";
+    static BOLD: &[u8] = b"bold";
+
+    fn cell(bold: bool, italic: bool) -> Cell {
+        Cell::new('x').with_style(CellStyle {
+            bold,
+            italic,
+            ..CellStyle::default()
+        })
+    }
+
+    #[test]
+    fn test_bold_cell_selects_bold_face() {
+        let mut renderer = AndroidRenderer::new(14.0);
+        renderer.set_fonts(REGULAR, Some(BOLD));
+
+        let glyph = renderer.glyph_style(&cell(true, false));
+        assert_eq!(glyph.face, FontFace::Bold);
+        assert_eq!(glyph.smear, 0.0);
+        assert_eq!(renderer.font_data(glyph.face), Some(BOLD));
+
+        let glyph = renderer.glyph_style(&cell(false, false));
+        assert_eq!(glyph.face, FontFace::Regular);
+        assert_eq!(glyph.skew_x, 0.0);
+    }
+
+    #[test]
+    fn test_bold_without_bold_face_is_smeared() {
+        let mut renderer = AndroidRenderer::new(14.0);
+        renderer.set_fonts(REGULAR, None);
+
+        let glyph = renderer.glyph_style(&cell(true, true));
+        assert_eq!(glyph.face, FontFace::Regular);
+        assert_eq!(glyph.smear, SYNTHETIC_BOLD_SMEAR);
+        assert_eq!(glyph.skew_x, ITALIC_SKEW_X);
+    }
+}
+
+#[cfg(test)]
+mod glyph_fallback_tests {
+    use crate::{AndroidRenderer, GlyphChoice, GlyphFallback};
+
+    fn ascii_only(c: char) -> bool {
+        c.is_ascii()
+    }
+
+    #[test]
+    fn test_fallback_policies() {
+        let mut renderer = AndroidRenderer::new(14.0);
+        assert_eq!(
+            renderer.choose_glyph('a', ascii_only),
+            GlyphChoice::Char('a')
+        );
+        assert_eq!(renderer.choose_glyph('字', ascii_only), GlyphChoice::Notdef);
+
+        renderer.set_glyph_fallback(GlyphFallback::Space);
+        assert_eq!(
+            renderer.choose_glyph('\x01', ascii_only),
+            GlyphChoice::Char(' ')
+        );
+
+        renderer.set_glyph_fallback(GlyphFallback::Replacement('?'));
+        assert_eq!(
+            renderer.choose_glyph('字', ascii_only),
+            GlyphChoice::Char('?')
+        );
+
+        renderer.set_glyph_fallback(GlyphFallback::Replacement('\u{fffd}'));
+        assert_eq!(renderer.choose_glyph('字', ascii_only), GlyphChoice::Notdef);
+    }
+}
+
+#[cfg(test)]
+mod focus_reporting_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    #[test]
+    fn test_parse_focus_mode() {
+        let mut parser = AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"\x1b[?1004h").unwrap(),
+            vec![Command::SetFocusEvents(true)]
+        );
+        assert_eq!(
+            parser.parse(b"\x1b[?1004l").unwrap(),
+            vec![Command::SetFocusEvents(false)]
+        );
+    }
+
+    #[test]
+    fn test_focus_reports_only_when_enabled() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.focus_changed(true);
+        assert!(buffer.drain_responses().is_empty());
+
+        buffer
+            .execute_command(Command::SetFocusEvents(true))
+            .unwrap();
+        buffer.focus_changed(true);
+        buffer.focus_changed(false);
+        assert_eq!(
+            buffer.drain_responses(),
+            vec![b"\x1b[I".to_vec(), b"\x1b[O".to_vec()]
+        );
+    }
+}
+
+#[cfg(test)]
+mod synchronized_output_tests {
+    use crate::parser::{AnsiParser, Command};
+    use crate::{ScreenRenderer, TerminalEngine};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_mode_2026() {
+        let mut parser = AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"\x1b[?2026h").unwrap(),
+            vec![Command::SetSynchronizedOutput(true)]
+        );
+        assert_eq!(
+            parser.parse(b"\x1b[?2026l").unwrap(),
+            vec![Command::SetSynchronizedOutput(false)]
+        );
+    }
+
+    #[test]
+    fn test_render_is_held_until_frame_ends() {
+        let mut engine = TerminalEngine::new(20, 5, Box::new(ScreenRenderer::new()));
+        engine.render().unwrap();
+
+        engine.write(b"\x1b[?2026hhalf a fr").unwrap();
+        assert!(engine.buffer().is_synchronized_output());
+        assert!(!engine.needs_render());
+        assert!(!engine.render_if_needed().unwrap());
+
+        engine.write(b"ame\x1b[?2026l").unwrap();
+        assert!(!engine.buffer().is_synchronized_output());
+        assert!(engine.needs_render());
+    }
+
+    #[test]
+    fn test_hold_is_released_after_timeout() {
+        let mut engine = TerminalEngine::new(20, 5, Box::new(ScreenRenderer::new()));
+        engine.render().unwrap();
+        engine.write(b"\x1b[?2026hnever finished").unwrap();
+        assert!(engine.buffer().is_frame_held());
+
+        engine
+            .buffer_mut()
+            .set_synchronized_output_timeout(Duration::ZERO);
+        assert!(engine.buffer().is_synchronized_output());
+        assert!(!engine.buffer().is_frame_held());
+        assert!(engine.render_if_needed().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod private_mode_save_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    fn feed(parser: &mut AnsiParser, buffer: &mut TerminalBuffer, data: &[u8]) {
+        for cmd in parser.parse(data).unwrap() {
+            buffer.execute_command(cmd).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_xtsave_xtrestore() {
+        let mut parser = AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"\x1b[?1;1049s").unwrap(),
+            vec![Command::SavePrivateModes(vec![1, 1049])]
+        );
+        assert_eq!(
+            parser.parse(b"\x1b[?1049r").unwrap(),
+            vec![Command::RestorePrivateModes(vec![1049])]
+        );
+    }
+
+    #[test]
+    fn test_save_and_restore_application_cursor_keys() {
+        let mut parser = AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(10, 5);
+
+        feed(&mut parser, &mut buffer, b"\x1b[?1h\x1b[?1s\x1b[?1l");
+        assert!(!buffer.is_application_cursor_keys());
+
+        feed(&mut parser, &mut buffer, b"\x1b[?1r");
+        assert!(buffer.is_application_cursor_keys());
+        assert_eq!(buffer.private_mode(1), Some(true));
+    }
+
+    #[test]
+    fn test_restore_alternate_screen() {
+        let mut parser = AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(10, 5);
+
+        feed(&mut parser, &mut buffer, b"\x1b[?1049s\x1b[?1049h");
+        assert!(buffer.is_alternate_screen());
+        feed(&mut parser, &mut buffer, b"\x1b[?1049r");
+        assert!(!buffer.is_alternate_screen());
+    }
+
+    #[test]
+    fn test_active_modes_lists_tracked_modes() {
+        let mut parser = AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(10, 5);
+        let defaults = buffer.active_modes();
+        assert!(defaults.contains(&(7, true)));
+        assert!(defaults.contains(&(25, true)));
+        assert!(defaults.iter().all(|&(mode, _)| mode != 9 && mode != 47));
+
+        feed(
+            &mut parser,
+            &mut buffer,
+            b"\x1b[?1h\x1b[?2004h\x1b[?25l\x1b[?1002h",
+        );
+        let enabled: Vec<u16> = buffer
+            .active_modes()
+            .into_iter()
+            .filter_map(|(mode, on)| on.then_some(mode))
+            .collect();
+        assert_eq!(enabled, vec![1, 7, 1002, 2004]);
+        assert!(!buffer.is_cursor_visible());
+
+        // DECTCEM is now saved and restored like the others
+        feed(&mut parser, &mut buffer, b"\x1b[?25s\x1b[?25h\x1b[?25r");
+        assert!(!buffer.is_cursor_visible());
+    }
+
+    #[test]
+    fn test_ansi_modes_are_reported_apart() {
+        let mut parser = AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(10, 5);
+        assert_eq!(buffer.active_ansi_modes(), vec![(4, false), (20, false)]);
+
+        feed(&mut parser, &mut buffer, b"\x1b[4;20h");
+        assert_eq!(buffer.active_ansi_modes(), vec![(4, true), (20, true)]);
+        assert_eq!(buffer.ansi_mode(4), Some(true));
+        assert_eq!(buffer.ansi_mode(7), None);
+        // Neither is a DEC private mode
+        assert_eq!(buffer.private_mode(4), None);
+        assert_eq!(buffer.private_mode(20), None);
+
+        feed(&mut parser, &mut buffer, b"\x1b[4l");
+        assert!(!buffer.is_insert_mode());
+    }
+
+    #[test]
+    fn test_insert_mode_pushes_the_row_right() {
+        let mut parser = AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(6, 2);
+        feed(&mut parser, &mut buffer, b"abcdef\x1b[1;2H\x1b[4hXY");
+        let text: String = buffer
+            .grid()
+            .row(0)
+            .unwrap()
+            .iter()
+            .map(|c| c.character)
+            .collect();
+        assert_eq!(text, "aXYbcd");
+        assert_eq!(buffer.cursor_pos(), (3, 0));
+
+        feed(&mut parser, &mut buffer, b"\x1b[4lZ");
+        let text: String = buffer
+            .grid()
+            .row(0)
+            .unwrap()
+            .iter()
+            .map(|c| c.character)
+            .collect();
+        assert_eq!(text, "aXYZcd");
+    }
+}
+
+#[cfg(test)]
+mod answerback_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::AnsiParser;
+
+    #[test]
+    fn test_enq_sends_answerback() {
+        let mut parser = AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(10, 5);
+
+        for cmd in parser.parse(b"\x05").unwrap() {
+            buffer.execute_command(cmd).unwrap();
+        }
+        assert!(buffer.drain_responses().is_empty());
+
+        buffer.set_answerback("rin");
+        assert_eq!(buffer.answerback(), "rin");
+        for cmd in parser.parse(b"\x05").unwrap() {
+            buffer.execute_command(cmd).unwrap();
+        }
+        assert_eq!(buffer.drain_responses(), vec![b"rin".to_vec()]);
+        assert_eq!(buffer.cursor_pos(), (0, 0));
+    }
+}
+
+#[cfg(test)]
+mod erase_display_tests {
+    use crate::core::{Color, TerminalBuffer};
+    use crate::parser::Command;
+
+    fn filled(width: usize, height: usize) -> TerminalBuffer {
+        let mut buffer = TerminalBuffer::new(width, height);
+        // Keep the last row from wrapping and scrolling the screen
+        buffer
+            .execute_command(Command::SetAutoWrapMode(false))
+            .unwrap();
+        for y in 0..height {
+            buffer.execute_command(Command::MoveCursor(0, y)).unwrap();
+            for _ in 0..width {
+                buffer.write_char('x').unwrap();
+            }
+        }
+        buffer
+    }
+
+    fn row_text(buffer: &TerminalBuffer, y: usize) -> String {
+        buffer
+            .grid()
+            .row(y)
+            .unwrap()
+            .iter()
+            .map(|c| c.character)
+            .collect()
+    }
+
+    #[test]
+    fn test_erase_to_cursor_at_last_column() {
+        let mut buffer = filled(6, 3);
+        buffer.execute_command(Command::MoveCursor(5, 1)).unwrap();
+        buffer
+            .execute_command(Command::SetBackground(Color::BLUE))
+            .unwrap();
+        buffer.execute_command(Command::EraseDisplay(1)).unwrap();
+
+        assert_eq!(row_text(&buffer, 0), "      ");
+        assert_eq!(row_text(&buffer, 1), "      ");
+        assert_eq!(row_text(&buffer, 2), "xxxxxx");
+        assert_eq!(buffer.grid().get(5, 1).unwrap().style.bg, Color::BLUE);
+        assert_eq!(buffer.grid().get(0, 0).unwrap().style.bg, Color::BLUE);
+    }
+
+    #[test]
+    fn test_clear_screen_uses_current_background() {
+        let mut buffer = filled(6, 3);
+        buffer
+            .execute_command(Command::SetBackground(Color::BLUE))
+            .unwrap();
+        buffer.execute_command(Command::ClearScreen).unwrap();
+
+        for y in 0..3 {
+            assert_eq!(row_text(&buffer, y), "      ");
+            for x in 0..6 {
+                assert_eq!(buffer.grid().get(x, y).unwrap().style.bg, Color::BLUE);
+            }
+        }
+        assert_eq!(buffer.cursor_pos(), (0, 0));
+    }
+
+    #[test]
+    fn test_erase_to_cursor_beyond_shrunk_width() {
+        let mut buffer = filled(10, 3);
+        buffer.execute_command(Command::MoveCursor(9, 1)).unwrap();
+        buffer.resize(5, 3).unwrap();
+        // Reflow puts the cursor on the last cell of the rewrapped line,
+        // now the top row, with the three rows above it in scrollback
+        assert_eq!(buffer.cursor_pos(), (4, 0));
+        buffer.execute_command(Command::EraseDisplay(1)).unwrap();
+        assert_eq!(row_text(&buffer, 0), "     ");
+        assert_eq!(row_text(&buffer, 1), "xxxxx");
+
+        // A tab can also leave the cursor past the right edge
+        let mut buffer = filled(10, 3);
+        buffer.execute_command(Command::MoveCursor(0, 1)).unwrap();
+        buffer.execute_command(Command::Execute(b'\t')).unwrap();
+        buffer.execute_command(Command::Execute(b'\t')).unwrap();
+        buffer.execute_command(Command::EraseDisplay(1)).unwrap();
+        assert_eq!(row_text(&buffer, 1), "          ");
+        assert_eq!(row_text(&buffer, 2), "xxxxxxxxxx");
+    }
+}
+
+#[cfg(test)]
+mod pixel_to_cell_tests {
+    use crate::AndroidRenderer;
+
+    #[test]
+    fn test_cell_at_pixel() {
+        // 20px font: 12px wide, 24px tall cells
+        let renderer = AndroidRenderer::new(20.0);
+        assert_eq!(renderer.cell_size(), (12.0, 24.0));
+
+        assert_eq!(renderer.cell_at_pixel(0.0, 0.0), (0, 0));
+        assert_eq!(renderer.cell_at_pixel(11.9, 23.9), (0, 0));
+        assert_eq!(renderer.cell_at_pixel(12.0, 24.0), (1, 1));
+        assert_eq!(renderer.cell_at_pixel(125.0, 50.0), (10, 2));
+        assert_eq!(renderer.cell_at_pixel(-5.0, -1.0), (0, 0));
+    }
+
+    #[test]
+    fn test_measured_cell_size_replaces_estimate() {
+        let mut renderer = AndroidRenderer::new(20.0);
+        renderer.set_cell_size(11.0, 26.0);
+        assert_eq!(renderer.cell_size(), (11.0, 26.0));
+        assert_eq!(renderer.cell_at_pixel(12.0, 24.0), (1, 0));
+        assert_eq!(renderer.cell_at_pixel(22.0, 52.0), (2, 2));
+    }
+
+    #[test]
+    fn test_engine_forwards_cell_size() {
+        let mut engine = crate::TerminalEngine::new(10, 4, Box::new(AndroidRenderer::new(20.0)));
+        assert_eq!(engine.cell_size(), Some((12.0, 24.0)));
+        engine.set_cell_size(9.5, 21.0);
+        assert_eq!(engine.cell_size(), Some((9.5, 21.0)));
+    }
+}
+
+#[cfg(test)]
+mod event_sink_tests {
+    use crate::{EventSink, ScreenRenderer, TerminalEngine};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recording {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl EventSink for Recording {
+        fn on_title(&mut self, title: &str) {
+            self.events.lock().unwrap().push(format!("title:{}", title));
+        }
+
+        fn on_cwd(&mut self, cwd: &str) {
+            self.events.lock().unwrap().push(format!("cwd:{}", cwd));
+        }
+
+        fn on_bell(&mut self) {
+            self.events.lock().unwrap().push("bell".to_string());
+        }
+
+        fn on_unknown_private_mode(&mut self, mode: u16, set: bool) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("mode:{}={}", mode, set));
+        }
+    }
+
+    #[test]
+    fn test_sink_receives_title_cwd_and_bell() {
+        let sink = Recording::default();
+        let events = sink.events.clone();
+        let mut engine = TerminalEngine::new(20, 5, Box::new(ScreenRenderer::new()));
+        engine.set_event_sink(Box::new(sink));
+
+        engine.write(b"\x1b]0;my title\x07").unwrap();
+        engine
+            .write(b"\x1b]7;file://host/home/user\x1b\\\x07")
+            .unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["title:my title", "cwd:/home/user", "bell"]
+        );
+    }
+
+    #[test]
+    fn test_unknown_private_modes_are_reported() {
+        let mut parser = crate::AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"\x1b[?9999h").unwrap(),
+            vec![crate::Command::UnknownPrivateMode {
+                mode: 9999,
+                set: true
+            }]
+        );
+
+        let sink = Recording::default();
+        let events = sink.events.clone();
+        let mut engine = TerminalEngine::new(20, 5, Box::new(ScreenRenderer::new()));
+        engine.set_event_sink(Box::new(sink));
+        // The known mode in the middle isn't reported
+        engine.write(b"\x1b[?9999;25;1234l").unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["mode:9999=false", "mode:1234=false"]
+        );
+    }
+}
+
+#[cfg(test)]
+mod write_outcome_tests {
+    use crate::{ScreenRenderer, TerminalEngine, WriteOutcome};
+
+    fn engine() -> TerminalEngine {
+        TerminalEngine::new(20, 5, Box::new(ScreenRenderer::new()))
+    }
+
+    #[test]
+    fn test_plain_text_is_dirty_and_nothing_is_not() {
+        let mut engine = engine();
+        assert_eq!(
+            engine.process(b"hello").unwrap(),
+            WriteOutcome {
+                dirty: true,
+                ..WriteOutcome::default()
+            }
+        );
+        assert_eq!(engine.process(b"").unwrap(), WriteOutcome::default());
+    }
+
+    #[test]
+    fn test_reports_responses_and_title() {
+        let mut engine = engine();
+        let outcome = engine.process(b"\x1b]2;vim\x07\x1b[6n").unwrap();
+        assert!(!outcome.dirty);
+        assert!(outcome.title_changed);
+        assert!(outcome.has_responses);
+    }
+
+    #[test]
+    fn test_pure_query_is_not_dirty() {
+        let mut engine = engine();
+        engine.mark_rendered();
+        assert_eq!(
+            engine.process(b"\x1b[6n").unwrap(),
+            WriteOutcome {
+                has_responses: true,
+                ..WriteOutcome::default()
+            }
+        );
+        assert!(!engine.needs_render());
+    }
+
+    #[test]
+    fn test_cursor_and_mode_changes_are_dirty() {
+        let mut engine = engine();
+        engine.process(b"hello").unwrap();
+        for data in [&b"\x1b[H"[..], b"\x1b[?25l", b"\x1b[?1049h", b"\x1b[4h"] {
+            engine.mark_rendered();
+            assert!(engine.process(data).unwrap().dirty, "{data:?}");
+            assert!(engine.needs_render());
+        }
+    }
+
+    #[test]
+    fn test_keeps_rows_dirtied_before_the_call() {
+        let mut engine = engine();
+        engine.mark_rendered();
+        engine.process(b"hello").unwrap();
+        assert!(!engine.process(b"\x1b[6n").unwrap().dirty);
+        assert!(engine.buffer().grid().is_row_dirty(0));
+    }
+}
+
+#[cfg(test)]
+mod bulk_print_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    fn rows(buffer: &TerminalBuffer) -> Vec<String> {
+        (0..buffer.grid().height())
+            .map(|y| {
+                buffer
+                    .grid()
+                    .row(y)
+                    .unwrap()
+                    .iter()
+                    .map(|c| format!("{}{:?}{}", c.character, c.style, c.wide_spacer))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plain_text_is_one_bulk_command() {
+        let mut parser = AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"hello, world").unwrap(),
+            vec![Command::PrintStr("hello, world".to_string())]
+        );
+        assert_eq!(parser.parse(b"x").unwrap(), vec![Command::Print('x')]);
+    }
+
+    #[test]
+    fn test_bulk_matches_per_char_path() {
+        let input = "\x1b[31mThe quick brown fox\x1b[0m jumps over 字 the lazy dog \
+                     \x1b(0lqqk\x1b(B and wraps around the edge of the screen";
+        for auto_wrap in [true, false] {
+            let mut bulk = TerminalBuffer::new(12, 4);
+            let mut single = TerminalBuffer::new(12, 4);
+            bulk.execute_command(Command::SetAutoWrapMode(auto_wrap))
+                .unwrap();
+            single
+                .execute_command(Command::SetAutoWrapMode(auto_wrap))
+                .unwrap();
+
+            let mut parser = AnsiParser::new();
+            for cmd in parser.parse(input.as_bytes()).unwrap() {
+                match cmd {
+                    Command::PrintStr(s) => {
+                        bulk.execute_command(Command::PrintStr(s.clone())).unwrap();
+                        for c in s.chars() {
+                            single.execute_command(Command::Print(c)).unwrap();
+                        }
+                    }
+                    cmd => {
+                        bulk.execute_command(cmd.clone()).unwrap();
+                        single.execute_command(cmd).unwrap();
+                    }
+                }
+            }
+
+            assert_eq!(rows(&bulk), rows(&single));
+            assert_eq!(bulk.cursor_pos(), single.cursor_pos());
+        }
+    }
+}
+
+#[cfg(test)]
+mod erase_chars_tests {
+    use crate::core::{Color, TerminalBuffer};
+    use crate::parser::{AnsiParser, Command};
+
+    #[test]
+    fn test_parse_ech() {
+        let mut parser = AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"\x1b[3X").unwrap(),
+            vec![Command::EraseChars(3)]
+        );
+        assert_eq!(
+            parser.parse(b"\x1b[X").unwrap(),
+            vec![Command::EraseChars(1)]
+        );
+    }
+
+    #[test]
+    fn test_ech_clears_without_shifting() {
+        let mut parser = AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(10, 2);
+        for cmd in parser.parse(b"abcdefgh\x1b[1;3H\x1b[44m\x1b[3X").unwrap() {
+            buffer.execute_command(cmd).unwrap();
+        }
+
+        let row: String = buffer
+            .grid()
+            .row(0)
+            .unwrap()
+            .iter()
+            .map(|c| c.character)
+            .collect();
+        assert_eq!(row, "ab   fgh  ");
+        assert_eq!(buffer.cursor_pos(), (2, 0));
+        assert_eq!(
+            buffer.grid().get(2, 0).unwrap().style.bg,
+            Color::new(36, 114, 200)
+        );
+    }
+}
+
+#[cfg(test)]
+mod pending_sequence_tests {
+    use crate::parser::{AnsiParser, Command};
+
+    #[test]
+    fn test_split_csi_is_pending() {
+        let mut parser = AnsiParser::new();
+        assert!(!parser.has_pending());
+
+        assert!(parser.parse(b"\x1b[").unwrap().is_empty());
+        assert!(parser.has_pending());
+
+        assert_eq!(parser.parse(b"2J").unwrap(), vec![Command::ClearScreen]);
+        assert!(!parser.has_pending());
+    }
+
+    #[test]
+    fn test_strings_and_utf8_are_pending_until_terminated() {
+        let mut parser = AnsiParser::new();
+
+        parser.parse(b"\x1b]0;title").unwrap();
+        assert!(parser.has_pending());
+        parser.parse(b"\x07").unwrap();
+        assert!(!parser.has_pending());
+
+        parser.parse(b"\x1bP$q").unwrap();
+        assert!(parser.has_pending());
+        parser.parse(b" q\x1b\\").unwrap();
+        assert!(!parser.has_pending());
+
+        parser.parse(&[0xe2, 0x82]).unwrap();
+        assert!(parser.has_pending());
+        parser.parse(&[0xac]).unwrap();
+        assert!(!parser.has_pending());
+    }
+
+    #[test]
+    fn test_flush_lone_escape() {
+        let mut parser = AnsiParser::new();
+        assert_eq!(parser.parse(b"ab\x1b").unwrap().len(), 1);
+        assert!(parser.has_pending());
+
+        assert!(parser.flush_pending().is_empty());
+        assert!(!parser.has_pending());
+        // The ESC is gone, so what follows is plain text again
+        let cmds = parser.parse(b"[31m").unwrap();
+        assert_eq!(cmds, vec![Command::PrintStr("[31m".to_string())]);
+    }
+
+    #[test]
+    fn test_flush_dispatches_unterminated_osc() {
+        let mut parser = AnsiParser::new();
+        parser.parse(b"\x1b]2;half a title").unwrap();
+        assert_eq!(
+            parser.flush_pending(),
+            vec![Command::SetTitle("half a title".to_string())]
+        );
+        assert!(parser.flush_pending().is_empty());
+    }
+
+    #[test]
+    fn test_flush_partial_utf8() {
+        let mut parser = AnsiParser::new();
+        parser.parse(b"\xe2\x82").unwrap();
+        assert!(parser.has_pending());
+        assert_eq!(parser.flush_pending(), vec![Command::Print('\u{fffd}')]);
+    }
+}
+
+#[cfg(test)]
+mod reflow_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::Command;
+
+    fn cursor_char(buffer: &TerminalBuffer) -> char {
+        let (x, y) = buffer.cursor_pos();
+        buffer.grid().get(x, y).unwrap().character
+    }
+
+    fn row_text(buffer: &TerminalBuffer, y: usize) -> String {
+        buffer
+            .grid()
+            .row(y)
+            .unwrap()
+            .iter()
+            .map(|c| c.character)
+            .collect()
+    }
+
+    fn write(buffer: &mut TerminalBuffer, s: &str) {
+        buffer.write_str(s).unwrap();
+    }
+
+    #[test]
+    fn test_shrink_keeps_cursor_on_wrapped_character() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        write(&mut buffer, "abcdefghijklmnopqrstuvwxy");
+        assert!(buffer.grid().is_row_wrapped(0));
+        assert!(buffer.grid().is_row_wrapped(1));
+        assert!(!buffer.grid().is_row_wrapped(2));
+
+        // 'n' is the 14th character: row 1, column 3
+        buffer.execute_command(Command::MoveCursor(3, 1)).unwrap();
+        assert_eq!(cursor_char(&buffer), 'n');
+
+        buffer.resize(6, 5).unwrap();
+        assert_eq!(cursor_char(&buffer), 'n');
+        assert_eq!(buffer.cursor_pos(), (1, 2));
+        assert_eq!(row_text(&buffer, 0), "abcdef");
+        assert_eq!(row_text(&buffer, 4), "y     ");
+
+        buffer.resize(10, 5).unwrap();
+        assert_eq!(cursor_char(&buffer), 'n');
+        assert_eq!(row_text(&buffer, 1), "klmnopqrst");
+    }
+
+    #[test]
+    fn test_shrink_pushes_rows_above_cursor_into_scrollback() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        write(&mut buffer, "first");
+        buffer.execute_command(Command::Execute(b'\r')).unwrap();
+        buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        write(&mut buffer, "0123456789abcdefgh");
+        buffer.execute_command(Command::MoveCursor(5, 2)).unwrap();
+        assert_eq!(cursor_char(&buffer), 'f');
+
+        // The long line needs four rows at width 5, so the first line
+        // and the start of the long one scroll off
+        buffer.resize(5, 3).unwrap();
+        assert_eq!(cursor_char(&buffer), 'f');
+        assert_eq!(buffer.cursor_pos(), (0, 2));
+        assert_eq!(buffer.scrollback_len(), 2);
+        assert_eq!(row_text(&buffer, 0), "56789");
+    }
+
+    #[test]
+    fn test_cursor_after_full_row_stays_on_next_row() {
+        let mut buffer = TerminalBuffer::new(4, 3);
+        write(&mut buffer, "abcd");
+        assert_eq!(buffer.cursor_pos(), (0, 1));
+
+        buffer.resize(2, 3).unwrap();
+        assert_eq!(buffer.cursor_pos(), (0, 2));
+        assert_eq!(row_text(&buffer, 1), "cd");
+    }
+
+    #[test]
+    fn test_wide_char_not_split_across_rows() {
+        let mut buffer = TerminalBuffer::new(6, 3);
+        write(&mut buffer, "ab中c");
+        buffer.resize(3, 3).unwrap();
+        assert_eq!(row_text(&buffer, 0), "ab ");
+        assert_eq!(buffer.grid().get(0, 1).unwrap().character, '中');
+        assert!(buffer.grid().get(0, 1).unwrap().wide);
+        assert_eq!(buffer.grid().get(2, 1).unwrap().character, 'c');
+    }
+
+    #[test]
+    fn test_auto_wrap_marks_the_row_it_leaves() {
+        let mut buffer = TerminalBuffer::new(5, 3);
+        write(&mut buffer, "abcdefg");
+        assert!(buffer.grid().is_row_wrapped(0));
+        assert!(!buffer.grid().is_row_wrapped(1));
+
+        // A full row ended by CR LF is a hard break
+        buffer.execute_command(Command::MoveCursor(0, 1)).unwrap();
+        write(&mut buffer, "12345");
+        buffer.execute_command(Command::Execute(b'\r')).unwrap();
+        buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        assert!(!buffer.grid().is_row_wrapped(1));
+
+        // So is a line feed issued on a row that had wrapped
+        buffer.execute_command(Command::MoveCursor(4, 0)).unwrap();
+        buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        assert!(!buffer.grid().is_row_wrapped(0));
+    }
+
+    #[test]
+    fn test_wraps_past_the_line_limit_become_hard_breaks() {
+        let mut buffer = TerminalBuffer::new(4, 4);
+        buffer.set_reflow_line_limit(8);
+        write(&mut buffer, "abcdefghijk");
+
+        // Rows 0-1 make one 8-cell line, row 2 starts another
+        buffer.resize(3, 5).unwrap();
+        assert_eq!(row_text(&buffer, 0), "abc");
+        assert_eq!(row_text(&buffer, 2), "gh ");
+        assert!(!buffer.grid().is_row_wrapped(2));
+        assert_eq!(row_text(&buffer, 3), "ijk");
+    }
+
+    #[test]
+    fn test_enormous_line_is_cut_at_the_line_limit() {
+        let (width, height) = (40, 25);
+        let filled = || {
+            let mut buffer = TerminalBuffer::new(width, height);
+            write(&mut buffer, &"x".repeat(width * height - 1));
+            assert!((0..height - 1).all(|y| buffer.grid().is_row_wrapped(y)));
+            buffer
+        };
+        let breaks = |buffer: &TerminalBuffer| -> Vec<usize> {
+            (0..height)
+                .filter(|&y| !buffer.grid().is_row_wrapped(y))
+                .collect()
+        };
+
+        // Uncapped, the whole screen rewraps as one line
+        let mut buffer = filled();
+        buffer.resize(width - 1, height).unwrap();
+        assert_eq!(breaks(&buffer), [height - 1]);
+
+        // Capped at 10 source rows, the screen becomes lines of 400, 400
+        // and 199 cells: 11, 11 and 6 rows at width 39, with the first
+        // three rows pushed into scrollback
+        let mut buffer = filled();
+        buffer.set_reflow_line_limit(10 * width);
+        buffer.resize(width - 1, height).unwrap();
+        assert_eq!(buffer.scrollback_len(), 3);
+        assert_eq!(breaks(&buffer), [7, 18, 24]);
+    }
+}
+
+#[cfg(test)]
+mod command_injection_tests {
+    use crate::TerminalEngine;
+    use crate::core::{CellStyle, Color, TerminalBuffer};
+    use crate::parser::{AnsiParser, Command};
+    use crate::renderer::AndroidRenderer;
+
+    fn commands() -> Vec<Command> {
+        vec![
+            Command::MoveCursor(2, 1),
+            Command::SetForeground(Color::RED),
+            Command::PrintStr("hi".to_string()),
+            Command::SetStyle(CellStyle::default()),
+            Command::Print('!'),
+        ]
+    }
+
+    #[test]
+    fn test_buffer_execute_commands() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        buffer.execute_commands(commands()).unwrap();
+
+        let grid = buffer.grid();
+        assert_eq!(grid.get(2, 1).unwrap().character, 'h');
+        assert_eq!(grid.get(3, 1).unwrap().style.fg, Color::RED);
+        assert_eq!(grid.get(4, 1).unwrap().character, '!');
+        assert_eq!(grid.get(4, 1).unwrap().style, CellStyle::default());
+        assert_eq!(buffer.cursor_pos(), (5, 1));
+    }
+
+    #[test]
+    fn test_engine_apply_matches_parsed_write() {
+        let bytes = b"\x1b[2;3H\x1b[31mhi\x1b[0m!";
+        let parsed = AnsiParser::new().parse(bytes).unwrap();
+
+        let mut applied = TerminalEngine::new(10, 3, Box::new(AndroidRenderer::new(12.0)));
+        assert!(applied.apply(parsed).unwrap().dirty);
+        assert!(!applied.apply(Vec::new()).unwrap().dirty);
+
+        let mut written = TerminalEngine::new(10, 3, Box::new(AndroidRenderer::new(12.0)));
+        written.write(bytes).unwrap();
+
+        for y in 0..3 {
+            assert_eq!(
+                applied.buffer().grid().row(y),
+                written.buffer().grid().row(y)
+            );
+        }
+        assert_eq!(applied.buffer().cursor_pos(), written.buffer().cursor_pos());
+    }
+
+    #[test]
+    fn test_batched_apply_matches_one_by_one() {
+        // Text split across commands and chunks, wide chars, wrapping,
+        // scrolling, tabs and style changes in between
+        let mut bytes = Vec::new();
+        for i in 0..40 {
+            bytes.extend_from_slice(format!("line {} \x1b[3{}m中文", i, i % 8).as_bytes());
+            bytes.extend_from_slice(b"\tend of a long line that wraps\x1b[0m\r\n");
+        }
+
+        let mut batched = TerminalEngine::new(16, 5, Box::new(AndroidRenderer::new(12.0)));
+        let mut naive = TerminalBuffer::new(16, 5);
+        let mut parser = AnsiParser::new();
+        for chunk in bytes.chunks(7) {
+            batched.write(chunk).unwrap();
+            for cmd in parser.parse(chunk).unwrap() {
+                naive.execute_command(cmd).unwrap();
+            }
+        }
+
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        batched.buffer().export(&mut a, true).unwrap();
+        naive.export(&mut b, true).unwrap();
+        assert_eq!(String::from_utf8(a).unwrap(), String::from_utf8(b).unwrap());
+        assert_eq!(batched.buffer().cursor_pos(), naive.cursor_pos());
+    }
+}
+
+#[cfg(test)]
+mod raw_log_tests {
+    use crate::renderer::AndroidRenderer;
+    use crate::{InputEncoding, TerminalEngine};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_raw_log_receives_exactly_the_written_bytes() {
+        let mut engine = TerminalEngine::new(20, 4, Box::new(AndroidRenderer::new(12.0)));
+        let capture = Capture::default();
+        engine.set_raw_log(Some(Box::new(capture.clone())));
+
+        let chunks: [&[u8]; 3] = [b"\x1b[1;31mred", b"\xe2\x82", b"\xac\x1b[0m\r\n"];
+        for chunk in chunks {
+            engine.write(chunk).unwrap();
+        }
+        // Logged before any transcoding
+        engine.set_input_encoding(InputEncoding::Latin1);
+        engine.process(b"\xe9").unwrap();
+        assert_eq!(
+            *capture.0.lock().unwrap(),
+            chunks
+                .concat()
+                .into_iter()
+                .chain([0xe9])
+                .collect::<Vec<_>>()
+        );
+
+        engine.set_raw_log(None);
+        engine.write(b"more").unwrap();
+        assert_eq!(capture.0.lock().unwrap().len(), chunks.concat().len() + 1);
+    }
+
+    /// Fails every write, counting the attempts
+    struct Broken(Arc<Mutex<usize>>);
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            *self.0.lock().unwrap() += 1;
+            Err(std::io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failing_raw_log_is_dropped() {
+        let mut engine = TerminalEngine::new(20, 4, Box::new(AndroidRenderer::new(12.0)));
+        let attempts = Arc::new(Mutex::new(0));
+        engine.set_raw_log(Some(Box::new(Broken(attempts.clone()))));
+
+        engine.write(b"still ").unwrap();
+        engine.write(b"shown").unwrap();
+        assert_eq!(engine.buffer().current_line_text(), "still shown");
+        assert_eq!(*attempts.lock().unwrap(), 1);
+    }
+}
+
+#[cfg(test)]
+mod recording_tests {
+    use crate::renderer::AndroidRenderer;
+    use crate::{Frame, Pacing, SessionPlayer, SessionRecorder, TerminalEngine};
+    use std::io::Cursor;
+
+    fn engine() -> TerminalEngine {
+        TerminalEngine::new(20, 4, Box::new(AndroidRenderer::new(12.0)))
+    }
+
+    #[test]
+    fn test_record_and_replay_round_trip() {
+        let chunks: [&[u8]; 4] = [
+            b"hello ",
+            b"\x1b[1;31mwor",
+            b"ld\x1b[0m\r\n",
+            b"\xe2\x82\xac \x1b[",
+        ];
+
+        let mut live = engine();
+        let mut recorder = SessionRecorder::new(Vec::new());
+        for chunk in chunks {
+            recorder.write(&mut live, chunk).unwrap();
+        }
+        let recording = recorder.into_inner().unwrap();
+        assert_eq!(recording.iter().filter(|&&b| b == b'\n').count(), 4);
+
+        let mut player = SessionPlayer::new(Cursor::new(recording.clone()));
+        let first = player.next_frame().unwrap().unwrap();
+        assert_eq!(first.data, b"hello ");
+
+        let mut replayed = engine();
+        let mut player = SessionPlayer::new(Cursor::new(recording));
+        assert_eq!(player.play(&mut replayed, Pacing::Instant).unwrap(), 4);
+        assert!(player.next_frame().unwrap().is_none());
+
+        for y in 0..4 {
+            assert_eq!(replayed.buffer().grid().row(y), live.buffer().grid().row(y));
+        }
+        assert_eq!(replayed.buffer().cursor_pos(), live.buffer().cursor_pos());
+    }
+
+    #[test]
+    fn test_malformed_recording_is_an_error() {
+        let mut player = SessionPlayer::new(Cursor::new(b"{\"elapsed_ms\": 1}\n".to_vec()));
+        assert!(matches!(player.next_frame(), Err(crate::Error::Io(_))));
+
+        let line = serde_json::to_string(&Frame {
+            elapsed_ms: 0,
+            data: b"x".to_vec(),
+        })
+        .unwrap();
+        let mut player = SessionPlayer::new(Cursor::new(format!("\n{line}\n")));
+        assert_eq!(player.next_frame().unwrap().unwrap().data, b"x");
+    }
+}
+
+#[cfg(test)]
+mod export_tests {
+    use crate::core::{Color, TerminalBuffer};
+    use crate::parser::Command;
+    use crate::renderer::text::push_row;
+
+    fn buffer_with_scrollback() -> TerminalBuffer {
+        let mut buffer = TerminalBuffer::new(6, 2);
+        for line in ["one", "two", "three"] {
+            buffer.write_str(line).unwrap();
+            buffer.execute_command(Command::Execute(b'\r')).unwrap();
+            buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        }
+        buffer
+            .execute_command(Command::SetForeground(Color::RED))
+            .unwrap();
+        buffer.write_str("red").unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_export_plain_text() {
+        let buffer = buffer_with_scrollback();
+        assert_eq!(buffer.scrollback_len(), 2);
+
+        let mut out = Vec::new();
+        buffer.export(&mut out, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "one\ntwo\nthree\nred\n");
+    }
+
+    #[test]
+    fn test_export_with_styles() {
+        let buffer = buffer_with_scrollback();
+        let mut out = Vec::new();
+        buffer.export(&mut out, true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "one\ntwo\nthree\n\x1b[0;38;2;255;0;0mred\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_push_row_resets_between_runs() {
+        let mut buffer = TerminalBuffer::new(6, 1);
+        buffer
+            .execute_command(Command::SetBackground(Color::BLUE))
+            .unwrap();
+        buffer.write_str("a").unwrap();
+        buffer
+            .execute_command(Command::SetBackground(Color::BLACK))
+            .unwrap();
+        buffer.write_str("b").unwrap();
+
+        let mut line = String::new();
+        push_row(
+            &mut line,
+            buffer.grid().row(0).unwrap(),
+            true,
+            buffer.theme(),
+        );
+        assert_eq!(line, "\x1b[0;48;2;0;0;255ma\x1b[0mb");
+    }
+}
+
+#[cfg(test)]
+mod selective_erase_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    fn run(buffer: &mut TerminalBuffer, parser: &mut AnsiParser, bytes: &[u8]) {
+        for cmd in parser.parse(bytes).unwrap() {
+            buffer.execute_command(cmd).unwrap();
+        }
+    }
+
+    fn row_text(buffer: &TerminalBuffer, y: usize) -> String {
+        buffer
+            .grid()
+            .row(y)
+            .unwrap()
+            .iter()
+            .map(|c| c.character)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_decsca_and_selective_erase() {
+        let mut parser = AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"\x1b[1\"q\x1b[\"q").unwrap(),
+            vec![
+                Command::SetCharacterProtection(true),
+                Command::SetCharacterProtection(false)
+            ]
+        );
+        assert_eq!(
+            parser.parse(b"\x1b[?J\x1b[?2K").unwrap(),
+            vec![
+                Command::SelectiveEraseDisplay(0),
+                Command::SelectiveEraseLine(2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_selective_erase_skips_protected_cells() {
+        let mut parser = AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(8, 2);
+        run(&mut buffer, &mut parser, b"ab\x1b[1\"qCD\x1b[0\"qef");
+        assert!(buffer.grid().get(2, 0).unwrap().protected);
+        assert!(!buffer.grid().get(4, 0).unwrap().protected);
+
+        run(&mut buffer, &mut parser, b"\x1b[?2K");
+        assert_eq!(row_text(&buffer, 0), "  CD    ");
+
+        // A normal erase still clears protected cells
+        run(&mut buffer, &mut parser, b"\x1b[2K");
+        assert_eq!(row_text(&buffer, 0), "        ");
+    }
+
+    #[test]
+    fn test_selective_erase_is_not_a_private_mode() {
+        let mut parser = AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(4, 2);
+        run(&mut buffer, &mut parser, b"abcd\x1b[2;1Hwx");
+        assert_eq!(
+            parser.parse(b"\x1b[?2J").unwrap(),
+            vec![Command::SelectiveEraseDisplay(2)]
+        );
+        // Not mistaken for mode 1 (DECCKM) either
+        assert_eq!(
+            parser.parse(b"\x1b[?1K").unwrap(),
+            vec![Command::SelectiveEraseLine(1)]
+        );
+
+        run(&mut buffer, &mut parser, b"\x1b[?2J");
+        assert_eq!(row_text(&buffer, 0), "    ");
+        assert_eq!(row_text(&buffer, 1), "    ");
+        assert!(!buffer.is_application_cursor_keys());
+    }
+
+    #[test]
+    fn test_selective_erase_display_from_cursor() {
+        let mut parser = AnsiParser::new();
+        let mut buffer = TerminalBuffer::new(4, 3);
+        run(&mut buffer, &mut parser, b"ab\x1b[1\"qcd\x1b[0\"qwxyz");
+        run(&mut buffer, &mut parser, b"\x1b[1;2H\x1b[?J");
+        assert_eq!(row_text(&buffer, 0), "a cd");
+        assert_eq!(row_text(&buffer, 1), "    ");
+    }
+}
+
+#[cfg(test)]
+mod quote_intermediate_tests {
+    use crate::parser::{AnsiParser, Command};
+
+    #[test]
+    fn test_decscl_is_ignored() {
+        let mut parser = AnsiParser::new();
+        assert!(parser.parse(b"\x1b[64\"p").unwrap().is_empty());
+        assert!(parser.parse(b"\x1b[65;1\"p").unwrap().is_empty());
+        assert_eq!(
+            parser.parse(b"\x1b[62\"pok").unwrap(),
+            vec![Command::PrintStr("ok".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_quote_finals_do_not_alias_plain_csi() {
+        let mut parser = AnsiParser::new();
+        // Without the intermediate these would be ED and CUP
+        assert!(parser.parse(b"\x1b[2\"J\x1b[3\"H").unwrap().is_empty());
+        assert_eq!(
+            parser.parse(b"\x1b[1\"q\x1b[2J").unwrap(),
+            vec![Command::SetCharacterProtection(true), Command::ClearScreen]
+        );
+    }
+}
+
+#[cfg(test)]
+mod current_style_tests {
+    use crate::core::{CellStyle, Color, TerminalBuffer};
+
+    #[test]
+    fn test_set_style_then_write() {
+        let mut buffer = TerminalBuffer::new(5, 1);
+        let style = CellStyle {
+            bold: true,
+            ..CellStyle::default()
+        };
+        buffer.set_current_style(style);
+        buffer.set_foreground(Color::GREEN);
+        buffer.write_char('a').unwrap();
+        buffer.set_background(Color::BLUE);
+        buffer.write_char('b').unwrap();
+
+        let a = buffer.grid().get(0, 0).unwrap();
+        assert!(a.style.bold);
+        assert_eq!(a.style.fg, Color::GREEN);
+        assert_eq!(a.style.bg, CellStyle::default().bg);
+        assert_eq!(buffer.grid().get(1, 0).unwrap().style.bg, Color::BLUE);
+        assert_eq!(buffer.current_style().bg, Color::BLUE);
+    }
+
+    #[test]
+    fn test_reset_style_keeps_the_screen() {
+        let mut buffer = TerminalBuffer::new(5, 1);
+        buffer.write_char('a').unwrap();
+        buffer.set_current_style(CellStyle {
+            bold: true,
+            ..CellStyle::default()
+        });
+        buffer.set_foreground(Color::RED);
+
+        buffer.reset_style();
+        buffer.write_char('b').unwrap();
+        assert_eq!(buffer.grid().get(0, 0).unwrap().character, 'a');
+        assert_eq!(buffer.grid().get(1, 0).unwrap().style, CellStyle::default());
+
+        let default = CellStyle {
+            bg: Color::BLUE,
+            ..CellStyle::default()
+        };
+        buffer.set_default_style(default);
+        buffer.set_foreground(Color::RED);
+        buffer.reset_style();
+        assert_eq!(buffer.current_style(), default);
+    }
+}
+
+#[cfg(test)]
+mod scroll_region_cursor_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    fn run(buffer: &mut TerminalBuffer, bytes: &[u8]) {
+        let mut parser = AnsiParser::new();
+        buffer
+            .execute_commands(parser.parse(bytes).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_region_above_cursor_homes_cursor() {
+        let mut buffer = TerminalBuffer::new(10, 10);
+        run(&mut buffer, b"\x1b[9;4H\x1b[2;5r");
+        assert_eq!(buffer.cursor_pos(), (0, 0));
+
+        // In origin mode home is the region top
+        run(&mut buffer, b"\x1b[?6h\x1b[9;4H\x1b[3;6r");
+        assert_eq!(buffer.cursor_pos(), (0, 2));
+        buffer.write_str("x").unwrap();
+        assert_eq!(buffer.grid().get(0, 2).unwrap().character, 'x');
+    }
+
+    #[test]
+    fn test_shrinking_screen_clamps_region_and_cursor() {
+        let mut buffer = TerminalBuffer::new(10, 10);
+        run(&mut buffer, b"\x1b[3;10r\x1b[?6h\x1b[8;1H");
+        assert_eq!(buffer.cursor_pos(), (0, 9));
+
+        buffer.resize(10, 6).unwrap();
+        assert_eq!(buffer.cursor_pos(), (0, 5));
+
+        // The region's bottom now follows the smaller screen
+        buffer.execute_command(Command::MoveCursor(0, 20)).unwrap();
+        assert_eq!(buffer.cursor_pos(), (0, 5));
+        buffer.execute_command(Command::MoveCursor(0, 0)).unwrap();
+        assert_eq!(buffer.cursor_pos(), (0, 2));
+
+        // A region that no longer fits is dropped
+        buffer.resize(10, 2).unwrap();
+        buffer.execute_command(Command::MoveCursor(0, 0)).unwrap();
+        assert_eq!(buffer.cursor_pos(), (0, 0));
+    }
+
+    #[test]
+    fn test_cup_past_region_clamps_to_region_bottom() {
+        let mut buffer = TerminalBuffer::new(80, 24);
+        run(&mut buffer, b"\x1b[5;15r\x1b[?6h\x1b[100;100H");
+        assert_eq!(buffer.cursor_pos(), (79, 14));
+
+        // Rows count from the region top, and HVP behaves the same
+        run(&mut buffer, b"\x1b[3;2H");
+        assert_eq!(buffer.cursor_pos(), (1, 6));
+        run(&mut buffer, b"\x1b[11;1f");
+        assert_eq!(buffer.cursor_pos(), (0, 14));
+        run(&mut buffer, b"\x1b[0;0H");
+        assert_eq!(buffer.cursor_pos(), (0, 4));
+    }
+
+    #[test]
+    fn test_cup_without_origin_mode_ignores_region() {
+        let mut buffer = TerminalBuffer::new(80, 24);
+        run(&mut buffer, b"\x1b[5;15r\x1b[100;1H");
+        assert_eq!(buffer.cursor_pos(), (0, 23));
+        run(&mut buffer, b"\x1b[2;1H");
+        assert_eq!(buffer.cursor_pos(), (0, 1));
+    }
+}
+
+#[cfg(test)]
+mod decrqss_tests {
+    use crate::core::{CellStyle, Color, TerminalBuffer};
+    use crate::parser::AnsiParser;
+
+    fn feed(buffer: &mut TerminalBuffer, bytes: &[u8]) {
+        let mut parser = AnsiParser::new();
+        buffer
+            .execute_commands(parser.parse(bytes).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_decrqss_sgr() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        feed(&mut buffer, b"\x1b[1;31m\x1bP$qm\x1b\\");
+        assert_eq!(
+            buffer.drain_responses(),
+            vec![b"\x1bP1$r0;1;31m\x1b\\".to_vec()]
+        );
+
+        // Colors outside the palette come back as truecolor
+        feed(&mut buffer, b"\x1b[0;4:3;48;2;1;2;3m\x1bP$qm\x1b\\");
+        assert_eq!(
+            buffer.drain_responses(),
+            vec![b"\x1bP1$r0;4:3;48;2;1;2;3m\x1b\\".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_sgr_params_round_trip() {
+        let mut style = CellStyle::default();
+        style.apply_sgr(&[3, 7, 94, 38, 5, 200]);
+        let mut parsed = CellStyle::default();
+        let params: Vec<u16> = style
+            .sgr_params()
+            .split(';')
+            .map(|p| p.parse().unwrap())
+            .collect();
+        parsed.apply_sgr(&params);
+        assert_eq!(parsed, style);
+        assert_ne!(style.fg, Color::WHITE);
+    }
+
+    #[test]
+    fn test_decrqss_scroll_region() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        feed(&mut buffer, b"\x1bP$qr\x1b\\");
+        assert_eq!(
+            buffer.drain_responses(),
+            vec![b"\x1bP1$r1;5r\x1b\\".to_vec()]
+        );
+
+        feed(&mut buffer, b"\x1b[2;4r\x1bP$qr\x1b\\");
+        assert_eq!(
+            buffer.drain_responses(),
+            vec![b"\x1bP1$r2;4r\x1b\\".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_decrqss_margins_can_be_saved_and_restored() {
+        let mut buffer = TerminalBuffer::new(80, 24);
+        feed(&mut buffer, b"\x1b[3;10r\x1bP$qr\x1b\\");
+        let reply = buffer.drain_responses().remove(0);
+        assert_eq!(reply, b"\x1bP1$r3;10r\x1b\\");
+
+        feed(&mut buffer, b"\x1b[r\x1bP$qr\x1b\\");
+        assert_eq!(
+            buffer.drain_responses(),
+            vec![b"\x1bP1$r1;24r\x1b\\".to_vec()]
+        );
+
+        // The reply's body is the DECSTBM that puts the margins back
+        let mut restore = b"\x1b[".to_vec();
+        restore.extend_from_slice(&reply[5..reply.len() - 2]);
+        feed(&mut buffer, &restore);
+        feed(&mut buffer, b"\x1bP$qr\x1b\\");
+        assert_eq!(buffer.drain_responses(), vec![reply]);
+    }
+}
+
+#[cfg(test)]
+mod input_encoding_tests {
+    use crate::renderer::AndroidRenderer;
+    use crate::{InputEncoding, TerminalEngine};
+
+    fn engine() -> TerminalEngine {
+        TerminalEngine::new(10, 2, Box::new(AndroidRenderer::new(12.0)))
+    }
+
+    fn first_chars(engine: &TerminalEngine, n: usize) -> String {
+        (0..n)
+            .map(|x| engine.buffer().grid().get(x, 0).unwrap().character)
+            .collect()
+    }
+
+    #[test]
+    fn test_high_byte_in_utf8_mode_is_replacement() {
+        let mut engine = engine();
+        assert_eq!(engine.input_encoding(), InputEncoding::Utf8);
+        engine.write(b"caf\xe9!").unwrap();
+        assert_eq!(first_chars(&engine, 5), "caf\u{fffd}!");
+    }
+
+    #[test]
+    fn test_high_byte_in_latin1_mode() {
+        let mut engine = engine();
+        engine.set_input_encoding(InputEncoding::Latin1);
+        engine.write(b"caf\xe9 \xb0").unwrap();
+        assert_eq!(first_chars(&engine, 6), "café °");
+
+        // Escape sequences still parse
+        engine.write(b"\x1b[2;1H\xff").unwrap();
+        assert_eq!(engine.buffer().grid().get(0, 1).unwrap().character, 'ÿ');
+    }
+}
+
+#[cfg(test)]
+mod theme_tests {
+    use crate::{AnsiParser, Color, Command, Theme};
+
+    fn custom_red() -> Theme {
+        let mut theme = Theme::default();
+        theme.ansi[1] = Color::new(200, 10, 60);
+        theme
+    }
+
+    #[test]
+    fn test_theme_red_is_used_for_sgr_31() {
+        let mut parser = AnsiParser::new();
+        parser.set_theme(custom_red());
+        let cmds = parser.parse(b"\x1b[31m").unwrap();
+        assert!(cmds.contains(&Command::SetForeground(Color::new(200, 10, 60))));
+    }
+
+    #[test]
+    fn test_default_colors_follow_theme() {
+        let mut parser = AnsiParser::new();
+        parser.set_theme(Theme {
+            foreground: Color::new(40, 40, 40),
+            background: Color::new(250, 250, 240),
+            ..Theme::default()
+        });
+        parser.parse(b"\x1b[31;42m").unwrap();
+        let cmds = parser.parse(b"\x1b[39;49m").unwrap();
+        assert!(cmds.contains(&Command::SetForeground(Color::new(40, 40, 40))));
+        assert!(cmds.contains(&Command::SetBackground(Color::new(250, 250, 240))));
+    }
+
+    #[test]
+    fn test_osc4_overrides_layer_on_theme() {
+        let mut parser = AnsiParser::new();
+        parser.set_theme(custom_red());
+        parser.parse(b"\x1b]4;1;rgb:00/80/ff\x07").unwrap();
+        let cmds = parser.parse(b"\x1b[31m").unwrap();
+        assert!(cmds.contains(&Command::SetForeground(Color::new(0, 128, 255))));
+
+        // OSC 104 drops the override and the theme shows through again
+        parser.parse(b"\x1b]104;1\x07\x1b[0m").unwrap();
+        let cmds = parser.parse(b"\x1b[31m").unwrap();
+        assert!(cmds.contains(&Command::SetForeground(Color::new(200, 10, 60))));
+    }
+
+    fn light() -> Theme {
+        Theme {
+            foreground: Color::new(40, 40, 40),
+            background: Color::new(250, 250, 240),
+            ..custom_red()
+        }
+    }
+
+    fn themed_engine() -> crate::TerminalEngine {
+        let renderer = Box::new(crate::ScreenRenderer::new());
+        let mut engine = crate::TerminalEngine::new(6, 3, renderer);
+        engine.write(b"hi").unwrap();
+        engine.set_theme(light());
+        engine
+    }
+
+    #[test]
+    fn test_blank_cells_take_theme_colors() {
+        let mut engine = themed_engine();
+        let bg = |engine: &crate::TerminalEngine, x, y| {
+            engine.buffer().grid().get(x, y).unwrap().style.bg
+        };
+        let background = light().background;
+        // The initial screen, and text written before the theme was set
+        assert_eq!(bg(&engine, 5, 2), background);
+        assert_eq!(
+            engine.buffer().grid().get(0, 0).unwrap().style.fg,
+            Color::new(40, 40, 40)
+        );
+
+        engine.write(b"\x1b[2J\x1b[H\x1b[K\n\n\n").unwrap();
+        for (x, y) in [(0, 0), (3, 1), (5, 2)] {
+            assert_eq!(bg(&engine, x, y), background, "({x}, {y})");
+        }
+        engine.resize(8, 4).unwrap();
+        assert_eq!(bg(&engine, 7, 3), background);
+        engine.write(b"\x1b[?1049h").unwrap();
+        assert_eq!(bg(&engine, 0, 0), background);
+    }
+
+    #[test]
+    fn test_reports_and_exports_use_theme() {
+        let mut engine = themed_engine();
+        engine.write(b"\x1b[31m\x1bP$qm\x1b\\").unwrap();
+        let replies = engine.buffer_mut().drain_responses();
+        assert_eq!(replies, vec![b"\x1bP1$r0;31m\x1b\\".to_vec()]);
+
+        let mut text = Vec::new();
+        engine.buffer().export(&mut text, true).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "hi\n\n\n");
+        assert_eq!(crate::to_html(engine.buffer()), "<pre>hi\n\n</pre>");
+    }
+
+    #[test]
+    fn test_reports_and_exports_follow_osc4_overrides() {
+        let mut engine = themed_engine();
+        engine
+            .write(b"\x1b]4;1;rgb:00/80/ff\x07\x1b[31mx\x1bP$qm\x1b\\")
+            .unwrap();
+        let replies = engine.buffer_mut().drain_responses();
+        assert_eq!(replies, vec![b"\x1bP1$r0;31m\x1b\\".to_vec()]);
+
+        let mut text = Vec::new();
+        engine.buffer().export(&mut text, true).unwrap();
+        assert!(String::from_utf8(text).unwrap().contains("\x1b[0;31mx"));
+
+        // Once reset, the override's color is no longer palette entry 1
+        engine.write(b"\x1b]104\x07\x1bP$qm\x1b\\").unwrap();
+        let replies = engine.buffer_mut().drain_responses();
+        assert_eq!(replies, vec![b"\x1bP1$r0;38;2;0;128;255m\x1b\\".to_vec()]);
+    }
+
+    #[test]
+    fn test_osc12_query_reports_theme_cursor() {
+        let mut engine = themed_engine();
+        engine.set_theme(Theme {
+            cursor: Color::new(0xff, 0x80, 0x00),
+            ..light()
+        });
+        engine.write(b"\x1b]12;?\x07\x1b]12;#000000\x07").unwrap();
+        let replies = engine.buffer_mut().drain_responses();
+        assert_eq!(replies, vec![b"\x1b]12;rgb:ffff/8080/0000\x1b\\".to_vec()]);
+    }
+}
+
+#[cfg(test)]
+mod backarrow_key_tests {
+    use crate::{AnsiParser, InputHandler, Key, KeyEvent, TerminalBuffer};
+
+    fn backspace_after(sequence: &[u8]) -> Vec<u8> {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        let mut parser = AnsiParser::new();
+        buffer
+            .execute_commands(parser.parse(sequence).unwrap())
+            .unwrap();
+        let mut handler = InputHandler::new();
+        handler.set_backspace_byte(buffer.backspace_byte());
+        handler.handle_key(KeyEvent::new(Key::Backspace)).unwrap()
+    }
+
+    #[test]
+    fn test_backspace_sends_del_by_default() {
+        assert_eq!(backspace_after(b""), vec![0x7f]);
+        assert_eq!(backspace_after(b"\x1b[?67h\x1b[?67l"), vec![0x7f]);
+    }
+
+    #[test]
+    fn test_decbkm_sends_bs() {
+        assert_eq!(backspace_after(b"\x1b[?67h"), vec![0x08]);
+    }
+
+    #[test]
+    fn test_decbkm_is_saved_and_restored() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        let mut parser = AnsiParser::new();
+        let cmds = parser.parse(b"\x1b[?67h\x1b[?67s\x1b[?67l").unwrap();
+        buffer.execute_commands(cmds).unwrap();
+        assert_eq!(buffer.private_mode(67), Some(false));
+        let cmds = parser.parse(b"\x1b[?67r").unwrap();
+        buffer.execute_commands(cmds).unwrap();
+        assert_eq!(buffer.backspace_byte(), 0x08);
+    }
+}
+
+#[cfg(test)]
+mod capabilities_tests {
+    use crate::{AnsiParser, CAPABILITIES, TerminalBuffer};
+
+    fn replies(sequence: &[u8]) -> Vec<String> {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        let mut parser = AnsiParser::new();
+        buffer
+            .execute_commands(parser.parse(sequence).unwrap())
+            .unwrap();
+        buffer
+            .drain_responses()
+            .into_iter()
+            .map(|r| String::from_utf8(r).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_primary_da_comes_from_the_table() {
+        assert_eq!(replies(b"\x1b[c"), vec![CAPABILITIES.primary_da()]);
+    }
+
+    #[test]
+    fn test_termcap_colors_match_the_table() {
+        let hex = |s: &str| s.bytes().map(|b| format!("{b:02X}")).collect::<String>();
+        let colors = CAPABILITIES.colors.to_string();
+        assert!(CAPABILITIES.term.ends_with(&format!("{colors}color")));
+        for name in ["Co", "colors"] {
+            assert_eq!(CAPABILITIES.termcap(name), Some(colors.clone()));
+            let query = format!("\x1bP+q{}\x1b\\", hex(name));
+            assert_eq!(
+                replies(query.as_bytes()),
+                vec![format!("\x1bP1+r{}={}\x1b\\", hex(name), hex(&colors))]
+            );
+        }
+    }
+
+    #[test]
+    fn test_secondary_da_is_not_primary() {
+        assert_eq!(replies(b"\x1b[>c"), vec!["\x1b[>1;100;0c".to_string()]);
+        assert_eq!(replies(b"\x1b[>0c"), replies(b"\x1b[>c"));
+    }
+
+    #[test]
+    fn test_termcap_replies_per_name() {
+        // "TN" and an unknown "zz", one reply each
+        assert_eq!(
+            replies(b"\x1bP+q544E;7A7A\x1b\\"),
+            vec![
+                "\x1bP1+r544E=787465726D2D323536636F6C6F72\x1b\\".to_string(),
+                "\x1bP0+r7A7A\x1b\\".to_string(),
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod direction_hint_tests {
+    use crate::core::{Direction, TerminalBuffer};
+    use crate::parser::Command;
+
+    #[test]
+    fn test_hebrew_line_is_rtl() {
+        let mut buffer = TerminalBuffer::new(20, 3);
+        buffer.write_str("שלום עולם").unwrap();
+        assert!(buffer.grid().is_rtl(0));
+        assert!(!buffer.grid().is_rtl(1));
+    }
+
+    #[test]
+    fn test_first_strong_char_decides() {
+        let mut buffer = TerminalBuffer::new(20, 3);
+        // Digits and punctuation are neutral, so the Arabic word decides
+        buffer.write_str("12. مرحبا hello").unwrap();
+        assert!(buffer.grid().is_rtl(0));
+
+        buffer.execute_command(Command::Execute(b'\r')).unwrap();
+        buffer.execute_command(Command::Execute(b'\n')).unwrap();
+        buffer.write_str("hello שלום").unwrap();
+        assert_eq!(buffer.grid().row_direction(1), Some(Direction::Ltr));
+        assert_eq!(buffer.grid().row_direction(2), None);
+    }
+
+    #[test]
+    fn test_wrapped_rows_keep_line_direction() {
+        let mut buffer = TerminalBuffer::new(4, 3);
+        buffer.write_str("אבגדabc").unwrap();
+        assert!(buffer.grid().is_row_wrapped(0));
+        assert!(buffer.grid().is_rtl(1));
+
+        // Erasing the screen drops the hints
+        buffer.execute_command(Command::ClearScreen).unwrap();
+        assert_eq!(buffer.grid().row_direction(0), None);
+    }
+}
+
+#[cfg(test)]
+mod grapheme_cursor_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::Command;
+
+    // Man, ZWJ, woman, ZWJ, girl: three wide cells joined into one cluster
+    const FAMILY: &str = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+
+    fn row_text(buffer: &TerminalBuffer) -> String {
+        let row = buffer.grid().row(0).unwrap();
+        row.iter()
+            .filter(|c| !c.wide_spacer)
+            .map(|c| c.character)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    #[test]
+    fn test_left_over_combined_e_lands_on_base_char() {
+        let mut buffer = TerminalBuffer::new(10, 2);
+        buffer.write_str("ae\u{301}").unwrap();
+        assert_eq!(buffer.cursor_pos(), (2, 0));
+        buffer
+            .execute_command(Command::MoveCursorRelative(-1, 0))
+            .unwrap();
+        assert_eq!(buffer.cursor_pos(), (1, 0));
+        assert_eq!(buffer.grid().get(1, 0).unwrap().character, 'e');
+    }
+
+    #[test]
+    fn test_cursor_moves_skip_zwj_sequence() {
+        let mut buffer = TerminalBuffer::new(12, 2);
+        buffer.write_str("a").unwrap();
+        buffer.write_str(FAMILY).unwrap();
+        buffer.write_str("b").unwrap();
+        assert_eq!(buffer.cursor_pos(), (8, 0));
+
+        // From 'b', one step left lands inside the emoji: snap to its start
+        buffer
+            .execute_command(Command::MoveCursorRelative(-2, 0))
+            .unwrap();
+        assert_eq!(buffer.cursor_pos(), (1, 0));
+
+        buffer
+            .execute_command(Command::MoveCursorRelative(1, 0))
+            .unwrap();
+        assert_eq!(buffer.cursor_pos(), (7, 0));
+    }
+
+    #[test]
+    fn test_delete_removes_whole_cluster() {
+        let mut buffer = TerminalBuffer::new(12, 2);
+        buffer.write_str("a").unwrap();
+        buffer.write_str(FAMILY).unwrap();
+        buffer.write_str("b").unwrap();
+        buffer.execute_command(Command::MoveCursor(1, 0)).unwrap();
+        buffer.execute_command(Command::DeleteChars(1)).unwrap();
+        assert_eq!(row_text(&buffer), "ab");
+
+        // Plain cells still delete one at a time
+        buffer.execute_command(Command::MoveCursor(0, 0)).unwrap();
+        buffer.execute_command(Command::DeleteChars(1)).unwrap();
+        assert_eq!(row_text(&buffer), "b");
+    }
+
+    #[test]
+    fn test_backspace_still_steps_by_column() {
+        let mut buffer = TerminalBuffer::new(10, 2);
+        buffer.write_str("\u{4E2D}").unwrap();
+        buffer.execute_command(Command::Execute(0x08)).unwrap();
+        assert_eq!(buffer.cursor_pos(), (1, 0));
+    }
+}
+
+#[cfg(test)]
+mod query_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    #[test]
+    fn test_query_returns_cpr_bytes() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.execute_command(Command::MoveCursor(3, 2)).unwrap();
+        let reply = buffer.query(Command::CursorPositionReport).unwrap();
+        assert_eq!(reply, b"\x1b[3;4R");
+        assert!(!buffer.has_pending_responses());
+    }
+
+    #[test]
+    fn test_decdsr_replies() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        let mut parser = AnsiParser::new();
+        crate::feed(
+            &mut buffer,
+            &mut parser,
+            b"\x1b[3;4H\x1b[?6n\x1b[?15n\x1b[?99n",
+        )
+        .unwrap();
+        assert_eq!(
+            buffer.drain_responses(),
+            vec![b"\x1b[?3;4;1R".to_vec(), b"\x1b[?11n".to_vec()]
+        );
+        // The plain CPR is unchanged
+        assert_eq!(
+            buffer.query(Command::CursorPositionReport).unwrap(),
+            b"\x1b[3;4R"
+        );
+    }
+
+    #[test]
+    fn test_query_leaves_earlier_replies_queued() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer
+            .execute_command(Command::DeviceAttributeQuery)
+            .unwrap();
+        assert_eq!(
+            buffer.query(Command::CursorPositionReport).unwrap(),
+            b"\x1b[1;1R"
+        );
+        assert!(buffer.query(Command::Print('x')).unwrap().is_empty());
+        assert_eq!(buffer.drain_responses().len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod keypad_tests {
+    use crate::{AnsiParser, InputEvent, InputHandler, Key, KeyEvent, KeypadKey, TerminalBuffer};
+
+    const KEYPAD_5: Key = Key::Keypad(KeypadKey::Digit(5));
+
+    #[test]
+    fn test_keypad_5_in_numeric_mode() {
+        let mut handler = InputHandler::new();
+        assert_eq!(handler.handle_key(KeyEvent::new(KEYPAD_5)).unwrap(), b"5");
+        assert_eq!(KeyEvent::new(KEYPAD_5).to_ansi(), b"5");
+    }
+
+    #[test]
+    fn test_keypad_5_in_application_mode() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        let mut parser = AnsiParser::new();
+        buffer
+            .execute_commands(parser.parse(b"\x1b=").unwrap())
+            .unwrap();
+        assert!(buffer.is_application_keypad());
+
+        let mut handler = InputHandler::new();
+        handler.set_application_keypad(buffer.is_application_keypad());
+        let bytes = handler.handle_key(KeyEvent::new(KEYPAD_5)).unwrap();
+        assert_eq!(bytes, b"\x1bOu");
+        assert_eq!(
+            handler.decode(&bytes),
+            vec![InputEvent::Key(KeyEvent::new(KEYPAD_5))]
+        );
+
+        buffer
+            .execute_commands(parser.parse(b"\x1b>").unwrap())
+            .unwrap();
+        assert!(!buffer.is_application_keypad());
+    }
+
+    #[test]
+    fn test_keypad_enter_and_operators() {
+        let mut handler = InputHandler::new();
+        handler.set_application_keypad(true);
+        let key = |k| KeyEvent::new(Key::Keypad(k));
+        assert_eq!(
+            handler.handle_key(key(KeypadKey::Enter)).unwrap(),
+            b"\x1bOM"
+        );
+        assert_eq!(
+            handler.handle_key(key(KeypadKey::Minus)).unwrap(),
+            b"\x1bOm"
+        );
+        assert_eq!(key(KeypadKey::Enter).to_ansi(), b"\r");
+        assert_eq!(key(KeypadKey::Multiply).to_ansi(), b"*");
+    }
+}
+
+#[cfg(test)]
+mod c1_transmission_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    fn feed(buffer: &mut TerminalBuffer, parser: &mut AnsiParser, bytes: &[u8]) {
+        buffer
+            .execute_commands(parser.parse(bytes).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_s8c1t_replies_use_8bit_introducers() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        let mut parser = AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"\x1b G").unwrap(),
+            vec![Command::SetC1Transmission(true)]
+        );
+
+        feed(&mut buffer, &mut parser, b"\x1b G\x1b[6n\x1bP$qm\x1b\\");
+        assert_eq!(
+            buffer.drain_responses(),
+            vec![b"\x9b1;1R".to_vec(), b"\x901$r0m\x9c".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_s7c1t_is_the_default_and_restores_7bit() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        let mut parser = AnsiParser::new();
+        feed(&mut buffer, &mut parser, b"\x1b[6n\x1b G\x1b F\x1b[6n");
+        assert_eq!(
+            buffer.drain_responses(),
+            vec![b"\x1b[1;1R".to_vec(), b"\x1b[1;1R".to_vec()]
+        );
+    }
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use crate::core::{Cell, CellRuns, Grid, TerminalBuffer};
+    use crate::parser::AnsiParser;
+
+    fn buffer_with_output(bytes: &[u8]) -> TerminalBuffer {
+        let mut buffer = TerminalBuffer::new(80, 24);
+        let mut parser = AnsiParser::new();
+        buffer
+            .execute_commands(parser.parse(bytes).unwrap())
+            .unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_grid_round_trips_compactly() {
+        let mut output = Vec::new();
+        for i in 0..30 {
+            output.extend_from_slice(format!("\x1b[3{}mline {i}\x1b[0m\r\n", i % 8).as_bytes());
+        }
+        output.extend_from_slice(&[b'x'; 100]);
+        let buffer = buffer_with_output(&output);
+        let grid = buffer.grid();
+
+        let cells: Vec<Cell> = (0..grid.height())
+            .flat_map(|y| grid.row(y).unwrap().to_vec())
+            .collect();
+        let naive = serde_json::to_string(&cells).unwrap();
+        let encoded = serde_json::to_string(grid).unwrap();
+        assert!(
+            encoded.len() * 10 < naive.len(),
+            "{} vs {}",
+            encoded.len(),
+            naive.len()
+        );
+
+        let restored: Grid = serde_json::from_str(&encoded).unwrap();
+        assert_eq!((restored.width(), restored.height()), (80, 24));
+        for y in 0..grid.height() {
+            assert_eq!(restored.row(y), grid.row(y));
+            assert_eq!(restored.is_row_wrapped(y), grid.is_row_wrapped(y));
+        }
+        assert!(grid.is_row_wrapped(22));
+    }
+
+    #[test]
+    fn test_scrollback_round_trips() {
+        let mut output = Vec::new();
+        for i in 0..40 {
+            output.extend_from_slice(format!("\x1b[1mrow {i}\r\n").as_bytes());
+        }
+        let buffer = buffer_with_output(&output);
+        let json = serde_json::to_string(&buffer.scrollback_snapshot()).unwrap();
+
+        let mut restored = TerminalBuffer::new(80, 24);
+        restored
+            .restore_scrollback(serde_json::from_str(&json).unwrap())
+            .unwrap();
+        assert_eq!(restored.scrollback_len(), buffer.scrollback_len());
+        for i in 0..buffer.scrollback_len() {
+            assert_eq!(restored.scrollback_row(i), buffer.scrollback_row(i));
+        }
+
+        restored.set_scrollback_limit(5);
+        restored
+            .restore_scrollback(buffer.scrollback_snapshot())
+            .unwrap();
+        assert_eq!(restored.scrollback_len(), 5);
+        assert_eq!(
+            restored.scrollback_row(0),
+            buffer.scrollback_row(buffer.scrollback_len() - 5)
+        );
+    }
+
+    #[test]
+    fn test_mismatched_snapshot_is_rejected() {
+        let mut json = serde_json::to_value(Grid::new(4, 2)).unwrap();
+        json["height"] = 3.into();
+        assert!(serde_json::from_value::<Grid>(json).is_err());
+
+        let runs = CellRuns::encode(&vec![Cell::default(); 4]);
+        assert_eq!((runs.runs(), runs.len()), (1, Some(4)));
+    }
+
+    #[test]
+    fn test_oversized_runs_are_rejected_before_decoding() {
+        // Two runs of u32::MAX cells claim far more than a 4x2 grid holds
+        let mut json = serde_json::to_value(Grid::new(4, 2)).unwrap();
+        let cell = serde_json::to_value(Cell::default()).unwrap();
+        json["cells"] = serde_json::json!([[u32::MAX, cell], [u32::MAX, cell]]);
+        assert!(serde_json::from_value::<Grid>(json).is_err());
+
+        let runs: CellRuns = serde_json::from_value(serde_json::json!([[u32::MAX, cell]])).unwrap();
+        assert_eq!(runs.decode(80), None);
+        let mut buffer = TerminalBuffer::new(80, 24);
+        assert!(buffer.restore_scrollback(vec![runs]).is_err());
+        assert_eq!(buffer.scrollback_len(), 0);
+    }
+
+    #[test]
+    fn test_huge_width_snapshot_is_rejected() {
+        // One row claiming u32::MAX columns, filled by a single run
+        let mut json = serde_json::to_value(Grid::new(4, 1)).unwrap();
+        let cell = serde_json::to_value(Cell::default()).unwrap();
+        json["width"] = u32::MAX.into();
+        json["cells"] = serde_json::json!([[u32::MAX, cell]]);
+        assert!(serde_json::from_value::<Grid>(json).is_err());
+    }
+
+    #[test]
+    fn test_huge_area_snapshot_is_rejected() {
+        // Both sides are within bounds, but one run claims every cell of a
+        // 65535x65535 grid
+        let side = u16::MAX as usize;
+        let cell = serde_json::to_value(Cell::default()).unwrap();
+        let json = serde_json::json!({
+            "width": side,
+            "height": side,
+            "cells": [[side * side, cell]],
+            "wrapped": vec![false; side],
+            "direction": vec![serde_json::Value::Null; side],
+            "blank_style": serde_json::to_value(crate::core::CellStyle::default()).unwrap(),
+            "line_attrs": vec!["Single"; side],
+        });
+        assert!(serde_json::from_value::<Grid>(json).is_err());
+    }
+
+    #[test]
+    fn test_zero_width_snapshot_is_rejected() {
+        let mut json = serde_json::to_value(Grid::new(4, 2)).unwrap();
+        json["width"] = 0.into();
+        json["cells"] = serde_json::json!([]);
+        assert!(serde_json::from_value::<Grid>(json).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_without_line_attrs_is_rejected() {
+        let mut json = serde_json::to_value(Grid::new(4, 2)).unwrap();
+        json.as_object_mut().unwrap().remove("line_attrs");
+        assert!(serde_json::from_value::<Grid>(json).is_err());
+    }
+}
+
+#[cfg(test)]
+mod vertical_motion_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    fn run(buffer: &mut TerminalBuffer, bytes: &[u8]) {
+        let mut parser = AnsiParser::new();
+        buffer
+            .execute_commands(parser.parse(bytes).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_vt_and_ff_move_down_a_row() {
+        for byte in [0x0b, 0x0c] {
+            let mut buffer = TerminalBuffer::new(10, 5);
+            run(&mut buffer, b"\x1b[2;4H");
+            run(&mut buffer, &[byte]);
+            assert_eq!(buffer.cursor_pos(), (3, 2), "byte {byte:#x}");
+        }
+    }
+
+    #[test]
+    fn test_nel_is_carriage_return_and_line_feed() {
+        let mut parser = AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"\x1bE").unwrap(),
+            vec![Command::Execute(0x85)]
+        );
+
+        for bytes in [&b"\x1bE"[..], b"\xc2\x85"] {
+            let mut buffer = TerminalBuffer::new(10, 5);
+            run(&mut buffer, b"\x1b[2;4H");
+            run(&mut buffer, bytes);
+            assert_eq!(buffer.cursor_pos(), (0, 2), "{bytes:?}");
+        }
+    }
+
+    #[test]
+    fn test_line_movement_scrolls_the_region() {
+        for bytes in [&b"\n"[..], b"\x0b", b"\x0c", b"\x1bE"] {
+            let mut buffer = TerminalBuffer::new(10, 5);
+            run(
+                &mut buffer,
+                b"top\x1b[5;1Hlast\x1b[2;3r\x1b[2;1Hone\x1b[3;1Htwo",
+            );
+            run(&mut buffer, bytes);
+
+            assert_eq!(buffer.cursor_pos().1, 2, "{bytes:?}");
+            let row = |y| buffer.grid().get(0, y).unwrap().character;
+            assert_eq!([row(0), row(1), row(2), row(4)], ['t', 't', ' ', 'l']);
+            assert_eq!(buffer.scrollback_len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_autowrap_scrolls_the_region() {
+        let mut buffer = TerminalBuffer::new(4, 5);
+        run(&mut buffer, b"\x1b[2;3r\x1b[3;1Habcdefg");
+
+        let text = |y| -> String {
+            let row = buffer.grid().row(y).unwrap();
+            row.iter().map(|c| c.character).collect()
+        };
+        assert_eq!(text(1), "abcd");
+        assert_eq!(text(2), "efg ");
+        // Wrapping must not walk out of the region into the rows below it
+        assert_eq!(text(3), "    ");
+        assert_eq!(buffer.cursor_pos(), (3, 2));
+        assert_eq!(buffer.scrollback_len(), 0);
+        assert!(buffer.grid().is_row_wrapped(1));
+    }
+}
+
+#[cfg(test)]
+mod current_line_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::AnsiParser;
+
+    fn run(buffer: &mut TerminalBuffer, bytes: &[u8]) {
+        let mut parser = AnsiParser::new();
+        buffer
+            .execute_commands(parser.parse(bytes).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_returns_the_typed_command() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        run(&mut buffer, b"old output\r\n\x1b[32m$\x1b[0m ls -la");
+        assert_eq!(buffer.current_line_text(), "$ ls -la");
+
+        run(&mut buffer, b"\r\n");
+        assert_eq!(buffer.current_line_text(), "");
+    }
+
+    #[test]
+    fn test_joins_soft_wrapped_rows() {
+        let mut buffer = TerminalBuffer::new(8, 5);
+        run(&mut buffer, b"$ echo \xe4\xb8\xad hello world   ");
+        // The cursor sits on the final row; the line started two rows up
+        assert_eq!(buffer.cursor_pos().1, 2);
+        assert_eq!(buffer.current_line_text(), "$ echo \u{4e2d} hello world");
+
+        run(&mut buffer, b"\x1b[1;1H");
+        assert_eq!(buffer.current_line_text(), "$ echo \u{4e2d} hello world");
+    }
+
+    #[test]
+    fn test_word_at_snaps_to_word() {
+        let mut buffer = TerminalBuffer::new(30, 3);
+        run(
+            &mut buffer,
+            "cat ~/src/main.rs, \u{4e2d}\u{6587}ok".as_bytes(),
+        );
+        let word = |x| buffer.word_at(x, 0);
+        assert_eq!(word(9), Some((4..17, "~/src/main.rs".to_string())));
+        assert_eq!(word(4), word(16));
+        assert_eq!(word(0), Some((0..3, "cat".to_string())));
+        // Punctuation and blanks aren't words
+        assert_eq!(word(17), None);
+        assert_eq!(word(18), None);
+        // Clicking a wide character's spacer takes the whole word
+        assert_eq!(word(20), Some((19..25, "\u{4e2d}\u{6587}ok".to_string())));
+        assert_eq!(buffer.word_at(40, 0), None);
+
+        buffer.set_word_chars("");
+        assert_eq!(buffer.word_at(7, 0), Some((6..9, "src".to_string())));
+    }
+
+    #[test]
+    fn test_line_at_selects_wrapped_line() {
+        let mut buffer = TerminalBuffer::new(8, 5);
+        run(&mut buffer, b"first\r\n$ echo hello world\r\nlast");
+        let line = (1..4, "$ echo hello world".to_string());
+        assert_eq!(buffer.line_at(1), line);
+        assert_eq!(buffer.line_at(3), line);
+        assert_eq!(buffer.line_at(0), (0..1, "first".to_string()));
+        assert_eq!(buffer.line_at(99), (4..5, "last".to_string()));
+    }
+}
+
+#[cfg(test)]
+mod hyperlink_tests {
+    use crate::core::Hyperlink;
+    use crate::parser::{AnsiParser, Command};
+
+    fn link(osc: &[u8]) -> Option<Hyperlink> {
+        let mut parser = AnsiParser::new();
+        match parser.parse(osc).unwrap().as_slice() {
+            [Command::SetHyperlink(link)] => link.clone(),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_uri_with_semicolons_is_reassembled() {
+        let link = link(b"\x1b]8;id=doc;https://example.com/a;b?c=1;d\x1b\\").unwrap();
+        assert_eq!(link.uri(), "https://example.com/a;b?c=1;d");
+        assert_eq!(link.id(), "doc");
+    }
+
+    #[test]
+    fn test_id_is_found_among_other_params() {
+        let link = link(b"\x1b]8;foo=bar:id=x1;file:///tmp\x07").unwrap();
+        assert_eq!(link.id(), "x1");
+        assert_eq!(link.uri(), "file:///tmp");
+    }
+
+    #[test]
+    fn test_missing_id_gets_a_generated_one() {
+        let first = link(b"\x1b]8;;https://a.example\x07").unwrap();
+        let second = link(b"\x1b]8;id=;https://a.example\x07").unwrap();
+        assert_eq!(first.uri(), "https://a.example");
+        assert!(!first.id().is_empty());
+        assert_ne!(first.id(), second.id());
+    }
+
+    #[test]
+    fn test_missing_or_empty_uri_ends_the_link() {
+        assert_eq!(link(b"\x1b]8;;\x07"), None);
+        assert_eq!(link(b"\x1b]8;id=x\x07"), None);
+        assert_eq!(link(b"\x1b]8\x07"), None);
+    }
+}
+
+#[cfg(test)]
+mod pixel_size_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    #[test]
+    fn test_csi_14t_reports_the_pixel_size() {
+        let mut parser = AnsiParser::new();
+        let commands = parser.parse(b"\x1b[14t").unwrap();
+        assert_eq!(commands, vec![Command::TextAreaPixelSizeReport]);
+
+        let mut buffer = TerminalBuffer::new(80, 24);
+        buffer.set_pixel_size(640, 384);
+        buffer.execute_commands(commands).unwrap();
+        assert_eq!(buffer.drain_responses(), vec![b"\x1b[4;384;640t".to_vec()]);
+    }
+}
+
+#[cfg(all(test, feature = "pty"))]
+mod pty_size_tests {
+    use crate::Pty;
+
+    #[test]
+    fn test_resize_pixels_sets_every_field() {
+        let mut pty = Pty::spawn("/bin/sh", 80, 24, None, None).unwrap();
+        assert_eq!(pty.pixel_size(), (0, 0));
+
+        pty.resize_pixels(100, 30, 800, 480).unwrap();
+        assert_eq!(pty.size(), (100, 30));
+        assert_eq!(pty.pixel_size(), (800, 480));
+
+        // A plain resize keeps the pixel size
+        pty.resize(90, 30).unwrap();
+        assert_eq!(pty.pixel_size(), (800, 480));
+    }
+}
+
+#[cfg(test)]
+mod feed_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::{AnsiParser, Command};
+
+    const PATHOLOGICAL: &[&[u8]] = &[
+        b"\x1b[999999999;999999999H",
+        b"\x1b[99999999b",
+        b"\x1b[99999999@\x1b[99999999P\x1b[99999999X",
+        b"\x1b[99999999L\x1b[99999999M\x1b[99999999S\x1b[99999999T",
+        b"\x1b[99999999A\x1b[99999999B\x1b[99999999C\x1b[99999999D",
+        b"\x1b[999999999;1r\x1b[5;99999999r\x1b[?6h\x1b[99999999;1H\n\n",
+        b"\x1b[?99999999h\x1b[?99999999s\x1b[?99999999r",
+        b"\x1b[38;5;99999999m\x1b[48;2;999;999;999m\x1b[58:2::1:2m",
+        b"\x1b]4;99999;#zzzzzz\x07\x1b]8;;\x07\x1b]104;999\x07",
+        b"\x1bP+q\xff\xfe\x1b\\\x1bP$q\x1b\\",
+        b"\xc2\x9b9;9\xc2",
+        "\u{4e2d}\u{200d}\u{301}\x1b[1;99999999H\u{4e2d}\u{4e2d}".as_bytes(),
+    ];
+
+    #[test]
+    fn test_pathological_sequences_do_not_panic() {
+        for (width, height) in [(1, 1), (2, 3), (80, 24)] {
+            for data in PATHOLOGICAL {
+                let mut buffer = TerminalBuffer::new(width, height);
+                let mut parser = AnsiParser::new();
+                crate::feed(&mut buffer, &mut parser, data).unwrap();
+                crate::feed(&mut buffer, &mut parser, b"text\r\n\t\x08").unwrap();
+
+                let (x, y) = buffer.cursor_pos();
+                assert!(x < width && y < height, "{data:?} at {width}x{height}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_huge_counts_are_bounded_by_the_screen() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        let mut parser = AnsiParser::new();
+        crate::feed(&mut buffer, &mut parser, b"a\r\nb\r\nc\r\nd\x1b[2;1H").unwrap();
+
+        crate::feed(&mut buffer, &mut parser, b"\x1b[99999L").unwrap();
+        let column: String = (0..5)
+            .map(|y| buffer.grid().get(0, y).unwrap().character)
+            .collect();
+        assert_eq!(column, "a    ");
+        assert_eq!(buffer.scrollback_len(), 0);
+
+        crate::feed(
+            &mut buffer,
+            &mut parser,
+            b"\x1b[1;1Hx\r\ny\r\nz\x1b[2;1H\x1b[99999M",
+        )
+        .unwrap();
+        assert_eq!(buffer.grid().get(0, 0).unwrap().character, 'x');
+        assert_eq!(buffer.grid().get(0, 2).unwrap().character, ' ');
+        assert_eq!(buffer.scrollback_len(), 0);
+
+        // Counts beyond what the parser can produce are clamped too
+        for cmd in [
+            Command::InsertChars(usize::MAX),
+            Command::DeleteChars(usize::MAX),
+            Command::EraseChars(usize::MAX),
+            Command::InsertLine(usize::MAX),
+            Command::DeleteLine(usize::MAX),
+            Command::ScrollUp(usize::MAX),
+            Command::ScrollDown(usize::MAX),
+            Command::MoveCursorRelative(i32::MAX, i32::MIN),
+            Command::SetOriginMode(true),
+            Command::MoveCursor(usize::MAX, usize::MAX),
+        ] {
+            buffer.execute_command(cmd).unwrap();
+        }
+        assert_eq!(buffer.cursor_pos(), (9, 4));
+    }
+
+    #[test]
+    fn test_zero_sized_screens_are_one_cell() {
+        let mut buffer = TerminalBuffer::new(0, 0);
+        assert_eq!((buffer.grid().width(), buffer.grid().height()), (1, 1));
+        let mut parser = AnsiParser::new();
+        crate::feed(&mut buffer, &mut parser, b"\x1b[5Bab\r\n\x1b[2;2r").unwrap();
+
+        buffer.resize(0, 7).unwrap();
+        assert_eq!((buffer.grid().width(), buffer.grid().height()), (1, 7));
+        crate::feed(&mut buffer, &mut parser, b"\x1b[99;99H\x1b[?1049hx").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::AnsiParser;
+
+    fn screen(data: &[u8]) -> TerminalBuffer {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        crate::feed(&mut buffer, &mut AnsiParser::new(), data).unwrap();
+        buffer
+    }
+
+    fn assert_converges(mut from: TerminalBuffer, to: &TerminalBuffer) -> TerminalBuffer {
+        let commands = from.diff_commands(to);
+        from.execute_commands(commands).unwrap();
+        for y in 0..3 {
+            assert_eq!(from.grid().row(y), to.grid().row(y), "row {y}");
+        }
+        assert_eq!(from.cursor_pos(), to.cursor_pos());
+        assert_eq!(from.current_style(), to.current_style());
+        assert!(from.diff_commands(to).is_empty());
+        from
+    }
+
+    #[test]
+    fn test_diff_converges_styled_region() {
+        let from = screen(b"hello\r\nworld\r\nagain");
+        let to = screen(b"hello\r\nw\x1b[1;31mORL\x1b[0md\r\nagain\x1b[1;2H\x1b[4m");
+        let commands = from.diff_commands(&to);
+        // Only the three changed cells are printed
+        let printed: usize = commands
+            .iter()
+            .map(|c| match c {
+                crate::parser::Command::PrintStr(s) => s.chars().count(),
+                crate::parser::Command::Print(_) => 1,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(printed, 3);
+        assert_converges(from, &to);
+    }
+
+    #[test]
+    fn test_diff_handles_wide_chars_and_last_cell() {
+        let from = screen(b"\xe4\xb8\xad\xe6\x96\x87 abc\x1b[?6h");
+        let to = screen(b"a\xe4\xb8\xad\x1b[3;10Hz\x1b[3;9H");
+        assert_converges(from, &to);
+        assert!(screen(b"same").diff_commands(&screen(b"same")).is_empty());
+    }
+
+    #[test]
+    fn test_diff_overwrites_under_insert_mode() {
+        let from = screen(b"abcdef\x1b[4h");
+        let to = screen(b"aXcdef");
+        let from = assert_converges(from, &to);
+        assert!(from.is_insert_mode());
+    }
+
+    #[test]
+    fn test_diff_replaces_wide_chars_and_spacers() {
+        let wide = screen("中文中文中ab中".as_bytes());
+        let narrow = screen(b"abcdefghij\r\nklmnopqrst");
+        let targets: [&[u8]; 4] = [
+            // Narrow cells over both halves of wide ones
+            b"a\xe4\xb8\xad\x1b[1;4Hxy\x1b[2;2Hz",
+            // A wide char whose spacer was overwritten
+            "中文\x1b[1;2Hx".as_bytes(),
+            // A spacer whose wide char was overwritten
+            "a中\x1b[1;2Hb".as_bytes(),
+            // A spacer keeps the link and marks of the cell it replaced
+            "\x1b]8;;http://a\x07ab\u{301}\x1b]8;;\x07\x1b[1;1H中".as_bytes(),
+        ];
+        for target in targets {
+            let to = screen(target);
+            assert_converges(wide.clone(), &to);
+            assert_converges(narrow.clone(), &to);
+            assert_converges(to.clone(), &wide);
+        }
+    }
+
+    #[test]
+    fn test_diff_restores_protected_cells() {
+        let from = screen("中a\x1b[1\"qb".as_bytes());
+        // A protected wide char, then a protected spacer left behind
+        let to = screen("\x1b[1\"q中\x1b[0\"qa\x1b[1\"q中\x1b[0\"q\x1b[1;4Hz".as_bytes());
+        assert!(to.grid().get(1, 0).unwrap().protected);
+        assert!(to.grid().get(4, 0).unwrap().wide_spacer);
+        assert!(!to.grid().get(3, 0).unwrap().protected);
+        assert_converges(from, &to);
+    }
+
+    #[test]
+    fn test_diff_skips_hidden_half_of_double_width_row() {
+        let mut from = screen(b"\x1b#6");
+        let to = screen(b"abcdefghij\x1b[1;1H\x1b#6");
+        let commands = from.diff_commands(&to);
+        let printed: String = commands
+            .iter()
+            .filter_map(|c| match c {
+                crate::parser::Command::PrintStr(s) => Some(s.to_string()),
+                crate::parser::Command::Print(c) => Some(c.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(printed, "abcde");
+        from.execute_commands(commands).unwrap();
+        // Nothing spills into the next row
+        assert_eq!(from.grid().row(1), to.grid().row(1));
+    }
+}
+
+#[cfg(test)]
+mod line_attr_tests {
+    use crate::core::{LineAttr, TerminalBuffer};
+    use crate::parser::{AnsiParser, Command};
+
+    #[test]
+    fn test_esc_hash_sets_line_attr() {
+        let mut parser = AnsiParser::new();
+        assert_eq!(
+            parser.parse(b"\x1b#3\x1b#4\x1b#5\x1b#6").unwrap(),
+            vec![
+                Command::SetLineAttr(LineAttr::DoubleHeightTop),
+                Command::SetLineAttr(LineAttr::DoubleHeightBottom),
+                Command::SetLineAttr(LineAttr::Single),
+                Command::SetLineAttr(LineAttr::DoubleWidth),
+            ]
+        );
+
+        let mut buffer = TerminalBuffer::new(10, 3);
+        crate::feed(&mut buffer, &mut parser, b"\x1b[2;8H\x1b#6").unwrap();
+        assert_eq!(buffer.grid().line_attr(1), LineAttr::DoubleWidth);
+        assert_eq!(buffer.grid().line_attr(0), LineAttr::Single);
+        // The cursor is pulled back into the usable half
+        assert_eq!(buffer.cursor_pos(), (4, 1));
+
+        crate::feed(&mut buffer, &mut parser, b"\x1b#5").unwrap();
+        assert_eq!(buffer.grid().line_attr(1), LineAttr::Single);
+    }
+
+    #[test]
+    fn test_cursor_motion_stays_in_usable_half() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        let mut parser = AnsiParser::new();
+        crate::feed(&mut buffer, &mut parser, b"\x1b[2;1H\x1b#6\x1b[2;9H").unwrap();
+        assert_eq!(buffer.cursor_pos(), (4, 1));
+        crate::feed(&mut buffer, &mut parser, b"\x1b[2;1H\x1b[20C").unwrap();
+        assert_eq!(buffer.cursor_pos(), (4, 1));
+        // Moving onto the row from a single-width one clamps too
+        crate::feed(&mut buffer, &mut parser, b"\x1b[1;9H\x1b[1B").unwrap();
+        assert_eq!(buffer.cursor_pos(), (4, 1));
+    }
+
+    #[test]
+    fn test_double_width_rows_wrap_at_half_width() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        let mut parser = AnsiParser::new();
+        crate::feed(&mut buffer, &mut parser, b"\x1b#6abcdefg").unwrap();
+        let row: String = buffer.grid().row(0).unwrap()[..5]
+            .iter()
+            .map(|c| c.character)
+            .collect();
+        assert_eq!(row, "abcde");
+        assert_eq!(buffer.grid().get(0, 1).unwrap().character, 'f');
+        assert!(buffer.grid().is_row_wrapped(0));
+
+        // The attribute scrolls with its row and is cleared by ED 2
+        crate::feed(&mut buffer, &mut parser, b"\r\n\r\n").unwrap();
+        assert_eq!(buffer.grid().line_attr(0), LineAttr::Single);
+        crate::feed(&mut buffer, &mut parser, b"\x1b[1;1H\x1b#3").unwrap();
+        crate::feed(&mut buffer, &mut parser, b"\x1b[1S").unwrap();
+        assert_eq!(buffer.grid().line_attr(0), LineAttr::Single);
+        crate::feed(&mut buffer, &mut parser, b"\x1b[1;1H\x1b#3\x1b[1T").unwrap();
+        assert_eq!(buffer.grid().line_attr(1), LineAttr::DoubleHeightTop);
+        crate::feed(&mut buffer, &mut parser, b"\x1b[2J").unwrap();
+        assert_eq!(buffer.grid().line_attr(1), LineAttr::Single);
+    }
+
+    #[test]
+    fn test_erase_line_keeps_line_attr() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        let mut parser = AnsiParser::new();
+        crate::feed(&mut buffer, &mut parser, b"\x1b[2;1H\x1b#6abc").unwrap();
+        for el in [&b"\x1b[K"[..], b"\x1b[1K", b"\x1b[2K"] {
+            crate::feed(&mut buffer, &mut parser, el).unwrap();
+            assert_eq!(buffer.grid().line_attr(1), LineAttr::DoubleWidth, "{el:?}");
+        }
+    }
+
+    #[test]
+    fn test_erase_display_resets_only_whole_rows() {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        let mut parser = AnsiParser::new();
+        let setup = b"\x1b[1;1H\x1b#6\x1b[2;1H\x1b#6\x1b[3;1H\x1b#6\x1b[2;1H";
+        crate::feed(&mut buffer, &mut parser, setup).unwrap();
+        crate::feed(&mut buffer, &mut parser, b"\x1b[J").unwrap();
+        assert_eq!(buffer.grid().line_attr(1), LineAttr::DoubleWidth);
+        assert_eq!(buffer.grid().line_attr(2), LineAttr::Single);
+        assert_eq!(buffer.grid().line_attr(0), LineAttr::DoubleWidth);
+        crate::feed(&mut buffer, &mut parser, b"\x1b[1J").unwrap();
+        assert_eq!(buffer.grid().line_attr(1), LineAttr::DoubleWidth);
+        assert_eq!(buffer.grid().line_attr(0), LineAttr::Single);
+    }
+}
+
+#[cfg(all(test, feature = "pty"))]
+mod pty_coalescer_tests {
+    use crate::PtyCoalescer;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn test_burst_arrives_as_one_chunk() {
+        let (sender, receiver) = channel();
+        let output = PtyCoalescer::new(receiver, Duration::from_millis(500), 1 << 16);
+        assert_eq!(output.try_recv(), None);
+
+        let writer = std::thread::spawn(move || {
+            for chunk in ["ls", " -l", "\r\n", "total 0", "\r\n"] {
+                sender.send(chunk.as_bytes().to_vec()).unwrap();
+                std::thread::sleep(Duration::from_millis(2));
+            }
+        });
+        assert_eq!(output.recv().unwrap(), b"ls -l\r\ntotal 0\r\n");
+        writer.join().unwrap();
+        assert_eq!(output.recv(), None);
+    }
+
+    #[test]
+    fn test_chunks_stop_growing_at_max_bytes() {
+        let (sender, receiver) = channel();
+        for chunk in ["ab", "cd", "ef"] {
+            sender.send(chunk.as_bytes().to_vec()).unwrap();
+        }
+        drop(sender);
+        let output = PtyCoalescer::new(receiver, Duration::from_secs(60), 4);
+        assert_eq!(output.try_recv().unwrap(), b"abcd");
+        assert_eq!(output.try_recv().unwrap(), b"ef");
+        assert_eq!(output.try_recv(), None);
+    }
+
+    #[test]
+    fn test_spawn_reads_until_eof() {
+        let reader = Box::new(std::io::Cursor::new(b"hello".to_vec()));
+        let output = PtyCoalescer::spawn(reader, Duration::from_millis(50), 1 << 16);
+        assert_eq!(output.recv().unwrap(), b"hello");
+        assert_eq!(output.recv(), None);
+    }
+}
+
+#[cfg(test)]
+mod content_hash_tests {
+    use crate::core::TerminalBuffer;
+    use crate::parser::AnsiParser;
+
+    fn screen(data: &[u8]) -> TerminalBuffer {
+        let mut buffer = TerminalBuffer::new(10, 3);
+        crate::feed(&mut buffer, &mut AnsiParser::new(), data).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_equal_screens_hash_equal() {
+        let a = screen(b"hello\r\n\x1b[31mworld");
+        assert_eq!(
+            a.content_hash(),
+            screen(b"hello\r\n\x1b[31mworld").content_hash()
+        );
+        // The cursor and pen don't count
+        assert_eq!(
+            a.content_hash(),
+            screen(b"hello\r\n\x1b[31mworld\x1b[1;1H\x1b[0m").content_hash()
+        );
+        assert_ne!(a.content_hash(), TerminalBuffer::new(10, 3).content_hash());
+    }
+
+    #[test]
+    fn test_one_cell_change_hashes_differently() {
+        let base = screen(b"hello\r\nworld").content_hash();
+        assert_ne!(base, screen(b"hello\r\nworle").content_hash());
+        assert_ne!(base, screen(b"hello\r\nworl\x1b[4md").content_hash());
+        assert_ne!(base, screen(b"hello\r\nworld\xcc\x81").content_hash());
+    }
+
+    #[test]
+    fn test_line_attrs_links_and_protection_count() {
+        let base = screen(b"hello").content_hash();
+        assert_ne!(base, screen(b"\x1b#6hello").content_hash());
+        assert_ne!(
+            screen(b"\x1b#3hello").content_hash(),
+            screen(b"\x1b#4hello").content_hash()
+        );
+        assert_ne!(base, screen(b"\x1b[1\"qhello").content_hash());
+
+        let link = screen(b"\x1b]8;;https://a.example\x07hello").content_hash();
+        assert_ne!(base, link);
+        assert_ne!(
+            link,
+            screen(b"\x1b]8;;https://b.example\x07hello").content_hash()
+        );
+        // Unnamed links get a fresh id each time, which doesn't count
+        assert_eq!(
+            link,
+            screen(b"\x1b]8;;https://a.example\x07hello").content_hash()
+        );
+    }
+
+    #[test]
+    fn test_hash_is_pinned() {
+        // Fixed input bytes, so this holds on any platform or toolchain
+        assert_eq!(screen(b"\x1b[1;31m#").content_hash(), 4029354552843423107);
+    }
+
+    #[test]
+    fn test_hash_follows_the_viewport() {
+        let mut buffer = screen(b"1\r\n2\r\n3\r\n4");
+        let live = buffer.content_hash();
+        buffer.scroll_by(1);
+        assert_ne!(buffer.content_hash(), live);
+        buffer.scroll_to_bottom();
+        assert_eq!(buffer.content_hash(), live);
+    }
+}
+
+#[cfg(all(test, feature = "crossterm"))]
+mod crossterm_key_tests {
+    use crate::{Key, KeyEvent, Modifiers};
+    use crossterm::event::{self as ct, KeyCode, KeyModifiers};
+
+    fn convert(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::try_from(ct::KeyEvent::new(code, modifiers)).unwrap()
+    }
+
+    #[test]
+    fn test_plain_and_modified_keys() {
+        assert_eq!(
+            convert(KeyCode::Char('a'), KeyModifiers::NONE),
+            KeyEvent::new(Key::Char('a'))
+        );
+        let ctrl_c = convert(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert!(ctrl_c.modifiers.ctrl);
+        assert_eq!(ctrl_c.to_ansi(), vec![0x03]);
+
+        let alt_shift_up = convert(KeyCode::Up, KeyModifiers::ALT | KeyModifiers::SHIFT);
+        assert_eq!(
+            alt_shift_up,
+            KeyEvent::with_modifiers(
+                Key::Up,
+                Modifiers {
+                    alt: true,
+                    shift: true,
+                    ..Modifiers::default()
+                }
+            )
+        );
+        assert_eq!(convert(KeyCode::F(5), KeyModifiers::NONE).key, Key::F(5));
+    }
+
+    #[test]
+    fn test_back_tab_is_shift_tab() {
+        let key = convert(KeyCode::BackTab, KeyModifiers::NONE);
+        assert_eq!(key.key, Key::Tab);
+        assert_eq!(key.to_ansi(), b"\x1b[Z");
+    }
+
+    #[test]
+    fn test_unmapped_key_is_rejected() {
+        let event = ct::KeyEvent::new(KeyCode::CapsLock, KeyModifiers::NONE);
+        assert_eq!(KeyEvent::try_from(event), Err(KeyCode::CapsLock));
+    }
+}
+
+#[cfg(all(test, feature = "android"))]
+mod session_env_tests {
+    use crate::android::session_options;
+
+    #[test]
+    fn test_session_options_from_env_map() {
+        let options = session_options(
+            "/system/bin/mksh",
+            80,
+            24,
+            "/data/home",
+            "rin",
+            [
+                ("EDITOR".to_string(), "vi".to_string()),
+                ("PATH".to_string(), "/custom/bin".to_string()),
+            ],
+        );
+
+        assert_eq!(options.shell, "/system/bin/mksh");
+        assert_eq!((options.cols, options.rows), (80, 24));
+        assert_eq!(options.cwd.as_deref(), Some("/data/home"));
+        let var = |key: &str| options.env.get(key).map(String::as_str);
+        assert_eq!(var("TERM"), Some("xterm-256color"));
+        assert_eq!(var("HOME"), Some("/data/home"));
+        assert_eq!(var("ENV"), Some("/data/home/.mkshrc"));
+        assert_eq!(var("USER"), Some("rin"));
+        assert_eq!(var("PREFIX"), Some("/data/data/com.rin/files/usr"));
+        assert_eq!(
+            var("LD_LIBRARY_PATH"),
+            Some("/data/data/com.rin/files/usr/lib")
+        );
+        assert_eq!(var("EDITOR"), Some("vi"));
+        // Provided values win over the defaults
+        assert_eq!(var("PATH"), Some("/custom/bin"));
+    }
+
+    #[test]
+    fn test_env_pairs_keep_positions() {
+        use crate::android::env_pairs;
+        let some = |s: &str| Some(s.to_string());
+
+        assert_eq!(
+            env_pairs(vec![some("A"), some("B")], vec![some("1"), some("2")]),
+            Some(vec![
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "2".to_string())
+            ])
+        );
+        // A null in the middle would otherwise shift "2" onto "A"
+        assert_eq!(
+            env_pairs(vec![some("A"), some("B")], vec![None, some("2")]),
+            None
+        );
+        assert_eq!(env_pairs(vec![some("A")], vec![]), None);
+        assert_eq!(env_pairs(vec![], vec![]), Some(vec![]));
+    }
+}
+
+#[cfg(all(test, feature = "android"))]
+mod session_banner_tests {
+    use crate::android::{DEFAULT_BANNER, new_engine};
+
+    fn is_blank(engine: &crate::TerminalEngine) -> bool {
+        let grid = engine.buffer().grid();
+        (0..grid.height()).all(|y| grid.row_extent(y).is_none())
+    }
+
+    #[test]
+    fn test_engine_without_banner_is_empty() {
+        let engine = new_engine(40, 10, 12.0, None);
+        assert!(is_blank(&engine));
+        assert_eq!(engine.buffer().cursor_pos(), (0, 0));
+    }
+
+    #[test]
+    fn test_default_and_custom_banner() {
+        let engine = new_engine(40, 10, 12.0, Some(DEFAULT_BANNER));
+        assert!(!is_blank(&engine));
+
+        let engine = new_engine(40, 10, 12.0, Some("welcome\r\n"));
+        assert_eq!(engine.buffer().grid().row_extent(0), Some((0, 6)));
+        assert_eq!(engine.buffer().cursor_pos(), (0, 1));
+    }
+}
+
+#[cfg(all(test, feature = "android"))]
+mod jni_status_tests {
+    use crate::Error;
+    use crate::android::{JniStatus, error_message};
+
+    #[test]
+    fn test_status_codes_are_stable() {
+        assert_eq!(JniStatus::Ok.code(), 0);
+        assert_eq!(JniStatus::Failed.code(), -1);
+        assert_eq!(JniStatus::UnknownHandle.code(), -2);
+        assert_eq!(JniStatus::InvalidArgument.code(), -3);
+        assert_eq!(JniStatus::PtyFailed.code(), -4);
+    }
+
+    #[test]
+    fn test_errors_map_to_status() {
+        let pty = Error::Pty {
+            context: "PTY write failed",
+            source: "broken pipe".into(),
+        };
+        assert_eq!(JniStatus::from(&pty), JniStatus::PtyFailed);
+        assert_eq!(error_message(&pty), "PTY write failed: broken pipe");
+
+        let bounds = Error::OutOfBounds { x: 3, y: 9 };
+        assert_eq!(JniStatus::from(&bounds), JniStatus::Failed);
+
+        assert_eq!(JniStatus::of(&Ok::<(), Error>(())), JniStatus::Ok);
+        assert_eq!(
+            JniStatus::of(&Err::<(), _>(Error::from(std::io::Error::other("disk")))),
+            JniStatus::Failed
+        );
+    }
+}
+
+#[cfg(all(test, feature = "android"))]
+mod jni_poison_tests {
+    use crate::android::{lock, with_session};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn test_lock_recovers_from_poisoned_mutex() {
+        let mutex = Arc::new(Mutex::new(41));
+        let poisoner = mutex.clone();
+        let _ = thread::spawn(move || {
+            let mut guard = poisoner.lock().unwrap();
+            *guard += 1;
+            panic!("reader thread died");
+        })
+        .join();
+        assert!(mutex.is_poisoned());
+
+        assert_eq!(*lock(&mutex), 42);
+        *lock(&mutex) += 1;
+        assert_eq!(*lock(&mutex), 43);
+    }
+
+    #[test]
+    fn test_missing_handle_is_none() {
+        assert_eq!(with_session(-77, |_| ()), None);
+    }
+}
+
+#[cfg(all(test, feature = "android"))]
+mod jni_response_tests {
+    use crate::android::{feed_engine, new_engine};
+    use crate::{Error, Result};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_cursor_report_goes_straight_to_pty() {
+        let engine = Mutex::new(new_engine(20, 5, 12.0, None));
+        let mut pty = Vec::new();
+        let mut stub = |bytes: &[u8]| -> Result<()> {
+            pty.extend_from_slice(bytes);
+            Ok(())
+        };
+
+        feed_engine(&engine, b"ab\x1b[6n", &mut stub).unwrap();
+        feed_engine(&engine, b"plain output", &mut stub).unwrap();
+        assert_eq!(pty, b"\x1b[1;3R");
+        assert!(
+            engine
+                .lock()
+                .unwrap()
+                .buffer_mut()
+                .drain_responses()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_pty_write_error_is_returned() {
+        let engine = Mutex::new(new_engine(20, 5, 12.0, None));
+        let result = feed_engine(&engine, b"\x1b[6n", |_| {
+            Err(Error::Io(std::io::Error::other("closed")))
+        });
+        assert!(result.is_err());
+    }
 }